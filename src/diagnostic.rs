@@ -0,0 +1,74 @@
+
+// Parse and runtime errors only carry byte indices into the source, this turns
+// them into something a person can find
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Location {
+    pub line : usize,
+    pub column : usize,
+}
+
+// Lines and columns count from 1, columns are in chars
+pub fn locate(source : &str, index : usize) -> Location {
+    let index = index.min(source.len());
+    let before = &source[..index];
+
+    let line = before.matches('\n').count() + 1;
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    let column = before[line_start..].chars().count() + 1;
+
+    Location { line, column }
+}
+
+pub fn render(name : &str, source : &str, index : usize, message : &str) -> String {
+    let location = locate(source, index);
+    let text = source.lines().nth(location.line - 1).unwrap_or("");
+    let gutter = location.line.to_string().len();
+
+    format!( "{}:{}:{}: {}\n{} |\n{} | {}\n{} | {}^\n"
+           , name, location.line, location.column, message
+           , " ".repeat(gutter)
+           , location.line, text
+           , " ".repeat(gutter), " ".repeat(location.column - 1)
+           )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn locate_should_find_first_line() {
+        let l = locate("abc", 1);
+
+        assert_eq!( l, Location { line: 1, column: 2 } );
+    }
+
+    #[test]
+    fn locate_should_find_later_line() {
+        let l = locate("ab\ncd\nef", 7);
+
+        assert_eq!( l, Location { line: 3, column: 2 } );
+    }
+
+    #[test]
+    fn locate_should_count_columns_in_chars() {
+        let l = locate("é=1", "é=".len());
+
+        assert_eq!( l, Location { line: 1, column: 3 } );
+    }
+
+    #[test]
+    fn locate_should_handle_end_of_input() {
+        let l = locate("ab\n", 3);
+
+        assert_eq!( l, Location { line: 2, column: 1 } );
+    }
+
+    #[test]
+    fn render_should_point_at_column() {
+        let output = render("x.ash", "let x = 1;\nlet = 2;", 15, "parse error");
+
+        assert_eq!( output, "x.ash:2:5: parse error\n  |\n2 | let = 2;\n  |     ^\n" );
+    }
+}
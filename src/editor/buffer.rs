@@ -0,0 +1,201 @@
+
+// The line being edited. The cursor is an index into chars, so it sits
+// between characters and can be at the very end
+#[derive(Debug, Default)]
+pub struct LineBuffer {
+    chars : Vec<char>,
+    cursor : usize,
+}
+
+fn is_word_char(c : char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+impl LineBuffer {
+    pub fn new() -> LineBuffer {
+        LineBuffer { chars: vec![], cursor: 0 }
+    }
+
+    pub fn text(&self) -> String {
+        self.chars.iter().collect()
+    }
+
+    pub fn chars(&self) -> &[char] {
+        &self.chars
+    }
+
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chars.is_empty()
+    }
+
+    pub fn set(&mut self, text : &str) {
+        self.chars = text.chars().collect();
+        self.cursor = self.chars.len();
+    }
+
+    pub fn insert(&mut self, s : &str) {
+        for c in s.chars() {
+            self.chars.insert(self.cursor, c);
+            self.cursor += 1;
+        }
+    }
+
+    pub fn backspace(&mut self) {
+        if self.cursor > 0 {
+            self.cursor -= 1;
+            self.chars.remove(self.cursor);
+        }
+    }
+
+    pub fn delete(&mut self) {
+        if self.cursor < self.chars.len() {
+            self.chars.remove(self.cursor);
+        }
+    }
+
+    pub fn left(&mut self) {
+        self.cursor = self.cursor.saturating_sub(1);
+    }
+
+    pub fn right(&mut self) {
+        self.cursor = (self.cursor + 1).min(self.chars.len());
+    }
+
+    pub fn home(&mut self) {
+        self.cursor = 0;
+    }
+
+    pub fn end(&mut self) {
+        self.cursor = self.chars.len();
+    }
+
+    fn word_start(&self) -> usize {
+        let mut i = self.cursor;
+        while i > 0 && !is_word_char(self.chars[i - 1]) {
+            i -= 1;
+        }
+        while i > 0 && is_word_char(self.chars[i - 1]) {
+            i -= 1;
+        }
+        i
+    }
+
+    fn word_end(&self) -> usize {
+        let mut i = self.cursor;
+        while i < self.chars.len() && !is_word_char(self.chars[i]) {
+            i += 1;
+        }
+        while i < self.chars.len() && is_word_char(self.chars[i]) {
+            i += 1;
+        }
+        i
+    }
+
+    pub fn word_left(&mut self) {
+        self.cursor = self.word_start();
+    }
+
+    pub fn word_right(&mut self) {
+        self.cursor = self.word_end();
+    }
+
+    // The kill functions return what they removed so that it can be yanked back
+
+    pub fn kill_to_end(&mut self) -> String {
+        self.chars.drain(self.cursor..).collect()
+    }
+
+    pub fn kill_to_start(&mut self) -> String {
+        let killed = self.chars.drain(..self.cursor).collect();
+        self.cursor = 0;
+        killed
+    }
+
+    pub fn kill_word_left(&mut self) -> String {
+        let start = self.word_start();
+        let killed = self.chars.drain(start..self.cursor).collect();
+        self.cursor = start;
+        killed
+    }
+
+    pub fn kill_word_right(&mut self) -> String {
+        let end = self.word_end();
+        self.chars.drain(self.cursor..end).collect()
+    }
+
+    // Swaps the characters either side of the cursor, or the last two at the end of the line
+    pub fn transpose(&mut self) {
+        if self.chars.len() < 2 || self.cursor == 0 {
+            return;
+        }
+        if self.cursor == self.chars.len() {
+            self.cursor -= 1;
+        }
+        self.chars.swap(self.cursor - 1, self.cursor);
+        self.cursor += 1;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn buffer(text : &str, cursor : usize) -> LineBuffer {
+        LineBuffer { chars: text.chars().collect(), cursor }
+    }
+
+    #[test]
+    fn insert_should_insert_at_cursor() {
+        let mut b = buffer("ac", 1);
+
+        b.insert("b");
+
+        assert_eq!( b.text(), "abc" );
+        assert_eq!( b.cursor(), 2 );
+    }
+
+    #[test]
+    fn word_movement_should_skip_punctuation() {
+        let mut b = buffer("add(one, two)", 13);
+
+        b.word_left();
+        assert_eq!( b.cursor(), 9 );
+        b.word_left();
+        assert_eq!( b.cursor(), 4 );
+        b.word_right();
+        assert_eq!( b.cursor(), 7 );
+    }
+
+    #[test]
+    fn kill_word_left_should_return_removed_text() {
+        let mut b = buffer("let blah = 5", 8);
+
+        let killed = b.kill_word_left();
+
+        assert_eq!( killed, "blah" );
+        assert_eq!( b.text(), "let  = 5" );
+        assert_eq!( b.cursor(), 4 );
+    }
+
+    #[test]
+    fn kill_to_end_and_start_should_split_at_cursor() {
+        let mut b = buffer("abcdef", 3);
+
+        assert_eq!( b.kill_to_end(), "def" );
+        assert_eq!( b.kill_to_start(), "abc" );
+        assert!( b.is_empty() );
+    }
+
+    #[test]
+    fn transpose_should_swap_last_two_at_end() {
+        let mut b = buffer("ab", 2);
+
+        b.transpose();
+
+        assert_eq!( b.text(), "ba" );
+    }
+}
@@ -0,0 +1,62 @@
+
+fn is_word_char(c : char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+// Finds the word ending at the cursor, and every candidate that starts with it.
+// Returns where the word starts along with the sorted matches
+pub fn complete(line : &[char], cursor : usize, candidates : &[String]) -> (usize, Vec<String>) {
+    let mut start = cursor;
+    while start > 0 && is_word_char(line[start - 1]) {
+        start -= 1;
+    }
+
+    let word = line[start..cursor].iter().collect::<String>();
+
+    let mut matches = candidates.iter().filter(|c| c.starts_with(&word)).cloned().collect::<Vec<_>>();
+    matches.sort();
+    matches.dedup();
+
+    (start, matches)
+}
+
+pub fn common_prefix(words : &[String]) -> String {
+    let first = match words.first() {
+        Some(first) => first,
+        None => return String::new(),
+    };
+
+    let mut prefix = first.chars().collect::<Vec<_>>();
+    for word in &words[1..] {
+        let shared = prefix.iter().zip(word.chars()).take_while(|(a, b)| **a == *b).count();
+        prefix.truncate(shared);
+    }
+
+    prefix.into_iter().collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn names(ns : &[&str]) -> Vec<String> {
+        ns.iter().map(|n| n.to_string()).collect()
+    }
+
+    #[test]
+    fn complete_should_match_word_before_cursor() {
+        let line = "print(le".chars().collect::<Vec<_>>();
+
+        let (start, matches) = complete(&line, 8, &names(&["let", "len", "print", "len"]));
+
+        assert_eq!( start, 6 );
+        assert_eq!( matches, names(&["len", "let"]) );
+    }
+
+    #[test]
+    fn common_prefix_should_stop_at_first_difference() {
+        assert_eq!( common_prefix(&names(&["pushd", "push", "pwd"])), "p" );
+        assert_eq!( common_prefix(&names(&["pushd", "push"])), "push" );
+        assert_eq!( common_prefix(&[]), "" );
+    }
+}
@@ -0,0 +1,142 @@
+
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+const MAX_ENTRIES : usize = 1000;
+
+// Entries are kept one per line in the history file, oldest first. New
+// entries are appended as they're added so that nothing is lost if the
+// shell is killed
+pub struct History {
+    entries : Vec<String>,
+    path : Option<PathBuf>,
+}
+
+// $ASH_HISTORY, otherwise ~/.ash_history
+pub fn default_path() -> Option<PathBuf> {
+    match std::env::var_os("ASH_HISTORY") {
+        Some(path) => Some(PathBuf::from(path)),
+        None => std::env::var_os("HOME").map(|home| Path::new(&home).join(".ash_history")),
+    }
+}
+
+impl History {
+    pub fn new() -> History {
+        History { entries: vec![], path: None }
+    }
+
+    pub fn load(path : PathBuf) -> History {
+        let contents = fs::read_to_string(&path).unwrap_or_default();
+        let mut entries = contents.lines().filter(|line| !line.is_empty()).map(|line| line.to_string()).collect::<Vec<_>>();
+
+        if entries.len() > MAX_ENTRIES {
+            entries.drain(..entries.len() - MAX_ENTRIES);
+            // history is a convenience, so failing to trim it isn't worth reporting
+            let _ = fs::write(&path, entries.iter().map(|e| format!("{}\n", e)).collect::<String>());
+        }
+
+        History { entries, path: Some(path) }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn get(&self, index : usize) -> Option<&str> {
+        self.entries.get(index).map(|e| e.as_str())
+    }
+
+    // Blank lines and repeats of the previous entry are skipped
+    pub fn add(&mut self, line : &str) {
+        if line.trim().is_empty() || line.contains('\n') || self.entries.last().is_some_and(|last| last == line) {
+            return;
+        }
+
+        self.entries.push(line.to_string());
+
+        if let Some(path) = &self.path {
+            if let Ok(mut file) = OpenOptions::new().create(true).append(true).open(path) {
+                let _ = writeln!(file, "{}", line);
+            }
+        }
+    }
+
+    // The index of the newest entry before `before` that contains the query
+    pub fn search(&self, query : &str, before : usize) -> Option<usize> {
+        self.entries[..before.min(self.entries.len())].iter().rposition(|e| e.contains(query))
+    }
+}
+
+impl Default for History {
+    fn default() -> History {
+        History::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn temp_path(name : &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("ash_history_test_{}_{}", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn add_should_skip_blank_and_repeated_lines() {
+        let mut h = History::new();
+
+        h.add("a");
+        h.add("  ");
+        h.add("a");
+        h.add("b");
+
+        assert_eq!( h.len(), 2 );
+        assert_eq!( h.get(1), Some("b") );
+    }
+
+    #[test]
+    fn search_should_find_newest_match_before_index() {
+        let mut h = History::new();
+        h.add("let x = 1");
+        h.add("print(x)");
+        h.add("let y = 2");
+
+        assert_eq!( h.search("let", 3), Some(2) );
+        assert_eq!( h.search("let", 2), Some(0) );
+        assert_eq!( h.search("let", 0), None );
+        assert_eq!( h.search("blah", 3), None );
+    }
+
+    #[test]
+    fn load_should_read_entries_added_earlier() {
+        let path = temp_path("load");
+
+        let mut h = History::load(path.clone());
+        h.add("first");
+        h.add("second");
+
+        let h = History::load(path.clone());
+
+        assert_eq!( h.len(), 2 );
+        assert_eq!( h.get(0), Some("first") );
+
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn load_should_keep_only_newest_entries() {
+        let path = temp_path("trim");
+        fs::write(&path, (0..MAX_ENTRIES + 5).map(|i| format!("{}\n", i)).collect::<String>()).unwrap();
+
+        let h = History::load(path.clone());
+
+        assert_eq!( h.len(), MAX_ENTRIES );
+        assert_eq!( h.get(0), Some("5") );
+        assert_eq!( fs::read_to_string(&path).unwrap().lines().count(), MAX_ENTRIES );
+
+        let _ = fs::remove_file(&path);
+    }
+}
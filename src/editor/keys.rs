@@ -0,0 +1,145 @@
+
+use std::io::{self, Read};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Key {
+    Char(char),
+    Ctrl(char),
+    Alt(char),
+    Enter,
+    Tab,
+    Backspace,
+    Delete,
+    Left,
+    Right,
+    Up,
+    Down,
+    Home,
+    End,
+    Unknown,
+}
+
+// Returns None at the end of the input
+pub fn read_key<R : Read>(input : &mut R) -> io::Result<Option<Key>> {
+    let b = match read_byte(input)? {
+        Some(b) => b,
+        None => return Ok(None),
+    };
+
+    let key = match b {
+        b'\r' | b'\n' => Key::Enter,
+        b'\t' => Key::Tab,
+        8 | 127 => Key::Backspace,
+        27 => escape(input)?,
+        1..=26 => Key::Ctrl((b - 1 + b'a') as char),
+        0..=31 => Key::Unknown,
+        _ => match utf8(input, b)? {
+            Some(c) => Key::Char(c),
+            None => Key::Unknown,
+        },
+    };
+
+    Ok(Some(key))
+}
+
+fn read_byte<R : Read>(input : &mut R) -> io::Result<Option<u8>> {
+    let mut buffer = [0; 1];
+    loop {
+        match input.read(&mut buffer) {
+            Ok(0) => return Ok(None),
+            Ok(_) => return Ok(Some(buffer[0])),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => { },
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+// A lone escape is treated as a meta prefix for the next key, the same as emacs
+fn escape<R : Read>(input : &mut R) -> io::Result<Key> {
+    let key = match read_byte(input)? {
+        Some(b'[') => match read_byte(input)? {
+            Some(b'A') => Key::Up,
+            Some(b'B') => Key::Down,
+            Some(b'C') => Key::Right,
+            Some(b'D') => Key::Left,
+            Some(b'H') => Key::Home,
+            Some(b'F') => Key::End,
+            Some(d) if d.is_ascii_digit() => {
+                let mut code = vec![d];
+                loop {
+                    match read_byte(input)? {
+                        Some(b'~') => break,
+                        Some(b) if b.is_ascii_digit() || b == b';' => code.push(b),
+                        _ => return Ok(Key::Unknown),
+                    }
+                }
+                match &code[..] {
+                    b"1" | b"7" => Key::Home,
+                    b"4" | b"8" => Key::End,
+                    b"3" => Key::Delete,
+                    _ => Key::Unknown,
+                }
+            },
+            _ => Key::Unknown,
+        },
+        Some(b'O') => match read_byte(input)? {
+            Some(b'H') => Key::Home,
+            Some(b'F') => Key::End,
+            _ => Key::Unknown,
+        },
+        Some(8) | Some(127) => Key::Alt('\x7f'),
+        Some(b) if b.is_ascii_graphic() => Key::Alt(b as char),
+        _ => Key::Unknown,
+    };
+
+    Ok(key)
+}
+
+fn utf8<R : Read>(input : &mut R, first : u8) -> io::Result<Option<char>> {
+    let length = match first {
+        0x00..=0x7f => 1,
+        0xc0..=0xdf => 2,
+        0xe0..=0xef => 3,
+        0xf0..=0xf7 => 4,
+        _ => return Ok(None),
+    };
+
+    let mut bytes = vec![first];
+    for _ in 1..length {
+        match read_byte(input)? {
+            Some(b) => bytes.push(b),
+            None => return Ok(None),
+        }
+    }
+
+    Ok(std::str::from_utf8(&bytes).ok().and_then(|s| s.chars().next()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn keys(bytes : &[u8]) -> Vec<Key> {
+        let mut input = bytes;
+        let mut keys = vec![];
+        while let Some(key) = read_key(&mut input).unwrap() {
+            keys.push(key);
+        }
+        keys
+    }
+
+    #[test]
+    fn should_read_control_keys() {
+        assert_eq!( keys(b"\x01\x12\r\t\x7f"), vec![Key::Ctrl('a'), Key::Ctrl('r'), Key::Enter, Key::Tab, Key::Backspace] );
+    }
+
+    #[test]
+    fn should_read_escape_sequences() {
+        assert_eq!( keys(b"\x1b[A\x1b[D\x1b[3~\x1b[1~\x1bOF\x1bb"), vec![Key::Up, Key::Left, Key::Delete, Key::Home, Key::End, Key::Alt('b')] );
+    }
+
+    #[test]
+    fn should_read_multi_byte_chars() {
+        assert_eq!( keys("aé😀".as_bytes()), vec![Key::Char('a'), Key::Char('é'), Key::Char('😀')] );
+    }
+}
@@ -0,0 +1,434 @@
+
+mod buffer;
+mod complete;
+mod history;
+mod keys;
+mod terminal;
+
+use std::io::{self, Write};
+
+use buffer::LineBuffer;
+use keys::{read_key, Key};
+use terminal::RawMode;
+
+pub use history::{default_path, History};
+
+#[derive(Debug, PartialEq)]
+pub enum ReadLine {
+    Line(String),
+    // ctrl-c
+    Interrupted,
+    // ctrl-d on an empty line
+    Eof,
+}
+
+enum Step {
+    Continue,
+    Done(ReadLine),
+    List(Vec<String>),
+    ClearScreen,
+}
+
+struct Search {
+    query : String,
+    found : Option<usize>,
+    // what was on the line before the search started, for when it's cancelled
+    original : String,
+}
+
+// Everything about the line currently being read
+struct State {
+    buffer : LineBuffer,
+    // an index into history, or history.len() for the line being written
+    history_index : usize,
+    // the line being written, saved while looking through history
+    draft : String,
+    search : Option<Search>,
+}
+
+// Emacs style line editing over a raw mode terminal
+pub struct Editor {
+    history : History,
+    kill_ring : String,
+}
+
+impl Editor {
+    pub fn new(history : History) -> Editor {
+        Editor { history, kill_ring: String::new() }
+    }
+
+    // Completion candidates are passed in on each call because the bindings
+    // in scope change as lines are evaluated
+    pub fn read_line(&mut self, prompt : &str, candidates : &[String]) -> io::Result<ReadLine> {
+        let _raw = RawMode::enable()?;
+        let stdin = io::stdin();
+        let mut input = stdin.lock();
+        let stdout = io::stdout();
+        let mut output = stdout.lock();
+
+        // only a prompt's last line is redrawn as the line is edited
+        let (above, prompt) = match prompt.rfind('\n') {
+            Some(i) => prompt.split_at(i + 1),
+            None => ("", prompt),
+        };
+        write!(output, "{}", above.replace('\n', "\r\n"))?;
+
+        let mut state = self.start();
+        render(&mut output, prompt, &state)?;
+
+        loop {
+            let key = match read_key(&mut input)? {
+                Some(key) => key,
+                None => {
+                    write!(output, "\r\n")?;
+                    return Ok(ReadLine::Eof);
+                },
+            };
+
+            match self.handle(&mut state, key, candidates) {
+                Step::Continue => { },
+                Step::Done(line) => {
+                    state.search = None;
+                    render(&mut output, prompt, &state)?;
+                    write!(output, "\r\n")?;
+                    output.flush()?;
+                    if let ReadLine::Line(line) = &line {
+                        self.history.add(line);
+                    }
+                    return Ok(line);
+                },
+                Step::List(words) => write!(output, "\r\n{}\r\n", words.join("  "))?,
+                Step::ClearScreen => write!(output, "\x1b[H\x1b[2J")?,
+            }
+
+            render(&mut output, prompt, &state)?;
+        }
+    }
+
+    fn start(&self) -> State {
+        State { buffer: LineBuffer::new(), history_index: self.history.len(), draft: String::new(), search: None }
+    }
+
+    fn handle(&mut self, state : &mut State, key : Key, candidates : &[String]) -> Step {
+        if state.search.is_some() {
+            if let Some(step) = self.handle_search(state, key) {
+                return step;
+            }
+        }
+
+        let buffer = &mut state.buffer;
+
+        match key {
+            Key::Enter => return Step::Done(ReadLine::Line(buffer.text())),
+            Key::Ctrl('c') => return Step::Done(ReadLine::Interrupted),
+            Key::Ctrl('d') if buffer.is_empty() => return Step::Done(ReadLine::Eof),
+            Key::Ctrl('d') | Key::Delete => buffer.delete(),
+            Key::Char(c) => buffer.insert(&c.to_string()),
+            Key::Backspace | Key::Ctrl('h') => buffer.backspace(),
+            Key::Left | Key::Ctrl('b') => buffer.left(),
+            Key::Right | Key::Ctrl('f') => buffer.right(),
+            Key::Home | Key::Ctrl('a') => buffer.home(),
+            Key::End | Key::Ctrl('e') => buffer.end(),
+            Key::Alt('b') => buffer.word_left(),
+            Key::Alt('f') => buffer.word_right(),
+            Key::Ctrl('k') => self.kill_ring = buffer.kill_to_end(),
+            Key::Ctrl('u') => self.kill_ring = buffer.kill_to_start(),
+            Key::Ctrl('w') | Key::Alt('\x7f') => self.kill_ring = buffer.kill_word_left(),
+            Key::Alt('d') => self.kill_ring = buffer.kill_word_right(),
+            Key::Ctrl('y') => buffer.insert(&self.kill_ring),
+            Key::Ctrl('t') => buffer.transpose(),
+            Key::Up | Key::Ctrl('p') => self.previous(state),
+            Key::Down | Key::Ctrl('n') => self.next(state),
+            Key::Ctrl('l') => return Step::ClearScreen,
+            Key::Ctrl('r') => state.search = Some(Search { query: String::new(), found: None, original: buffer.text() }),
+            Key::Tab => return complete(buffer, candidates),
+            _ => { },
+        }
+
+        Step::Continue
+    }
+
+    // Returns None when the key ends the search and should then be handled as normal
+    fn handle_search(&mut self, state : &mut State, key : Key) -> Option<Step> {
+        let search = state.search.as_mut().expect("Editor::handle_search called outside of search");
+
+        match key {
+            Key::Char(c) => {
+                search.query.push(c);
+                let from = search.found.map_or(self.history.len(), |found| found + 1);
+                search.found = self.history.search(&search.query, from);
+            },
+            Key::Backspace | Key::Ctrl('h') => {
+                search.query.pop();
+                search.found = self.history.search(&search.query, self.history.len());
+            },
+            Key::Ctrl('r') => {
+                let before = search.found.unwrap_or(self.history.len());
+                if let Some(older) = self.history.search(&search.query, before) {
+                    search.found = Some(older);
+                }
+            },
+            Key::Ctrl('g') | Key::Ctrl('c') => {
+                state.buffer.set(&search.original);
+                state.search = None;
+                return Some(Step::Continue);
+            },
+            _ => {
+                if let Some(line) = search.found.and_then(|found| self.history.get(found)) {
+                    state.buffer.set(line);
+                }
+                state.search = None;
+                return None;
+            },
+        }
+
+        if let Some(line) = search.found.and_then(|found| self.history.get(found)) {
+            state.buffer.set(line);
+        }
+
+        Some(Step::Continue)
+    }
+
+    fn previous(&self, state : &mut State) {
+        if state.history_index == 0 {
+            return;
+        }
+        if state.history_index == self.history.len() {
+            state.draft = state.buffer.text();
+        }
+        state.history_index -= 1;
+        state.buffer.set(self.history.get(state.history_index).unwrap_or(""));
+    }
+
+    fn next(&self, state : &mut State) {
+        if state.history_index >= self.history.len() {
+            return;
+        }
+        state.history_index += 1;
+        match self.history.get(state.history_index) {
+            Some(line) => state.buffer.set(line),
+            None => state.buffer.set(&state.draft),
+        }
+    }
+}
+
+// A single match is filled in, several are filled in as far as they agree
+// and listed if that doesn't add anything
+fn complete(buffer : &mut LineBuffer, candidates : &[String]) -> Step {
+    let (start, matches) = complete::complete(buffer.chars(), buffer.cursor(), candidates);
+    let typed = buffer.cursor() - start;
+
+    match matches.len() {
+        0 => Step::Continue,
+        1 => {
+            buffer.insert(&matches[0].chars().skip(typed).collect::<String>());
+            Step::Continue
+        },
+        _ => {
+            let prefix = complete::common_prefix(&matches);
+            if prefix.chars().count() > typed {
+                buffer.insert(&prefix.chars().skip(typed).collect::<String>());
+                Step::Continue
+            }
+            else {
+                Step::List(matches)
+            }
+        },
+    }
+}
+
+// Redraws the whole line and then puts the cursor back where it belongs
+fn render<W : Write>(output : &mut W, prompt : &str, state : &State) -> io::Result<()> {
+    match &state.search {
+        Some(search) => {
+            let label = if search.found.is_some() || search.query.is_empty() { "reverse-i-search" } else { "failed reverse-i-search" };
+            write!(output, "\r({})'{}': {}\x1b[K", label, search.query, state.buffer.text())?;
+        },
+        None => {
+            write!(output, "\r{}{}\x1b[K\r", prompt, state.buffer.text())?;
+            let column = width(prompt) + state.buffer.cursor();
+            if column > 0 {
+                write!(output, "\x1b[{}C", column)?;
+            }
+        },
+    }
+
+    output.flush()
+}
+
+// Colour and other escape sequences in a prompt don't take up any columns
+fn width(prompt : &str) -> usize {
+    let mut width = 0;
+    let mut chars = prompt.chars();
+
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            // a CSI sequence runs to its final letter
+            if chars.next() == Some('[') {
+                chars.by_ref().find(|c| c.is_ascii_alphabetic());
+            }
+        }
+        else {
+            width += 1;
+        }
+    }
+
+    width
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn editor(entries : &[&str]) -> Editor {
+        let mut history = History::new();
+        for e in entries {
+            history.add(e);
+        }
+        Editor::new(history)
+    }
+
+    fn type_keys(editor : &mut Editor, keys : &[Key], candidates : &[&str]) -> (State, Option<ReadLine>) {
+        let candidates = candidates.iter().map(|c| c.to_string()).collect::<Vec<_>>();
+        let mut state = editor.start();
+
+        for key in keys {
+            if let Step::Done(line) = editor.handle(&mut state, *key, &candidates) {
+                return (state, Some(line));
+            }
+        }
+
+        (state, None)
+    }
+
+    fn chars(s : &str) -> Vec<Key> {
+        s.chars().map(Key::Char).collect()
+    }
+
+    #[test]
+    fn should_edit_and_submit_line() {
+        let mut e = editor(&[]);
+        let mut keys = chars("ad(1)");
+        keys.extend(&[Key::Ctrl('a'), Key::Right, Key::Char('d'), Key::Ctrl('e'), Key::Enter]);
+
+        let (_, line) = type_keys(&mut e, &keys, &[]);
+
+        assert_eq!( line, Some(ReadLine::Line("add(1)".to_string())) );
+    }
+
+    #[test]
+    fn should_yank_killed_text() {
+        let mut e = editor(&[]);
+        let mut keys = chars("one two");
+        keys.extend(&[Key::Ctrl('w'), Key::Ctrl('a'), Key::Ctrl('y')]);
+
+        let (state, _) = type_keys(&mut e, &keys, &[]);
+
+        assert_eq!( state.buffer.text(), "twoone " );
+    }
+
+    #[test]
+    fn ctrl_d_should_end_input_only_on_empty_line() {
+        let mut e = editor(&[]);
+
+        let (_, empty) = type_keys(&mut e, &[Key::Ctrl('d')], &[]);
+        let (state, _) = type_keys(&mut e, &[Key::Char('a'), Key::Left, Key::Ctrl('d')], &[]);
+
+        assert_eq!( empty, Some(ReadLine::Eof) );
+        assert_eq!( state.buffer.text(), "" );
+    }
+
+    #[test]
+    fn should_move_through_history_and_back_to_draft() {
+        let mut e = editor(&["first", "second"]);
+
+        let (state, _) = type_keys(&mut e, &[Key::Char('x'), Key::Up, Key::Up, Key::Up], &[]);
+        assert_eq!( state.buffer.text(), "first" );
+
+        let (state, _) = type_keys(&mut e, &[Key::Char('x'), Key::Up, Key::Up, Key::Down, Key::Down], &[]);
+        assert_eq!( state.buffer.text(), "x" );
+    }
+
+    #[test]
+    fn reverse_search_should_find_older_matches() {
+        let mut e = editor(&["let x = 1", "print(x)", "let y = 2"]);
+        let mut keys = vec![Key::Ctrl('r')];
+        keys.extend(chars("let"));
+        keys.extend(&[Key::Ctrl('r'), Key::Enter]);
+
+        let (_, line) = type_keys(&mut e, &keys, &[]);
+
+        assert_eq!( line, Some(ReadLine::Line("let x = 1".to_string())) );
+    }
+
+    #[test]
+    fn reverse_search_should_restore_line_when_cancelled() {
+        let mut e = editor(&["let x = 1"]);
+        let mut keys = chars("abc");
+        keys.push(Key::Ctrl('r'));
+        keys.extend(chars("let"));
+        keys.push(Key::Ctrl('g'));
+
+        let (state, _) = type_keys(&mut e, &keys, &[]);
+
+        assert_eq!( state.buffer.text(), "abc" );
+        assert!( state.search.is_none() );
+    }
+
+    #[test]
+    fn reverse_search_should_leave_match_for_editing() {
+        let mut e = editor(&["print(x)"]);
+        let mut keys = vec![Key::Ctrl('r')];
+        keys.extend(chars("pr"));
+        keys.extend(&[Key::Ctrl('e'), Key::Backspace]);
+
+        let (state, _) = type_keys(&mut e, &keys, &[]);
+
+        assert_eq!( state.buffer.text(), "print(x" );
+    }
+
+    #[test]
+    fn tab_should_complete_unique_match() {
+        let mut e = editor(&[]);
+        let mut keys = chars("pri");
+        keys.push(Key::Tab);
+
+        let (state, _) = type_keys(&mut e, &keys, &["print", "push"]);
+
+        assert_eq!( state.buffer.text(), "print" );
+    }
+
+    #[test]
+    fn tab_should_complete_common_prefix_then_list() {
+        let candidates = ["pushd", "push", "popd"];
+        let mut e = editor(&[]);
+        let mut state = e.start();
+        let candidates = candidates.iter().map(|c| c.to_string()).collect::<Vec<_>>();
+
+        for c in "pu".chars() {
+            e.handle(&mut state, Key::Char(c), &candidates);
+        }
+
+        assert!(matches!( e.handle(&mut state, Key::Tab, &candidates), Step::Continue ));
+        assert_eq!( state.buffer.text(), "push" );
+        assert!(matches!( e.handle(&mut state, Key::Tab, &candidates), Step::List(words) if words == vec!["push", "pushd"] ));
+    }
+
+    #[test]
+    fn width_should_skip_escape_sequences() {
+        assert_eq!( width("\x1b[1;32mash\x1b[0m> "), 5 );
+        assert_eq!( width("λ "), 2 );
+    }
+
+    #[test]
+    fn render_should_place_cursor_after_prompt() {
+        let mut state = editor(&[]).start();
+        state.buffer.set("abc");
+        state.buffer.left();
+        let mut output = vec![];
+
+        render(&mut output, "> ", &state).unwrap();
+
+        assert_eq!( String::from_utf8(output).unwrap(), "\r> abc\x1b[K\r\x1b[4C" );
+    }
+}
@@ -0,0 +1,76 @@
+
+use std::io;
+use std::os::raw::{c_int, c_uchar, c_uint};
+
+// struct termios as glibc and musl lay it out on Linux
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Termios {
+    c_iflag : c_uint,
+    c_oflag : c_uint,
+    c_cflag : c_uint,
+    c_lflag : c_uint,
+    c_line : c_uchar,
+    c_cc : [c_uchar; 32],
+    c_ispeed : c_uint,
+    c_ospeed : c_uint,
+}
+
+extern "C" {
+    fn tcgetattr(fd : c_int, termios : *mut Termios) -> c_int;
+    fn tcsetattr(fd : c_int, optional_actions : c_int, termios : *const Termios) -> c_int;
+}
+
+const STDIN : c_int = 0;
+const TCSAFLUSH : c_int = 2;
+
+const BRKINT : c_uint = 0o2;
+const INPCK : c_uint = 0o20;
+const ISTRIP : c_uint = 0o40;
+const ICRNL : c_uint = 0o400;
+const IXON : c_uint = 0o2000;
+const OPOST : c_uint = 0o1;
+const CS8 : c_uint = 0o60;
+const ISIG : c_uint = 0o1;
+const ICANON : c_uint = 0o2;
+const ECHO : c_uint = 0o10;
+const IEXTEN : c_uint = 0o100000;
+const VTIME : usize = 5;
+const VMIN : usize = 6;
+
+// Puts the terminal into raw mode until dropped. Keys arrive one at a time,
+// nothing is echoed, ctrl-c is just another key, and output isn't post 
+// processed so newlines have to be written as "\r\n"
+pub struct RawMode {
+    original : Termios,
+}
+
+impl RawMode {
+    pub fn enable() -> io::Result<RawMode> {
+        let mut original = Termios { c_iflag: 0, c_oflag: 0, c_cflag: 0, c_lflag: 0, c_line: 0, c_cc: [0; 32], c_ispeed: 0, c_ospeed: 0 };
+
+        if unsafe { tcgetattr(STDIN, &mut original) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut raw = original;
+        raw.c_iflag &= !(BRKINT | ICRNL | INPCK | ISTRIP | IXON);
+        raw.c_oflag &= !OPOST;
+        raw.c_cflag |= CS8;
+        raw.c_lflag &= !(ECHO | ICANON | IEXTEN | ISIG);
+        raw.c_cc[VMIN] = 1;
+        raw.c_cc[VTIME] = 0;
+
+        if unsafe { tcsetattr(STDIN, TCSAFLUSH, &raw) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(RawMode { original })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        unsafe { tcsetattr(STDIN, TCSAFLUSH, &self.original); }
+    }
+}
@@ -0,0 +1,187 @@
+
+use crate::parsing::ast::Span;
+use super::error::RuntimeError;
+use super::prompt;
+use super::value::{Builtin, Context, Value};
+
+pub const BUILTINS : &[Builtin] = &[
+    Builtin { name: "print", f: print },
+    Builtin { name: "str", f: str },
+    Builtin { name: "len", f: len },
+    Builtin { name: "push", f: push },
+    Builtin { name: "add", f: add },
+    Builtin { name: "sub", f: sub },
+    Builtin { name: "mul", f: mul },
+    Builtin { name: "div", f: div },
+    Builtin { name: "rem", f: rem },
+    Builtin { name: "eq", f: eq },
+    Builtin { name: "lt", f: lt },
+    Builtin { name: "gt", f: gt },
+    Builtin { name: "not", f: not },
+    Builtin { name: "cwd", f: prompt::cwd },
+    Builtin { name: "git_branch", f: prompt::git_branch },
+    Builtin { name: "last_status", f: prompt::last_status },
+];
+
+fn arity(args : &[Value], expected : usize, span : Span) -> Result<(), RuntimeError> {
+    if args.len() == expected {
+        Ok(())
+    }
+    else {
+        Err(RuntimeError::ArityMismatch { expected, found: args.len(), span })
+    }
+}
+
+fn integers(args : &[Value], span : Span) -> Result<(i64, i64), RuntimeError> {
+    arity(args, 2, span)?;
+    match (&args[0], &args[1]) {
+        (Value::Integer(a), Value::Integer(b)) => Ok((*a, *b)),
+        (Value::Integer(_), other) => Err(RuntimeError::TypeMismatch { expected: "integer", found: other.type_name(), span }),
+        (other, _) => Err(RuntimeError::TypeMismatch { expected: "integer", found: other.type_name(), span }),
+    }
+}
+
+fn overflow(span : Span) -> RuntimeError {
+    RuntimeError::Builtin { message: "integer overflow".to_string(), span }
+}
+
+fn print(args : &[Value], _context : &Context, _span : Span) -> Result<Value, RuntimeError> {
+    let text = args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>().join(" ");
+    println!("{}", text);
+    Ok(Value::unit())
+}
+
+fn str(args : &[Value], _context : &Context, span : Span) -> Result<Value, RuntimeError> {
+    arity(args, 1, span)?;
+    Ok(Value::String(args[0].to_string()))
+}
+
+fn len(args : &[Value], _context : &Context, span : Span) -> Result<Value, RuntimeError> {
+    arity(args, 1, span)?;
+    let length = match &args[0] {
+        Value::String(s) => s.chars().count(),
+        Value::List(items) => items.len(),
+        Value::Tuple(items) => items.len(),
+        Value::Record(fields) => fields.len(),
+        other => return Err(RuntimeError::TypeMismatch { expected: "string, list, tuple or record", found: other.type_name(), span }),
+    };
+    Ok(Value::Integer(length as i64))
+}
+
+fn push(args : &[Value], _context : &Context, span : Span) -> Result<Value, RuntimeError> {
+    arity(args, 2, span)?;
+    match &args[0] {
+        Value::List(items) => {
+            let mut items = items.clone();
+            items.push(args[1].clone());
+            Ok(Value::List(items))
+        },
+        other => Err(RuntimeError::TypeMismatch { expected: "list", found: other.type_name(), span }),
+    }
+}
+
+fn add(args : &[Value], _context : &Context, span : Span) -> Result<Value, RuntimeError> {
+    arity(args, 2, span)?;
+    match (&args[0], &args[1]) {
+        (Value::String(a), Value::String(b)) => Ok(Value::String(format!("{}{}", a, b))),
+        (Value::List(a), Value::List(b)) => Ok(Value::List(a.iter().chain(b.iter()).cloned().collect())),
+        _ => {
+            let (a, b) = integers(args, span)?;
+            a.checked_add(b).map(Value::Integer).ok_or_else(|| overflow(span))
+        },
+    }
+}
+
+fn sub(args : &[Value], _context : &Context, span : Span) -> Result<Value, RuntimeError> {
+    let (a, b) = integers(args, span)?;
+    a.checked_sub(b).map(Value::Integer).ok_or_else(|| overflow(span))
+}
+
+fn mul(args : &[Value], _context : &Context, span : Span) -> Result<Value, RuntimeError> {
+    let (a, b) = integers(args, span)?;
+    a.checked_mul(b).map(Value::Integer).ok_or_else(|| overflow(span))
+}
+
+fn div(args : &[Value], _context : &Context, span : Span) -> Result<Value, RuntimeError> {
+    let (a, b) = integers(args, span)?;
+    if b == 0 {
+        return Err(RuntimeError::Builtin { message: "division by zero".to_string(), span });
+    }
+    a.checked_div(b).map(Value::Integer).ok_or_else(|| overflow(span))
+}
+
+fn rem(args : &[Value], _context : &Context, span : Span) -> Result<Value, RuntimeError> {
+    let (a, b) = integers(args, span)?;
+    if b == 0 {
+        return Err(RuntimeError::Builtin { message: "division by zero".to_string(), span });
+    }
+    a.checked_rem(b).map(Value::Integer).ok_or_else(|| overflow(span))
+}
+
+fn eq(args : &[Value], _context : &Context, span : Span) -> Result<Value, RuntimeError> {
+    arity(args, 2, span)?;
+    Ok(Value::Bool(args[0] == args[1]))
+}
+
+fn compare(args : &[Value], span : Span) -> Result<std::cmp::Ordering, RuntimeError> {
+    arity(args, 2, span)?;
+    match (&args[0], &args[1]) {
+        (Value::Integer(a), Value::Integer(b)) => Ok(a.cmp(b)),
+        (Value::String(a), Value::String(b)) => Ok(a.cmp(b)),
+        (Value::Char(a), Value::Char(b)) => Ok(a.cmp(b)),
+        (a, b) if a.type_name() == b.type_name() =>
+            Err(RuntimeError::TypeMismatch { expected: "integer, string or char", found: a.type_name(), span }),
+        (a, b) => Err(RuntimeError::TypeMismatch { expected: a.type_name(), found: b.type_name(), span }),
+    }
+}
+
+fn lt(args : &[Value], _context : &Context, span : Span) -> Result<Value, RuntimeError> {
+    Ok(Value::Bool(compare(args, span)? == std::cmp::Ordering::Less))
+}
+
+fn gt(args : &[Value], _context : &Context, span : Span) -> Result<Value, RuntimeError> {
+    Ok(Value::Bool(compare(args, span)? == std::cmp::Ordering::Greater))
+}
+
+fn not(args : &[Value], _context : &Context, span : Span) -> Result<Value, RuntimeError> {
+    arity(args, 1, span)?;
+    match &args[0] {
+        Value::Bool(b) => Ok(Value::Bool(!b)),
+        // a failed command is false, so not gives true for it
+        Value::Status(status) => Ok(Value::Bool(*status != 0)),
+        other => Err(RuntimeError::TypeMismatch { expected: "bool or status", found: other.type_name(), span }),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn add_should_concatenate_strings() {
+        let v = add(&[Value::String("a".to_string()), Value::String("b".to_string())], &Context::default(), Span::default());
+
+        assert_eq!( v, Ok(Value::String("ab".to_string())) );
+    }
+
+    #[test]
+    fn add_should_report_overflow() {
+        let v = add(&[Value::Integer(i64::MAX), Value::Integer(1)], &Context::default(), Span::default());
+
+        assert!(matches!( v, Err(RuntimeError::Builtin { .. }) ));
+    }
+
+    #[test]
+    fn div_should_report_division_by_zero() {
+        let v = div(&[Value::Integer(1), Value::Integer(0)], &Context::default(), Span::default());
+
+        assert!(matches!( v, Err(RuntimeError::Builtin { .. }) ));
+    }
+
+    #[test]
+    fn lt_should_reject_mixed_types() {
+        let v = lt(&[Value::Integer(1), Value::String("2".to_string())], &Context::default(), Span::default());
+
+        assert!(matches!( v, Err(RuntimeError::TypeMismatch { expected: "integer", found: "string", .. }) ));
+    }
+}
@@ -0,0 +1,737 @@
+use std::collections::HashMap;
+use std::env;
+use std::ffi::OsStr;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, PipeReader, Read, Write};
+use std::os::fd::{AsFd, OwnedFd, RawFd};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{self, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Sender, SyncSender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+use crate::parsing::ast::Span;
+use super::error::RuntimeError;
+use super::jobs::{self, Pid, Wait};
+use super::value::Value;
+
+pub struct Stage {
+    pub run : Run,
+    pub redirects : Vec<Redirect>,
+    pub span : Span,
+}
+
+pub enum Run {
+    Program(Vec<String>),
+    // an ash function, called with each line read from the previous stage
+    // followed by the rest of the stage's words, or once with only the words
+    // when it's the first stage
+    Function(Value, Vec<String>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Redirect {
+    Input(String),
+    Output(String),
+    Append(String),
+    Error(String),
+    ErrorToOutput,
+    Both(String),
+}
+
+// A stage's standard streams, where None leaves the shell's own in place
+struct Fds {
+    stdin : Option<OwnedFd>,
+    stdout : Option<OwnedFd>,
+    stderr : Option<OwnedFd>,
+}
+
+impl Fds {
+    // Applied left to right like other shells, so '> f 2>&1' sends both to f
+    // while '2>&1 > f' sends only stdout there
+    fn redirect(&mut self, redirects : &[Redirect], span : Span) -> Result<(), RuntimeError> {
+        for redirect in redirects {
+            match redirect {
+                Redirect::Input(path) => self.stdin = Some(open(path, File::open(path), span)?),
+                Redirect::Output(path) => self.stdout = Some(open(path, File::create(path), span)?),
+                Redirect::Append(path) => {
+                    let file = OpenOptions::new().append(true).create(true).open(path);
+                    self.stdout = Some(open(path, file, span)?);
+                },
+                Redirect::Error(path) => self.stderr = Some(open(path, File::create(path), span)?),
+                Redirect::ErrorToOutput => {
+                    let stdout = match &self.stdout {
+                        Some(fd) => fd.try_clone(),
+                        None => io::stdout().as_fd().try_clone_to_owned(),
+                    };
+                    self.stderr = Some(stdout.map_err(|e| pipe_error(e, span))?);
+                },
+                Redirect::Both(path) => {
+                    let fd = open(path, File::create(path), span)?;
+                    self.stderr = Some(fd.try_clone().map_err(|e| pipe_error(e, span))?);
+                    self.stdout = Some(fd);
+                },
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn open(path : &str, file : io::Result<File>, span : Span) -> Result<OwnedFd, RuntimeError> {
+    file.map(OwnedFd::from)
+        .map_err(|e| RuntimeError::RedirectFailed { path: path.to_string(), message: e.to_string(), span })
+}
+
+fn pipe_error(e : io::Error, span : Span) -> RuntimeError {
+    RuntimeError::CommandFailed { name: "pipe".to_string(), message: e.to_string(), span }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Capture {
+    Nothing,
+    Stdout,
+    // stderr is taken from every stage, not only the last
+    All,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Captured {
+    pub status : i32,
+    pub stdout : String,
+    pub stderr : String,
+}
+
+// How a pipeline's processes are grouped, for job control
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Group {
+    // left in the shell's group, as when there's no terminal to share
+    Shell,
+    // in a group of their own, as background jobs are
+    Own,
+    // in a group of their own that is given the terminal
+    Foreground(RawFd),
+}
+
+pub struct Settings<'a> {
+    pub pipefail : bool,
+    pub capture : Capture,
+    pub group : Group,
+    pub environment : &'a HashMap<String, String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Progress {
+    Finished,
+    Stopped,
+    Running,
+}
+
+// A pipeline whose processes have been started but not yet waited for
+pub struct Running {
+    group : Option<Pid>,
+    // stage indexes and pids of the processes that haven't finished
+    waiting : Vec<(usize, Pid)>,
+    statuses : Vec<i32>,
+    pipefail : bool,
+    writers : Vec<JoinHandle<()>>,
+    stdout : Option<JoinHandle<String>>,
+    stderr : Option<JoinHandle<String>>,
+}
+
+impl Running {
+    pub fn group(&self) -> Option<Pid> {
+        self.group
+    }
+
+    // Stops at the first process found stopped, since the rest of its group will
+    // have been stopped along with it
+    pub fn wait(&mut self, block : bool) -> Progress {
+        while let Some(&(stage, pid)) = self.waiting.first() {
+            match jobs::wait_pid(pid, block) {
+                Wait::Exited(status) => {
+                    self.statuses[stage] = status;
+                    self.waiting.remove(0);
+                },
+                Wait::Stopped => return Progress::Stopped,
+                Wait::Running => return Progress::Running,
+            }
+        }
+
+        Progress::Finished
+    }
+
+    // The status is the last stage's, or with pipefail the last stage's that failed.
+    // Only called once every process has finished
+    pub fn finish(self) -> Captured {
+        for writer in self.writers {
+            let _ = writer.join();
+        }
+
+        let stdout = self.stdout.map(|reader| reader.join().unwrap_or_default()).unwrap_or_default();
+        let stderr = self.stderr.map(|reader| reader.join().unwrap_or_default()).unwrap_or_default();
+
+        let status = if self.pipefail {
+            self.statuses.iter().rev().find(|status| **status != 0).copied().unwrap_or(0)
+        }
+        else {
+            *self.statuses.last().expect("command::start given no stages")
+        };
+
+        Captured { status, stdout, stderr }
+    }
+}
+
+// Starts the stages of a pipeline, each program in its own process so that they
+// all run at once. Function stages run on the calling thread once the programs
+// have started, taking each line as it arrives, so this only returns when they
+// have all finished.
+//
+// Whatever was started is given back even after an error, so that it can be 
+// waited for and no process is left behind
+pub fn start<F>(stages : Vec<Stage>, settings : Settings, mut call : F) -> (Running, Result<(), RuntimeError>)
+    where F : FnMut(Value, Vec<Value>, Span) -> Result<Value, RuntimeError> {
+
+    let mut running = Running { group: None
+                              , waiting: vec![]
+                              , statuses: vec![0; stages.len()]
+                              , pipefail: settings.pipefail
+                              , writers: vec![]
+                              , stdout: None
+                              , stderr: None
+                              };
+
+    let span = stages.first().expect("command::start given no stages").span;
+
+    let outer = collect(settings.capture, span, &mut running);
+    let started = outer.and_then(|outer| spawn(stages, &settings, &mut call, outer, &mut running));
+
+    (running, started)
+}
+
+// Pipes to capture into, read from other threads so that they never fill
+fn collect(capture : Capture, span : Span, running : &mut Running) -> Result<Fds, RuntimeError> {
+    fn pipe(span : Span) -> Result<(OwnedFd, JoinHandle<String>), RuntimeError> {
+        let (reader, writer) = io::pipe().map_err(|e| pipe_error(e, span))?;
+        let reader = thread::spawn(move || read_text(reader).unwrap_or_default());
+        Ok((OwnedFd::from(writer), reader))
+    }
+
+    let mut outer = Fds { stdin: None, stdout: None, stderr: None };
+
+    if capture != Capture::Nothing {
+        let (writer, reader) = pipe(span)?;
+        outer.stdout = Some(writer);
+        running.stdout = Some(reader);
+    }
+    if capture == Capture::All {
+        let (writer, reader) = pipe(span)?;
+        outer.stderr = Some(writer);
+        running.stderr = Some(reader);
+    }
+
+    Ok(outer)
+}
+
+// The pipeline's own stdout goes to the last stage and its stderr to every stage.
+// They're dropped before returning so that a capture sees the end of its pipe
+fn spawn<F>( stages : Vec<Stage>
+           , settings : &Settings
+           , call : &mut F
+           , mut outer : Fds
+           , running : &mut Running
+           ) -> Result<(), RuntimeError>
+    where F : FnMut(Value, Vec<Value>, Span) -> Result<Value, RuntimeError> {
+
+    let last = stages.len() - 1;
+    let mut upstream : Option<PipeReader> = None;
+    let mut functions = vec![];
+
+    for (i, Stage { run, redirects, span }) in stages.into_iter().enumerate() {
+        let (reader, writer) = if i == last {
+            (None, outer.stdout.take())
+        }
+        else {
+            let (reader, writer) = io::pipe().map_err(|e| pipe_error(e, span))?;
+            (Some(reader), Some(OwnedFd::from(writer)))
+        };
+
+        let stderr = match &outer.stderr {
+            Some(fd) => Some(fd.try_clone().map_err(|e| pipe_error(e, span))?),
+            None => None,
+        };
+
+        let mut fds = Fds { stdin: upstream.take().map(OwnedFd::from), stdout: writer, stderr };
+        fds.redirect(&redirects, span)?;
+        upstream = reader;
+
+        match run {
+            Run::Program(words) => {
+                let (name, args) = words.split_first().expect("command::start given a stage with no words");
+
+                let program = find_program(name, settings.environment.get("PATH").map(OsStr::new))
+                    .ok_or_else(|| RuntimeError::CommandNotFound { name: name.clone(), span })?;
+
+                let mut command = process::Command::new(program);
+                command.arg0(name).args(args).env_clear().envs(settings.environment);
+
+                if let Some(fd) = fds.stdin {
+                    command.stdin(Stdio::from(fd));
+                }
+                if let Some(fd) = fds.stdout {
+                    command.stdout(Stdio::from(fd));
+                }
+                if let Some(fd) = fds.stderr {
+                    command.stderr(Stdio::from(fd));
+                }
+
+                let terminal = match settings.group {
+                    Group::Shell => None,
+                    Group::Own => Some(None),
+                    Group::Foreground(fd) => Some(Some(fd)),
+                };
+
+                if let Some(terminal) = terminal {
+                    let pgid = running.group.unwrap_or(0);
+                    unsafe {
+                        command.pre_exec(move || { jobs::enter_group(pgid, terminal); Ok(()) });
+                    }
+                }
+
+                let child = command.spawn()
+                    .map_err(|e| RuntimeError::CommandFailed { name: name.clone(), message: e.to_string(), span })?;
+                let pid = child.id() as Pid;
+
+                if terminal.is_some() {
+                    jobs::join_group(pid, *running.group.get_or_insert(pid));
+                }
+
+                running.waiting.push((i, pid));
+            },
+            // the shell's own stdin is left alone, it isn't this pipeline's to consume
+            Run::Function(function, args) => functions.push(Function { stage: i, function, args, fds, span }),
+        }
+    }
+
+    run_functions(functions, call, running)
+}
+
+// A function stage, waiting for the programs around it to start
+struct Function {
+    stage : usize,
+    function : Value,
+    args : Vec<String>,
+    fds : Fds,
+    span : Span,
+}
+
+// A function stage whose lines other threads are reading and writing
+struct Streaming {
+    stage : usize,
+    function : Value,
+    args : Vec<String>,
+    span : Span,
+    // dropped once the stage's input ends, so that its writer closes its output
+    output : Option<Sender<Vec<String>>>,
+    // set once what the stage writes to has gone, or the pipeline has failed
+    stopped : Arc<AtomicBool>,
+}
+
+impl Streaming {
+    // The status of a stage is that of its last call
+    fn give(&self, result : Result<Value, RuntimeError>, running : &mut Running) -> Result<(), RuntimeError> {
+        let (status, lines) = function_output(result?);
+        running.statuses[self.stage] = status;
+
+        let sent = match &self.output {
+            Some(output) if !lines.is_empty() => output.send(lines).is_ok(),
+            _ => true,
+        };
+        if !sent {
+            self.stopped.store(true, Ordering::Relaxed);
+        }
+        Ok(())
+    }
+}
+
+fn stop_all(stages : &[Streaming]) {
+    for stage in stages {
+        stage.stopped.store(true, Ordering::Relaxed);
+    }
+}
+
+// Only the calling thread can run ash, so other threads read and write the lines
+// of every function stage for it. Lines read are queued for the calling thread
+// in the order they arrive, and what each call gives back is queued for writing.
+// Writes can't be allowed to block here, since the stage reading them could be
+// another function waiting on this thread, but reads are held back so that a
+// fast stage before a function can't fill memory. A function stops when what it
+// writes to has gone, as a program would with SIGPIPE, and stops reading then
+// so that the stage before it sees the pipe close too
+fn run_functions<F>(functions : Vec<Function>, call : &mut F, running : &mut Running) -> Result<(), RuntimeError>
+    where F : FnMut(Value, Vec<Value>, Span) -> Result<Value, RuntimeError> {
+
+    let (arrived, arrivals) = mpsc::sync_channel::<(usize, Option<String>)>(1024);
+    let mut stages = vec![];
+
+    for (index, Function { stage, function, args, fds, span }) in functions.into_iter().enumerate() {
+        let stopped = Arc::new(AtomicBool::new(false));
+
+        let (output, queued) = mpsc::channel::<Vec<String>>();
+        let mut out : Box<dyn Write + Send> = match fds.stdout {
+            Some(fd) => Box::new(File::from(fd)),
+            None => Box::new(io::stdout()),
+        };
+        let writer_stopped = stopped.clone();
+        running.writers.push(thread::spawn(move || {
+            for lines in queued {
+                if write_lines(&mut out, &lines).is_err() {
+                    writer_stopped.store(true, Ordering::Relaxed);
+                    break;
+                }
+            }
+        }));
+
+        let first = fds.stdin.is_none();
+        if let Some(fd) = fds.stdin {
+            let (arrived, stopped) = (arrived.clone(), stopped.clone());
+            thread::spawn(move || read_lines(index, File::from(fd), arrived, stopped));
+        }
+
+        stages.push(Streaming { stage, function, args, span, output: Some(output), stopped });
+
+        // nothing comes before the first stage, so it's called once to make lines
+        if first {
+            let stage = &stages[index];
+            let result = call(stage.function.clone(), stage.args.iter().cloned().map(Value::String).collect(), stage.span);
+            stage.give(result, running).inspect_err(|_| stop_all(&stages))?;
+            stages[index].output = None;
+        }
+    }
+
+    // the readers hold the only senders left, so this ends once they all have
+    drop(arrived);
+
+    for (index, line) in arrivals {
+        let line = match line {
+            Some(line) => line,
+            None => {
+                stages[index].output = None;
+                continue;
+            },
+        };
+        let stage = &stages[index];
+        if stage.stopped.load(Ordering::Relaxed) {
+            continue;
+        }
+
+        let mut values = vec![Value::String(line)];
+        values.extend(stage.args.iter().cloned().map(Value::String));
+
+        let result = call(stage.function.clone(), values, stage.span);
+        stage.give(result, running).inspect_err(|_| stop_all(&stages))?;
+    }
+
+    Ok(())
+}
+
+// A list is written one item per line and a string as it is. A status is
+// the stage's status rather than something to write
+fn function_output(value : Value) -> (i32, Vec<String>) {
+    match value {
+        Value::Status(status) => (status, vec![]),
+        Value::List(items) => (0, items.iter().map(|item| item.to_string()).collect()),
+        Value::String(s) => (0, s.lines().map(|line| line.to_string()).collect()),
+        other if other == Value::unit() => (0, vec![]),
+        other => (0, vec![other.to_string()]),
+    }
+}
+
+fn read_text(mut input : impl Read) -> io::Result<String> {
+    let mut bytes = vec![];
+    input.read_to_end(&mut bytes)?;
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+// Sends each line without its line ending, then None once the input ends or the
+// stage stops
+fn read_lines(stage : usize, input : impl Read, arrived : SyncSender<(usize, Option<String>)>, stopped : Arc<AtomicBool>) {
+    let mut input = BufReader::new(input);
+    let mut line = vec![];
+
+    while !stopped.load(Ordering::Relaxed) {
+        line.clear();
+        match input.read_until(b'\n', &mut line) {
+            Ok(0) | Err(_) => break,
+            Ok(_) => {
+                let text = String::from_utf8_lossy(&line);
+                let text = text.strip_suffix('\n').unwrap_or(&text);
+                let text = text.strip_suffix('\r').unwrap_or(text);
+                if arrived.send((stage, Some(text.to_string()))).is_err() {
+                    return;
+                }
+            },
+        }
+    }
+    let _ = arrived.send((stage, None));
+}
+
+fn write_lines(out : &mut impl Write, lines : &[String]) -> io::Result<()> {
+    for line in lines {
+        writeln!(out, "{}", line)?;
+    }
+    out.flush()
+}
+
+// Names with a '/' in them are paths and aren't looked up
+pub fn find_program(name : &str, path : Option<&OsStr>) -> Option<PathBuf> {
+    if name.contains('/') {
+        return Some(PathBuf::from(name));
+    }
+
+    env::split_paths(path?)
+        .map(|dir| if dir.as_os_str().is_empty() { Path::new(".").join(name) } else { dir.join(name) })
+        .find(|candidate| is_executable(candidate))
+}
+
+fn is_executable(path : &Path) -> bool {
+    match path.metadata() {
+        Ok(metadata) => metadata.is_file() && metadata.permissions().mode() & 0o111 != 0,
+        Err(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // starts a pipeline and waits for all of it
+    fn run<F>(stages : Vec<Stage>, pipefail : bool, capture : Capture, environment : &HashMap<String, String>, call : F) -> Result<Captured, RuntimeError>
+        where F : FnMut(Value, Vec<Value>, Span) -> Result<Value, RuntimeError> {
+
+        let settings = Settings { pipefail, capture, group: Group::Shell, environment };
+        let (mut running, started) = start(stages, settings, call);
+        running.wait(true);
+        let captured = running.finish();
+        started.map(|_| captured)
+    }
+
+    fn environment() -> HashMap<String, String> {
+        env::vars().collect()
+    }
+
+    fn program(ws : &[&str]) -> Stage {
+        redirected(ws, vec![])
+    }
+
+    fn redirected(ws : &[&str], redirects : Vec<Redirect>) -> Stage {
+        Stage { run: Run::Program(ws.iter().map(|w| w.to_string()).collect()), redirects, span: Span::default() }
+    }
+
+    fn temp_path(name : &str) -> String {
+        let path = std::env::temp_dir().join(format!("ash_redirect_test_{}_{}", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path.to_string_lossy().into_owned()
+    }
+
+    fn run_programs(stages : Vec<Stage>, pipefail : bool) -> Result<Value, RuntimeError> {
+        run(stages, pipefail, Capture::Nothing, &environment(), |_, _, _| panic!("no functions in this pipeline"))
+            .map(|captured| Value::Status(captured.status))
+    }
+
+    #[test]
+    fn find_program_should_search_path_in_order() {
+        let path = std::env::join_paths(["/does/not/exist", "/bin", "/usr/bin"]).unwrap();
+
+        let found = find_program("sh", Some(&path));
+
+        assert!( found.is_some_and(|p| p.ends_with("sh")) );
+    }
+
+    #[test]
+    fn find_program_should_not_search_for_paths() {
+        let found = find_program("./blah", None);
+
+        assert_eq!( found, Some(PathBuf::from("./blah")) );
+    }
+
+    #[test]
+    fn find_program_should_not_find_missing_program() {
+        let path = std::env::join_paths(["/bin", "/usr/bin"]).unwrap();
+
+        assert_eq!( find_program("ash-no-such-program", Some(&path)), None );
+    }
+
+    #[test]
+    fn run_should_return_exit_status() {
+        assert_eq!( run_programs(vec![program(&["sh", "-c", "exit 3"])], false), Ok(Value::Status(3)) );
+        assert_eq!( run_programs(vec![program(&["true"])], false), Ok(Value::Status(0)) );
+    }
+
+    #[test]
+    fn run_should_report_signal_as_exit_status() {
+        let v = run_programs(vec![program(&["sh", "-c", "kill -9 $$"])], false);
+
+        assert_eq!( v, Ok(Value::Status(137)) );
+    }
+
+    #[test]
+    fn run_should_report_missing_command() {
+        let v = run_programs(vec![program(&["ash-no-such-program"])], false);
+
+        assert!(matches!( v, Err(RuntimeError::CommandNotFound { name, .. }) if name == "ash-no-such-program" ));
+    }
+
+    #[test]
+    fn run_should_connect_stages() {
+        let v = run_programs(vec![ program(&["printf", "a\\nb\\n"])
+                                 , program(&["sh", "-c", "test $(wc -l) = 2"])
+                                 ], false);
+
+        assert_eq!( v, Ok(Value::Status(0)) );
+    }
+
+    #[test]
+    fn run_should_report_failing_stage_with_pipefail() {
+        let stages = || vec![program(&["sh", "-c", "exit 3"]), program(&["sh", "-c", "exit 4"]), program(&["true"])];
+
+        assert_eq!( run_programs(stages(), false), Ok(Value::Status(0)) );
+        assert_eq!( run_programs(stages(), true), Ok(Value::Status(4)) );
+    }
+
+    #[test]
+    fn run_should_wait_for_started_stages_after_missing_command() {
+        let v = run_programs(vec![program(&["sleep", "0"]), program(&["ash-no-such-program"])], false);
+
+        assert!(matches!( v, Err(RuntimeError::CommandNotFound { .. }) ));
+    }
+
+    #[test]
+    fn run_should_pass_lines_through_function_stage() {
+        let stages = vec![ program(&["printf", "a\\nb\\n"])
+                         , Stage { run: Run::Function(Value::unit(), vec!["c".to_string()]), redirects: vec![], span: Span::default() }
+                         , program(&["sh", "-c", "test \"$(cat)\" = \"$(printf 'ac\\nbc')\""])
+                         ];
+
+        let v = run(stages, false, Capture::Nothing, &environment(), |_, args, _| match &args[..] {
+            [Value::String(line), Value::String(extra)] => Ok(Value::String(format!("{}{}", line, extra))),
+            other => panic!("unexpected arguments {:?}", other),
+        });
+
+        assert_eq!( v.map(|captured| captured.status), Ok(0) );
+    }
+
+    #[test]
+    fn run_should_stream_lines_through_function_stage() {
+        let stages = vec![ program(&["yes"])
+                         , Stage { run: Run::Function(Value::unit(), vec![]), redirects: vec![], span: Span::default() }
+                         , program(&["head", "-1"])
+                         ];
+
+        let v = run(stages, false, Capture::Stdout, &environment(), |_, args, _| Ok(args[0].clone()));
+
+        assert_eq!( v.map(|captured| captured.stdout), Ok("y\n".to_string()) );
+    }
+
+    #[test]
+    fn run_should_call_first_function_stage_once() {
+        let stages = vec![ Stage { run: Run::Function(Value::unit(), vec!["3".to_string()]), redirects: vec![], span: Span::default() }
+                         , program(&["cat"])
+                         ];
+        let mut calls = 0;
+
+        let v = run(stages, false, Capture::Stdout, &environment(), |_, args, _| {
+            calls += 1;
+            assert_eq!( args, vec![Value::String("3".to_string())] );
+            Ok(Value::List(vec![Value::Integer(1), Value::Integer(2)]))
+        });
+
+        assert_eq!( v.map(|captured| captured.stdout), Ok("1\n2\n".to_string()) );
+        assert_eq!( calls, 1 );
+    }
+
+    #[test]
+    fn redirect_should_write_append_and_read_files() {
+        let path = temp_path("write");
+
+        run_programs(vec![redirected(&["echo", "a"], vec![Redirect::Output(path.clone())])], false).unwrap();
+        run_programs(vec![redirected(&["echo", "b"], vec![Redirect::Append(path.clone())])], false).unwrap();
+        let v = run_programs(vec![redirected(&["sh", "-c", "test \"$(cat)\" = \"$(printf 'a\\nb')\""], vec![Redirect::Input(path.clone())])], false);
+
+        assert_eq!( v, Ok(Value::Status(0)) );
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn redirect_should_apply_in_order() {
+        let both = temp_path("both");
+        let out = temp_path("out");
+        let script = &["sh", "-c", "echo out; echo err >&2"];
+
+        run_programs(vec![redirected(script, vec![Redirect::Output(both.clone()), Redirect::ErrorToOutput])], false).unwrap();
+        run_programs(vec![redirected(script, vec![Redirect::Error(out.clone()), Redirect::Output(out.clone() + "1")])], false).unwrap();
+
+        assert_eq!( std::fs::read_to_string(&both).unwrap(), "out\nerr\n" );
+        assert_eq!( std::fs::read_to_string(&out).unwrap(), "err\n" );
+        assert_eq!( std::fs::read_to_string(out.clone() + "1").unwrap(), "out\n" );
+        for path in [both, out.clone(), out + "1"] {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+
+    #[test]
+    fn redirect_should_send_both_streams_and_stderr_into_pipe() {
+        let path = temp_path("pipe");
+
+        let v = run_programs(vec![ redirected(&["sh", "-c", "echo err >&2"], vec![Redirect::ErrorToOutput])
+                                 , redirected(&["cat"], vec![Redirect::Both(path.clone())])
+                                 ], false);
+
+        assert_eq!( v, Ok(Value::Status(0)) );
+        assert_eq!( std::fs::read_to_string(&path).unwrap(), "err\n" );
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn redirect_should_report_unreadable_file() {
+        let v = run_programs(vec![redirected(&["cat"], vec![Redirect::Input("/does/not/exist".to_string())])], false);
+
+        assert!(matches!( v, Err(RuntimeError::RedirectFailed { path, .. }) if path == "/does/not/exist" ));
+    }
+
+    #[test]
+    fn run_should_capture_stdout_and_stderr_of_every_stage() {
+        let stages = vec![ program(&["sh", "-c", "echo one >&2; echo a"])
+                         , program(&["sh", "-c", "cat; echo two >&2; exit 2"])
+                         ];
+
+        let v = run(stages, false, Capture::All, &environment(), |_, _, _| panic!("no functions in this pipeline"));
+
+        let v = v.map(|mut captured| {
+            // the two stages write to stderr in whichever order they get to it
+            let mut lines = captured.stderr.lines().collect::<Vec<_>>();
+            lines.sort();
+            captured.stderr = lines.join(",");
+            captured
+        });
+
+        assert_eq!( v, Ok(Captured { status: 2, stdout: "a\n".to_string(), stderr: "one,two".to_string() }) );
+    }
+
+    #[test]
+    fn run_should_leave_stderr_alone_when_capturing_stdout() {
+        let v = run(vec![program(&["sh", "-c", "echo out"])], false, Capture::Stdout, &environment(), |_, _, _| panic!("no functions"));
+
+        assert_eq!( v, Ok(Captured { status: 0, stdout: "out\n".to_string(), stderr: String::new() }) );
+    }
+
+    #[test]
+    fn function_output_should_take_status_from_function() {
+        assert_eq!( function_output(Value::Status(2)), (2, vec![]) );
+        assert_eq!( function_output(Value::String("a\nb".to_string())), (0, vec!["a".to_string(), "b".to_string()]) );
+        assert_eq!( function_output(Value::Integer(1)), (0, vec!["1".to_string()]) );
+    }
+}
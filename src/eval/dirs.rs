@@ -0,0 +1,179 @@
+use std::collections::HashMap;
+use std::env;
+use std::path::{Path, PathBuf};
+
+// The working directory and the stack pushd and popd keep of earlier ones.
+// Changing directory changes the whole process's, as it has to for commands
+// and relative paths to see it
+pub struct Dirs {
+    stack : Vec<PathBuf>,
+}
+
+impl Dirs {
+    pub fn new() -> Dirs {
+        Dirs { stack: vec![] }
+    }
+
+    // 'cd' goes to $HOME, 'cd -' back to $OLDPWD, and a relative path not
+    // starting with '.' is looked for in each directory of $CDPATH first
+    pub fn cd(&mut self, args : &[String], environment : &mut HashMap<String, String>) -> Result<i32, String> {
+        let (target, announce) = match args {
+            [] => (PathBuf::from(variable("HOME", environment)?), false),
+            [dash] if dash == "-" => (PathBuf::from(variable("OLDPWD", environment)?), true),
+            [path] => resolve(path, environment),
+            _ => return Err("cd takes at most one directory".to_string()),
+        };
+
+        change_to(&target, environment)?;
+        // like other shells, say where 'cd -' or a search ended up
+        if announce {
+            println!("{}", current()?.display());
+        }
+        Ok(0)
+    }
+
+    pub fn pwd(&self, args : &[String]) -> Result<i32, String> {
+        if !args.is_empty() {
+            return Err("pwd takes no arguments".to_string());
+        }
+        println!("{}", current()?.display());
+        Ok(0)
+    }
+
+    // 'pushd dir' saves the working directory and goes to dir, and 'pushd'
+    // alone swaps the working directory with the last one saved
+    pub fn pushd(&mut self, args : &[String], environment : &mut HashMap<String, String>) -> Result<i32, String> {
+        let here = current()?;
+
+        match args {
+            [] => {
+                let target = self.stack.pop().ok_or("pushd has no other directory")?;
+                if let Err(message) = change_to(&target, environment) {
+                    self.stack.push(target);
+                    return Err(message);
+                }
+            },
+            [path] => change_to(&resolve(path, environment).0, environment)?,
+            _ => return Err("pushd takes at most one directory".to_string()),
+        }
+
+        self.stack.push(here);
+        self.show(environment)
+    }
+
+    pub fn popd(&mut self, args : &[String], environment : &mut HashMap<String, String>) -> Result<i32, String> {
+        if !args.is_empty() {
+            return Err("popd takes no arguments".to_string());
+        }
+
+        let target = self.stack.last().ok_or("popd has no directory to go back to")?;
+        change_to(target, environment)?;
+        self.stack.pop();
+        self.show(environment)
+    }
+
+    // The working directory then the stack, latest first, with $HOME shown as '~'
+    fn show(&self, environment : &HashMap<String, String>) -> Result<i32, String> {
+        let here = current()?;
+        let dirs = std::iter::once(&here).chain(self.stack.iter().rev())
+                                         .map(|dir| tilde(dir, environment.get("HOME").map(|home| home.as_str())))
+                                         .collect::<Vec<_>>();
+        println!("{}", dirs.join(" "));
+        Ok(0)
+    }
+}
+
+impl Default for Dirs {
+    fn default() -> Dirs {
+        Dirs::new()
+    }
+}
+
+fn variable<'a>(name : &str, environment : &'a HashMap<String, String>) -> Result<&'a str, String> {
+    environment.get(name).map(|value| value.as_str()).ok_or_else(|| format!("cd: ${} is not set", name))
+}
+
+fn current() -> Result<PathBuf, String> {
+    env::current_dir().map_err(|e| format!("cannot find working directory: {}", e))
+}
+
+// Commands started afterwards see the move in $PWD and $OLDPWD
+fn change_to(target : &Path, environment : &mut HashMap<String, String>) -> Result<(), String> {
+    let before = current().ok();
+
+    env::set_current_dir(target).map_err(|e| format!("cd: {}: {}", target.display(), e))?;
+
+    if let Some(before) = before {
+        environment.insert("OLDPWD".to_string(), before.to_string_lossy().into_owned());
+    }
+    environment.insert("PWD".to_string(), current()?.to_string_lossy().into_owned());
+    Ok(())
+}
+
+// Where a cd to path goes, and whether it was found through $CDPATH. An empty
+// entry in $CDPATH is the working directory
+fn resolve(path : &str, environment : &HashMap<String, String>) -> (PathBuf, bool) {
+    let explicit = path.starts_with('/') || path == "." || path == ".."
+                || path.starts_with("./") || path.starts_with("../");
+
+    if let (false, Some(cdpath)) = (explicit, environment.get("CDPATH")) {
+        for entry in cdpath.split(':') {
+            let candidate = Path::new(if entry.is_empty() { "." } else { entry }).join(path);
+            if candidate.is_dir() {
+                return (candidate, !entry.is_empty());
+            }
+        }
+    }
+
+    (PathBuf::from(path), false)
+}
+
+// cd moves the whole process, so any test that changes the working directory
+// or depends on it takes a turn with this
+#[cfg(test)]
+pub fn working_dir_turn() -> std::sync::MutexGuard<'static, ()> {
+    static WORKING_DIR : std::sync::Mutex<()> = std::sync::Mutex::new(());
+    WORKING_DIR.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+// A $HOME of '/' would turn every path into '~' something, so that isn't abbreviated
+pub fn tilde(dir : &Path, home : Option<&str>) -> String {
+    let shown = dir.to_string_lossy().into_owned();
+    match home.map(|home| home.trim_end_matches('/')) {
+        Some(home) if !home.is_empty() && dir.starts_with(home) => format!("~{}", &shown[home.len()..]),
+        _ => shown,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn environment(pairs : &[(&str, &str)]) -> HashMap<String, String> {
+        pairs.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect()
+    }
+
+    #[test]
+    fn resolve_should_search_cdpath_for_plain_names() {
+        let root = env::temp_dir().join(format!("ash_dirs_cdpath_{}", std::process::id()));
+        std::fs::create_dir_all(root.join("project")).unwrap();
+        let env = environment(&[("CDPATH", &format!("/ash-no-such-dir:{}", root.display()))]);
+
+        assert_eq!( resolve("project", &env), (root.join("project"), true) );
+        assert_eq!( resolve("./project", &env), (PathBuf::from("./project"), false) );
+        assert_eq!( resolve("missing", &env), (PathBuf::from("missing"), false) );
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn tilde_should_abbreviate_home() {
+        let home = Some("/home/someone");
+
+        assert_eq!( tilde(Path::new("/home/someone/src"), home), "~/src" );
+        assert_eq!( tilde(Path::new("/home/someone"), home), "~" );
+        assert_eq!( tilde(Path::new("/home/someone2"), home), "/home/someone2" );
+        assert_eq!( tilde(Path::new("/home/someone"), None), "/home/someone" );
+        assert_eq!( tilde(Path::new("/home/someone"), Some("/")), "/home/someone" );
+        assert_eq!( tilde(Path::new("/home/someone/src"), Some("/home/someone/")), "~/src" );
+    }
+}
@@ -0,0 +1,121 @@
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+use super::value::Value;
+
+struct Frame {
+    vars : HashMap<String, Value>,
+    parent : Option<Env>,
+}
+
+// Frames are shared so that closures see bindings made after they were created,
+// which is what lets a named function call itself.
+#[derive(Clone)]
+pub struct Env {
+    frame : Rc<RefCell<Frame>>,
+}
+
+impl Env {
+    pub fn new() -> Env {
+        Env { frame: Rc::new(RefCell::new(Frame { vars: HashMap::new(), parent: None })) }
+    }
+
+    pub fn child(&self) -> Env {
+        Env { frame: Rc::new(RefCell::new(Frame { vars: HashMap::new(), parent: Some(self.clone()) })) }
+    }
+
+    pub fn get(&self, name : &str) -> Option<Value> {
+        let frame = self.frame.borrow();
+
+        match frame.vars.get(name) {
+            Some(value) => Some(value.clone()),
+            None => frame.parent.as_ref().and_then(|parent| parent.get(name)),
+        }
+    }
+
+    pub fn define(&self, name : &str, value : Value) {
+        self.frame.borrow_mut().vars.insert(name.to_string(), value);
+    }
+
+    // Every name visible from this env, sorted and without duplicates
+    pub fn names(&self) -> Vec<String> {
+        let mut names = vec![];
+        let mut env = Some(self.clone());
+
+        while let Some(e) = env {
+            let frame = e.frame.borrow();
+            names.extend(frame.vars.keys().cloned());
+            env = frame.parent.clone();
+        }
+
+        names.sort();
+        names.dedup();
+        names
+    }
+}
+
+impl Default for Env {
+    fn default() -> Env {
+        Env::new()
+    }
+}
+
+impl fmt::Debug for Env {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        // A closure's env can contain the closure itself
+        write!(f, "Env")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn get_should_find_binding_in_parent() {
+        let parent = Env::new();
+        parent.define("x", Value::Integer(1));
+
+        let child = parent.child();
+
+        assert_eq!( child.get("x"), Some(Value::Integer(1)) );
+    }
+
+    #[test]
+    fn define_in_child_should_shadow_parent() {
+        let parent = Env::new();
+        parent.define("x", Value::Integer(1));
+
+        let child = parent.child();
+        child.define("x", Value::Integer(2));
+
+        assert_eq!( child.get("x"), Some(Value::Integer(2)) );
+        assert_eq!( parent.get("x"), Some(Value::Integer(1)) );
+    }
+
+    #[test]
+    fn names_should_include_parents_once() {
+        let parent = Env::new();
+        parent.define("y", Value::Integer(1));
+        parent.define("x", Value::Integer(1));
+
+        let child = parent.child();
+        child.define("x", Value::Integer(2));
+        child.define("z", Value::Integer(2));
+
+        assert_eq!( child.names(), vec!["x", "y", "z"] );
+    }
+
+    #[test]
+    fn get_should_see_later_definitions_in_shared_frame() {
+        let env = Env::new();
+        let captured = env.clone();
+
+        env.define("x", Value::Integer(1));
+
+        assert_eq!( captured.get("x"), Some(Value::Integer(1)) );
+    }
+}
@@ -0,0 +1,103 @@
+
+use std::fmt;
+
+use crate::parsing::ast::Span;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RuntimeError {
+    UnboundVariable { name : String, span : Span },
+    TypeMismatch { expected : &'static str, found : &'static str, span : Span },
+    ArityMismatch { expected : usize, found : usize, span : Span },
+    UnknownArgument { name : String, span : Span },
+    DuplicateArgument { name : String, span : Span },
+    MissingArgument { name : String, span : Span },
+    NotCallable { found : &'static str, span : Span },
+    IndexOutOfBounds { index : i64, length : usize, span : Span },
+    MissingField { name : String, span : Span },
+    NoMatchingArm { span : Span },
+    StackOverflow { span : Span },
+    Builtin { message : String, span : Span },
+    CommandNotFound { name : String, span : Span },
+    CommandFailed { name : String, message : String, span : Span },
+    UnknownOption { name : String, span : Span },
+    RedirectFailed { path : String, message : String, span : Span },
+    AmbiguousRedirect { found : usize, span : Span },
+    EmptyCommand { span : Span },
+    UnsetEnvVar { name : String, span : Span },
+    NoGlobMatch { pattern : String, span : Span },
+    BackgroundFunction { span : Span },
+    // a builtin that changes the shell, used where a program would be run
+    ShellBuiltinStage { name : String, span : Span },
+    Interrupted { span : Span },
+    // not a failure, but 'exit' has to unwind everything running just as one does
+    Exit { code : i32, span : Span },
+    SourceFailed { path : String, message : String, span : Span },
+    // a command failed with errexit set
+    CommandStatus { status : i32, span : Span },
+}
+
+impl RuntimeError {
+    pub fn span(&self) -> Span {
+        match self {
+            RuntimeError::UnboundVariable { span, .. } => *span,
+            RuntimeError::TypeMismatch { span, .. } => *span,
+            RuntimeError::ArityMismatch { span, .. } => *span,
+            RuntimeError::UnknownArgument { span, .. } => *span,
+            RuntimeError::DuplicateArgument { span, .. } => *span,
+            RuntimeError::MissingArgument { span, .. } => *span,
+            RuntimeError::NotCallable { span, .. } => *span,
+            RuntimeError::IndexOutOfBounds { span, .. } => *span,
+            RuntimeError::MissingField { span, .. } => *span,
+            RuntimeError::NoMatchingArm { span } => *span,
+            RuntimeError::StackOverflow { span } => *span,
+            RuntimeError::Builtin { span, .. } => *span,
+            RuntimeError::CommandNotFound { span, .. } => *span,
+            RuntimeError::CommandFailed { span, .. } => *span,
+            RuntimeError::UnknownOption { span, .. } => *span,
+            RuntimeError::RedirectFailed { span, .. } => *span,
+            RuntimeError::AmbiguousRedirect { span, .. } => *span,
+            RuntimeError::EmptyCommand { span } => *span,
+            RuntimeError::UnsetEnvVar { span, .. } => *span,
+            RuntimeError::NoGlobMatch { span, .. } => *span,
+            RuntimeError::BackgroundFunction { span } => *span,
+            RuntimeError::ShellBuiltinStage { span, .. } => *span,
+            RuntimeError::Interrupted { span } => *span,
+            RuntimeError::Exit { span, .. } => *span,
+            RuntimeError::SourceFailed { span, .. } => *span,
+            RuntimeError::CommandStatus { span, .. } => *span,
+        }
+    }
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RuntimeError::UnboundVariable { name, .. } => write!(f, "unbound variable '{}'", name),
+            RuntimeError::TypeMismatch { expected, found, .. } => write!(f, "expected {} but found {}", expected, found),
+            RuntimeError::ArityMismatch { expected, found, .. } => write!(f, "expected {} arguments but found {}", expected, found),
+            RuntimeError::UnknownArgument { name, .. } => write!(f, "unknown argument '{}'", name),
+            RuntimeError::DuplicateArgument { name, .. } => write!(f, "argument '{}' given more than once", name),
+            RuntimeError::MissingArgument { name, .. } => write!(f, "missing argument '{}'", name),
+            RuntimeError::NotCallable { found, .. } => write!(f, "{} is not callable", found),
+            RuntimeError::IndexOutOfBounds { index, length, .. } => write!(f, "index {} is out of bounds for length {}", index, length),
+            RuntimeError::MissingField { name, .. } => write!(f, "record has no field '{}'", name),
+            RuntimeError::NoMatchingArm { .. } => write!(f, "no match arm matched the value"),
+            RuntimeError::StackOverflow { .. } => write!(f, "stack overflow"),
+            RuntimeError::Builtin { message, .. } => write!(f, "{}", message),
+            RuntimeError::CommandNotFound { name, .. } => write!(f, "command not found: {}", name),
+            RuntimeError::CommandFailed { name, message, .. } => write!(f, "{}: {}", name, message),
+            RuntimeError::UnknownOption { name, .. } => write!(f, "unknown shell option '{}'", name),
+            RuntimeError::RedirectFailed { path, message, .. } => write!(f, "cannot redirect to {}: {}", path, message),
+            RuntimeError::AmbiguousRedirect { found, .. } => write!(f, "redirect needs one path but found {}", found),
+            RuntimeError::EmptyCommand { .. } => write!(f, "command has no words once expanded"),
+            RuntimeError::UnsetEnvVar { name, .. } => write!(f, "environment variable '{}' is not set", name),
+            RuntimeError::NoGlobMatch { pattern, .. } => write!(f, "no matches for {}", pattern),
+            RuntimeError::BackgroundFunction { .. } => write!(f, "a pipeline with ash functions can't run in the background"),
+            RuntimeError::ShellBuiltinStage { name, .. } => write!(f, "{} runs in the shell itself, so can't be piped, redirected or run in the background", name),
+            RuntimeError::Interrupted { .. } => write!(f, "interrupted"),
+            RuntimeError::Exit { code, .. } => write!(f, "exit {}", code),
+            RuntimeError::SourceFailed { path, message, .. } => write!(f, "in {}: {}", path, message),
+            RuntimeError::CommandStatus { status, .. } => write!(f, "command failed with status {}", status),
+        }
+    }
+}
@@ -0,0 +1,381 @@
+use std::fs;
+use std::path::Path;
+
+// Patterns are written with '\' escaping anything that should match as itself,
+// which is how text from quotes and expressions is kept literal
+
+pub fn escape(text : &str) -> String {
+    let mut escaped = String::new();
+    for c in text.chars() {
+        if "*?[]\\".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+// A '[' only starts a class when there's a ']' to close it, so that words like
+// '[' and 'a[' are left alone
+pub fn has_magic(pattern : &str) -> bool {
+    let cs = pattern.chars().collect::<Vec<_>>();
+    let mut i = 0;
+
+    while i < cs.len() {
+        match cs[i] {
+            '\\' => i += 1,
+            '*' | '?' => return true,
+            '[' if class_end(&cs, i).is_some() => return true,
+            _ => { },
+        }
+        i += 1;
+    }
+
+    false
+}
+
+// Paths matching the pattern, sorted. '**' as a whole component matches any
+// number of directories, and names starting with '.' are only matched by a
+// component that starts with one too. A pattern ending in '/' only matches
+// directories, which keep the '/'
+pub fn expand(pattern : &str) -> Vec<String> {
+    let (root, rest) = match pattern.strip_prefix('/') {
+        Some(rest) => ("/".to_string(), rest),
+        None => (String::new(), pattern),
+    };
+    let dirs_only = rest.ends_with('/');
+
+    let mut components = rest.split('/').filter(|c| !c.is_empty()).collect::<Vec<_>>();
+    if components.last() == Some(&"**") {
+        components.push("*");
+    }
+
+    let mut found = vec![];
+    walk(&root, &components, &mut found);
+    if dirs_only {
+        found.retain(|path| Path::new(path).is_dir());
+        found.iter_mut().for_each(|path| path.push('/'));
+    }
+    found.sort();
+    found.dedup();
+    found
+}
+
+fn walk(prefix : &str, components : &[&str], found : &mut Vec<String>) {
+    let (component, rest) = match components.split_first() {
+        Some(split) => split,
+        None => {
+            found.push(prefix.to_string());
+            return;
+        },
+    };
+
+    if *component == "**" {
+        walk(prefix, rest, found);
+        // symlinks aren't followed here so that a link to a parent can't loop forever
+        for (name, file_type) in entries(prefix) {
+            if file_type.is_dir() && !name.starts_with('.') {
+                walk(&join(prefix, &name), components, found);
+            }
+        }
+    }
+    else if !has_magic(component) {
+        let path = join(prefix, &unescape(component));
+        if exists(&path, rest.is_empty()) {
+            walk(&path, rest, found);
+        }
+    }
+    else {
+        let pattern = component.chars().collect::<Vec<_>>();
+        for (name, _) in entries(prefix) {
+            if name.starts_with('.') && !component.starts_with('.') {
+                continue;
+            }
+            let path = join(prefix, &name);
+            if matches(&pattern, &name.chars().collect::<Vec<_>>()) && exists(&path, rest.is_empty()) {
+                walk(&path, rest, found);
+            }
+        }
+    }
+}
+
+fn entries(prefix : &str) -> Vec<(String, fs::FileType)> {
+    let dir = if prefix.is_empty() { "." } else { prefix };
+
+    match fs::read_dir(dir) {
+        Ok(entries) => entries.filter_map(|entry| {
+            let entry = entry.ok()?;
+            Some((entry.file_name().into_string().ok()?, entry.file_type().ok()?))
+        }).collect(),
+        Err(_) => vec![],
+    }
+}
+
+// anything can end a path, but only directories can have more after them
+fn exists(path : &str, last : bool) -> bool {
+    if last {
+        Path::new(path).symlink_metadata().is_ok()
+    }
+    else {
+        Path::new(path).is_dir()
+    }
+}
+
+fn join(prefix : &str, name : &str) -> String {
+    if prefix.is_empty() || prefix.ends_with('/') {
+        format!("{}{}", prefix, name)
+    }
+    else {
+        format!("{}/{}", prefix, name)
+    }
+}
+
+fn unescape(text : &str) -> String {
+    let mut s = String::new();
+    let mut cs = text.chars();
+    while let Some(c) = cs.next() {
+        match c {
+            '\\' => s.extend(cs.next()),
+            c => s.push(c),
+        }
+    }
+    s
+}
+
+// The index of the ']' closing a class that opens at start. A ']' straight
+// after the '[' (or after '[!') is part of the class
+fn class_end(pattern : &[char], start : usize) -> Option<usize> {
+    let mut i = start + 1;
+    if matches!(pattern.get(i), Some('!') | Some('^')) {
+        i += 1;
+    }
+    if pattern.get(i) == Some(&']') {
+        i += 1;
+    }
+
+    while i < pattern.len() {
+        match pattern[i] {
+            '\\' => i += 1,
+            ']' => return Some(i),
+            _ => { },
+        }
+        i += 1;
+    }
+
+    None
+}
+
+fn class_matches(class : &[char], c : char) -> bool {
+    let (negated, class) = match class.first() {
+        Some('!') | Some('^') => (true, &class[1..]),
+        _ => (false, class),
+    };
+
+    let mut found = false;
+    let mut i = 0;
+    while i < class.len() {
+        let (low, next) = match class[i] {
+            '\\' if i + 1 < class.len() => (class[i + 1], i + 2),
+            low => (low, i + 1),
+        };
+
+        if next + 1 < class.len() && class[next] == '-' {
+            let high = class[next + 1];
+            found |= low <= c && c <= high;
+            i = next + 2;
+        }
+        else {
+            found |= low == c;
+            i = next;
+        }
+    }
+
+    found != negated
+}
+
+enum Token<'a> {
+    Star,
+    Any,
+    Class(&'a [char]),
+    Char(char),
+}
+
+// The token starting at index i of the pattern, and the index after it
+fn token(pattern : &[char], i : usize) -> (Token<'_>, usize) {
+    match pattern[i] {
+        '*' => (Token::Star, i + 1),
+        '?' => (Token::Any, i + 1),
+        '[' => match class_end(pattern, i) {
+            Some(end) => (Token::Class(&pattern[i + 1..end]), end + 1),
+            None => (Token::Char('['), i + 1),
+        },
+        '\\' if i + 1 < pattern.len() => (Token::Char(pattern[i + 1]), i + 2),
+        c => (Token::Char(c), i + 1),
+    }
+}
+
+// When the pattern stops matching, only the last '*' seen needs to take one
+// more char and go again, since any earlier one could only take what that
+// one can. So this takes at most the length of the pattern times the name's
+pub fn matches(pattern : &[char], name : &[char]) -> bool {
+    let (mut p, mut n) = (0, 0);
+    // where the pattern goes on after the last '*', and where in the name that '*' stops
+    let mut retry = None;
+
+    while n < name.len() {
+        let matched = if p < pattern.len() {
+            match token(pattern, p) {
+                (Token::Star, next) => {
+                    retry = Some((next, n));
+                    p = next;
+                    continue;
+                },
+                (Token::Any, next) => Some(next),
+                (Token::Class(class), next) if class_matches(class, name[n]) => Some(next),
+                (Token::Char(c), next) if c == name[n] => Some(next),
+                _ => None,
+            }
+        }
+        else {
+            None
+        };
+
+        match (matched, retry) {
+            (Some(next), _) => {
+                p = next;
+                n += 1;
+            },
+            (None, Some((after, stop))) => {
+                retry = Some((after, stop + 1));
+                p = after;
+                n = stop + 1;
+            },
+            (None, None) => return false,
+        }
+    }
+
+    // all of the name has been matched, so only stars can be left
+    while p < pattern.len() {
+        match token(pattern, p) {
+            (Token::Star, next) => p = next,
+            _ => return false,
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn glob_matches(pattern : &str, name : &str) -> bool {
+        matches(&pattern.chars().collect::<Vec<_>>(), &name.chars().collect::<Vec<_>>())
+    }
+
+    fn temp_tree(name : &str, files : &[&str]) -> String {
+        let root = std::env::temp_dir().join(format!("ash_glob_test_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        for file in files {
+            let path = root.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        }
+        root.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn matches_should_handle_wildcards() {
+        assert!( glob_matches("*.rs", "main.rs") );
+        assert!( glob_matches("*", "") );
+        assert!( glob_matches("a?c", "abc") );
+        assert!( !glob_matches("a?c", "ac") );
+        assert!( !glob_matches("*.rs", "main.rs.bak") );
+    }
+
+    #[test]
+    fn matches_should_not_backtrack_exponentially() {
+        let name = "a".repeat(100);
+        let start = std::time::Instant::now();
+
+        assert!( !glob_matches("*a*a*a*a*a*a*a*a*a*b", &name) );
+        assert!( glob_matches("*a*a*a*a*a*a*a*a*a*", &name) );
+        assert!( start.elapsed() < std::time::Duration::from_secs(1) );
+    }
+
+    #[test]
+    fn matches_should_handle_classes() {
+        assert!( glob_matches("[abc].txt", "b.txt") );
+        assert!( glob_matches("file[0-9]", "file7") );
+        assert!( !glob_matches("file[!0-9]", "file7") );
+        assert!( glob_matches("[]a]", "]") );
+        assert!( !glob_matches("[ab]", "c") );
+    }
+
+    #[test]
+    fn matches_should_treat_escapes_literally() {
+        assert!( glob_matches(&escape("a*b"), "a*b") );
+        assert!( !glob_matches(&escape("a*b"), "axb") );
+    }
+
+    #[test]
+    fn has_magic_should_need_closed_class() {
+        assert!( has_magic("*.rs") );
+        assert!( has_magic("[ab]") );
+        assert!( !has_magic("[") );
+        assert!( !has_magic("a[") );
+        assert!( !has_magic(&escape("*")) );
+    }
+
+    #[test]
+    fn expand_should_sort_matches_and_skip_hidden_files() {
+        let root = temp_tree("sort", &["b.rs", "a.rs", "c.txt", ".d.rs"]);
+
+        let found = expand(&format!("{}/*.rs", root));
+
+        assert_eq!( found, vec![format!("{}/a.rs", root), format!("{}/b.rs", root)] );
+        assert_eq!( expand(&format!("{}/.*.rs", root)), vec![format!("{}/.d.rs", root)] );
+        let _ = fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn expand_should_match_directories_part_way() {
+        let root = temp_tree("dirs", &["src/a/x.rs", "src/b/y.rs", "lib/c/z.rs"]);
+
+        let found = expand(&format!("{}/src/*/*.rs", root));
+
+        assert_eq!( found, vec![format!("{}/src/a/x.rs", root), format!("{}/src/b/y.rs", root)] );
+        let _ = fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn expand_should_match_any_depth_with_double_star() {
+        let root = temp_tree("deep", &["a.rs", "src/b.rs", "src/x/c.rs", "src/x/d.txt"]);
+
+        let found = expand(&format!("{}/**/*.rs", root));
+
+        assert_eq!( found, vec![ format!("{}/a.rs", root)
+                               , format!("{}/src/b.rs", root)
+                               , format!("{}/src/x/c.rs", root)
+                               ] );
+        assert_eq!( expand(&format!("{}/src/**", root)).len(), 4 );
+        let _ = fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn expand_should_keep_trailing_slash_for_directories() {
+        let root = temp_tree("slash", &["src/a/x.rs", "src/b/y.rs", "src/c.rs"]);
+
+        let found = expand(&format!("{}/src/*/", root));
+
+        assert_eq!( found, vec![format!("{}/src/a/", root), format!("{}/src/b/", root)] );
+        let _ = fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn expand_should_find_nothing_for_missing_match() {
+        let root = temp_tree("none", &["a.txt"]);
+
+        assert!( expand(&format!("{}/*.rs", root)).is_empty() );
+        let _ = fs::remove_dir_all(root);
+    }
+}
@@ -0,0 +1,489 @@
+use std::io::{self, IsTerminal};
+use std::os::fd::{AsFd, AsRawFd, OwnedFd, RawFd};
+use std::os::raw::c_int;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use super::command::{Captured, Group, Progress, Running};
+
+pub type Pid = i32;
+
+extern "C" {
+    fn waitpid(pid : Pid, status : *mut c_int, options : c_int) -> Pid;
+    fn kill(pid : Pid, signal : c_int) -> c_int;
+    fn setpgid(pid : Pid, pgid : Pid) -> c_int;
+    fn getpgrp() -> Pid;
+    fn tcsetpgrp(fd : c_int, pgrp : Pid) -> c_int;
+    fn signal(signal : c_int, handler : usize) -> usize;
+}
+
+const WNOHANG : c_int = 1;
+const WUNTRACED : c_int = 2;
+
+const SIG_DFL : usize = 0;
+const SIG_IGN : usize = 1;
+
+// signal numbers as they are on Linux
+const SIGNALS : &[(&str, c_int)] = &[ ("HUP", 1), ("INT", 2), ("QUIT", 3), ("KILL", 9), ("USR1", 10)
+                                    , ("USR2", 12), ("TERM", 15), ("CONT", 18), ("STOP", 19), ("TSTP", 20)
+                                    , ("TTIN", 21), ("TTOU", 22)
+                                    ];
+const SIGINT : c_int = 2;
+const SIGQUIT : c_int = 3;
+const SIGTERM : c_int = 15;
+const SIGCONT : c_int = 18;
+const SIGTSTP : c_int = 20;
+const SIGTTIN : c_int = 21;
+const SIGTTOU : c_int = 22;
+
+// what an interactive shell ignores, and so what its children have to put back
+const JOB_SIGNALS : [c_int; 4] = [SIGQUIT, SIGTSTP, SIGTTIN, SIGTTOU];
+
+static INTERRUPTED : AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_interrupt(_signal : c_int) {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+// Whether ctrl-c has been pressed since this was last asked
+pub fn interrupted() -> bool {
+    INTERRUPTED.swap(false, Ordering::SeqCst)
+}
+
+pub enum Wait {
+    Exited(i32),
+    Stopped,
+    Running,
+}
+
+// Stopped processes are reported too, so that ctrl-z hands control back to the shell
+pub fn wait_pid(pid : Pid, block : bool) -> Wait {
+    let options = if block { WUNTRACED } else { WUNTRACED | WNOHANG };
+    let mut status : c_int = 0;
+
+    loop {
+        match unsafe { waitpid(pid, &mut status, options) } {
+            0 => return Wait::Running,
+            -1 if io::Error::last_os_error().kind() == io::ErrorKind::Interrupted => continue,
+            // someone else already reaped it, so there's no status to give
+            -1 => return Wait::Exited(1),
+            _ => { },
+        }
+
+        // the W* macros from sys/wait.h
+        return if status & 0xff == 0x7f {
+            Wait::Stopped
+        }
+        else if status & 0x7f == 0 {
+            Wait::Exited((status >> 8) & 0xff)
+        }
+        else {
+            Wait::Exited(128 + (status & 0x7f))
+        };
+    }
+}
+
+// Run in a child between fork and exec, so it only makes async signal safe calls.
+// A pgid of 0 makes the child the leader of a new group
+pub fn enter_group(pgid : Pid, terminal : Option<RawFd>) {
+    unsafe {
+        setpgid(0, pgid);
+        if let Some(fd) = terminal {
+            tcsetpgrp(fd, getpgrp());
+        }
+        signal(SIGINT, SIG_DFL);
+        for sig in JOB_SIGNALS {
+            signal(sig, SIG_DFL);
+        }
+    }
+}
+
+// The parent sets the group too, since it can't know whether the child got there first
+pub fn join_group(pid : Pid, pgid : Pid) {
+    unsafe { setpgid(pid, pgid); }
+}
+
+fn signal_group(pgid : Pid, sig : c_int) -> io::Result<()> {
+    if unsafe { kill(-pgid, sig) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+// The terminal an interactive shell shares with its foreground job
+struct Terminal {
+    fd : OwnedFd,
+    shell_group : Pid,
+}
+
+impl Terminal {
+    fn claim() -> Option<Terminal> {
+        let stdin = io::stdin();
+        if !stdin.is_terminal() {
+            return None;
+        }
+        let fd = stdin.as_fd().try_clone_to_owned().ok()?;
+
+        unsafe {
+            signal(SIGINT, on_interrupt as extern "C" fn(c_int) as usize);
+            for sig in JOB_SIGNALS {
+                signal(sig, SIG_IGN);
+            }
+            // fails harmlessly when the shell already leads its group
+            setpgid(0, 0);
+            tcsetpgrp(fd.as_raw_fd(), getpgrp());
+        }
+
+        Some(Terminal { fd, shell_group: unsafe { getpgrp() } })
+    }
+
+    fn give(&self, pgid : Pid) {
+        unsafe { tcsetpgrp(self.fd.as_raw_fd(), pgid); }
+    }
+
+    fn take_back(&self) {
+        unsafe { tcsetpgrp(self.fd.as_raw_fd(), self.shell_group); }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum State {
+    Running,
+    Stopped,
+}
+
+struct Job {
+    id : usize,
+    text : String,
+    state : State,
+    running : Running,
+}
+
+// Pipelines that were started in the background or stopped with ctrl-z
+pub struct Jobs {
+    jobs : Vec<Job>,
+    terminal : Option<Terminal>,
+}
+
+impl Jobs {
+    pub fn new() -> Jobs {
+        Jobs { jobs: vec![], terminal: None }
+    }
+
+    // Takes the terminal so that foreground jobs can be given it in turn, and
+    // so that ctrl-c and ctrl-z reach them rather than the shell
+    pub fn enable_control(&mut self) {
+        if self.terminal.is_none() {
+            self.terminal = Terminal::claim();
+        }
+    }
+
+    pub fn group(&self, background : bool) -> Group {
+        match &self.terminal {
+            _ if background => Group::Own,
+            Some(terminal) => Group::Foreground(terminal.fd.as_raw_fd()),
+            None => Group::Shell,
+        }
+    }
+
+    // Waits for a pipeline started in the foreground. If it's stopped it becomes a job
+    pub fn foreground(&mut self, running : Running, text : String) -> Captured {
+        self.wait_foreground(None, running, text)
+    }
+
+    pub fn background(&mut self, running : Running, text : String) -> usize {
+        let id = self.next_id();
+        if self.terminal.is_some() {
+            eprintln!("[{}] {}", id, running.group().unwrap_or(0));
+        }
+        self.jobs.push(Job { id, text, state: State::Running, running });
+        id
+    }
+
+    fn wait_foreground(&mut self, id : Option<usize>, mut running : Running, text : String) -> Captured {
+        let progress = running.wait(true);
+
+        if let Some(terminal) = &self.terminal {
+            terminal.take_back();
+        }
+
+        match progress {
+            Progress::Stopped => {
+                let id = id.unwrap_or_else(|| self.next_id());
+                eprintln!("\n[{}]+  Stopped  {}", id, text);
+                self.jobs.push(Job { id, text, state: State::Stopped, running });
+                self.jobs.sort_by_key(|job| job.id);
+                Captured { status: 128 + SIGTSTP, ..Captured::default() }
+            },
+            Progress::Finished | Progress::Running => running.finish(),
+        }
+    }
+
+    fn next_id(&self) -> usize {
+        self.jobs.iter().map(|job| job.id).max().unwrap_or(0) + 1
+    }
+
+    // Messages for jobs that have finished since last asked, which are then forgotten
+    pub fn reap(&mut self) -> Vec<String> {
+        let mut messages = vec![];
+        let mut i = 0;
+
+        while i < self.jobs.len() {
+            match self.jobs[i].running.wait(false) {
+                Progress::Finished => {
+                    let job = self.jobs.remove(i);
+                    let status = job.running.finish().status;
+                    messages.push(done_message(job.id, status, &job.text));
+                },
+                Progress::Stopped => {
+                    self.jobs[i].state = State::Stopped;
+                    i += 1;
+                },
+                Progress::Running => i += 1,
+            }
+        }
+
+        messages
+    }
+
+    pub fn list(&mut self) -> Vec<String> {
+        let mut lines = self.reap();
+        let current = self.jobs.last().map(|job| job.id);
+
+        for job in &self.jobs {
+            let marker = if Some(job.id) == current { '+' } else { ' ' };
+            let state = match job.state {
+                State::Running => "Running",
+                State::Stopped => "Stopped",
+            };
+            lines.push(format!("[{}]{}  {}  {}", job.id, marker, state, job.text));
+        }
+
+        lines
+    }
+
+    // '%n' is job n, '%%', '%+' or nothing the latest job, '%-' the one before,
+    // and a number the job with that process group
+    fn find(&self, spec : Option<&str>) -> Result<usize, String> {
+        let index = match spec {
+            None | Some("%%") | Some("%+") => self.jobs.len().checked_sub(1),
+            Some("%-") => self.jobs.len().checked_sub(2),
+            Some(spec) => match spec.strip_prefix('%') {
+                Some(id) => id.parse::<usize>().ok().and_then(|id| self.jobs.iter().position(|job| job.id == id)),
+                None => spec.parse::<Pid>().ok().and_then(|pid| self.jobs.iter().position(|job| job.running.group() == Some(pid))),
+            },
+        };
+
+        index.ok_or_else(|| format!("no such job: {}", spec.unwrap_or("%%")))
+    }
+
+    pub fn fg(&mut self, spec : Option<&str>) -> Result<i32, String> {
+        let index = self.find(spec)?;
+        let Job { id, text, running, .. } = self.jobs.remove(index);
+
+        println!("{}", text);
+        if let Some(pgid) = running.group() {
+            if let Some(terminal) = &self.terminal {
+                terminal.give(pgid);
+            }
+            let _ = signal_group(pgid, SIGCONT);
+        }
+
+        Ok(self.wait_foreground(Some(id), running, text).status)
+    }
+
+    pub fn bg(&mut self, spec : Option<&str>) -> Result<(), String> {
+        let index = self.find(spec)?;
+        let job = &mut self.jobs[index];
+
+        if let Some(pgid) = job.running.group() {
+            signal_group(pgid, SIGCONT).map_err(|e| e.to_string())?;
+        }
+        job.state = State::Running;
+        println!("[{}]  {} &", job.id, job.text);
+
+        Ok(())
+    }
+
+    // Waits for the given jobs, or all of them, and gives the last one's status.
+    // A job that stops while being waited for is left as a stopped job
+    pub fn wait(&mut self, specs : &[String]) -> Result<i32, String> {
+        let mut ids = vec![];
+        for spec in specs {
+            ids.push(self.jobs[self.find(Some(spec))?].id);
+        }
+        if specs.is_empty() {
+            ids = self.jobs.iter().map(|job| job.id).collect();
+        }
+
+        let mut status = 0;
+        for id in ids {
+            let index = match self.jobs.iter().position(|job| job.id == id) {
+                Some(index) => index,
+                None => continue,
+            };
+
+            match self.jobs[index].running.wait(true) {
+                Progress::Stopped => {
+                    self.jobs[index].state = State::Stopped;
+                    status = 128 + SIGTSTP;
+                },
+                Progress::Finished | Progress::Running => status = self.jobs.remove(index).running.finish().status,
+            }
+        }
+
+        Ok(status)
+    }
+
+    // 'kill [-SIGNAL | -s SIGNAL] target...' where targets are job specs or pids
+    pub fn kill(&mut self, args : &[String]) -> Result<(), String> {
+        let (sig, targets) = match args {
+            [s, name, targets @ ..] if s == "-s" => (signal_number(name)?, targets),
+            [flag, targets @ ..] if flag.starts_with('-') && flag.len() > 1 => (signal_number(&flag[1..])?, targets),
+            targets => (SIGTERM, targets),
+        };
+
+        if targets.is_empty() {
+            return Err("kill needs a job or process to signal".to_string());
+        }
+
+        for target in targets {
+            if target.starts_with('%') {
+                let index = self.find(Some(target))?;
+                let job = &mut self.jobs[index];
+                let pgid = job.running.group().ok_or_else(|| format!("{} has no process group", target))?;
+                signal_group(pgid, sig).map_err(|e| format!("{}: {}", target, e))?;
+                // a stopped job has to be woken to notice most signals
+                if job.state == State::Stopped && sig != SIGCONT {
+                    let _ = signal_group(pgid, SIGCONT);
+                    job.state = State::Running;
+                }
+            }
+            else {
+                let pid = target.parse::<Pid>().map_err(|_| format!("not a job or process: {}", target))?;
+                if unsafe { kill(pid, sig) } != 0 {
+                    return Err(format!("{}: {}", target, io::Error::last_os_error()));
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for Jobs {
+    fn default() -> Jobs {
+        Jobs::new()
+    }
+}
+
+fn done_message(id : usize, status : i32, text : &str) -> String {
+    if status == 0 {
+        format!("[{}]   Done  {}", id, text)
+    }
+    else {
+        format!("[{}]   Exit {}  {}", id, status, text)
+    }
+}
+
+// Names can be given with or without their SIG prefix, or as numbers
+fn signal_number(name : &str) -> Result<c_int, String> {
+    if let Ok(number) = name.parse::<c_int>() {
+        return Ok(number);
+    }
+
+    let upper = name.to_uppercase();
+    let short = upper.strip_prefix("SIG").unwrap_or(&upper);
+
+    SIGNALS.iter()
+           .find(|(signal, _)| *signal == short)
+           .map(|(_, number)| *number)
+           .ok_or_else(|| format!("unknown signal: {}", name))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::HashMap;
+    use crate::parsing::ast::Span;
+    use super::super::command::{self, Capture, Run, Settings, Stage};
+
+    fn start(words : &[&str]) -> Running {
+        let stage = Stage { run: Run::Program(words.iter().map(|w| w.to_string()).collect()), redirects: vec![], span: Span::default() };
+        let environment = std::env::vars().collect::<HashMap<_, _>>();
+        let settings = Settings { pipefail: false, capture: Capture::Nothing, group: Group::Own, environment: &environment };
+
+        let (running, started) = command::start(vec![stage], settings, |_, _, _| panic!("no functions in this pipeline"));
+        started.unwrap();
+        running
+    }
+
+    #[test]
+    fn wait_should_give_status_of_background_job() {
+        let mut jobs = Jobs::new();
+        jobs.background(start(&["sh", "-c", "exit 3"]), "sh".to_string());
+
+        assert_eq!( jobs.wait(&[]), Ok(3) );
+        assert!( jobs.list().is_empty() );
+    }
+
+    #[test]
+    fn background_should_number_jobs_from_one() {
+        let mut jobs = Jobs::new();
+
+        let first = jobs.background(start(&["sleep", "5"]), "sleep 5".to_string());
+        let second = jobs.background(start(&["sleep", "5"]), "sleep 5".to_string());
+
+        assert_eq!( (first, second), (1, 2) );
+        assert_eq!( jobs.list(), vec!["[1]   Running  sleep 5", "[2]+  Running  sleep 5"] );
+        jobs.kill(&["-KILL".to_string(), "%1".to_string(), "%2".to_string()]).unwrap();
+        assert_eq!( jobs.wait(&[]), Ok(128 + 9) );
+    }
+
+    #[test]
+    fn kill_should_stop_and_bg_should_continue_job() {
+        let mut jobs = Jobs::new();
+        jobs.background(start(&["sleep", "5"]), "sleep 5".to_string());
+
+        jobs.kill(&["-s".to_string(), "STOP".to_string(), "%%".to_string()]).unwrap();
+        assert_eq!( jobs.wait(&["%1".to_string()]), Ok(128 + SIGTSTP) );
+        assert_eq!( jobs.list(), vec!["[1]+  Stopped  sleep 5"] );
+
+        jobs.bg(None).unwrap();
+        jobs.kill(&["%1".to_string()]).unwrap();
+        assert_eq!( jobs.wait(&[]), Ok(128 + SIGTERM) );
+    }
+
+    #[test]
+    fn reap_should_report_finished_jobs_once() {
+        let mut jobs = Jobs::new();
+        jobs.background(start(&["true"]), "true".to_string());
+
+        let mut messages = jobs.reap();
+        for _ in 0..50 {
+            if !messages.is_empty() {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(20));
+            messages = jobs.reap();
+        }
+
+        assert_eq!( messages, vec!["[1]   Done  true"] );
+        assert!( jobs.reap().is_empty() );
+    }
+
+    #[test]
+    fn find_should_report_missing_job() {
+        let jobs = Jobs::new();
+
+        assert_eq!( jobs.find(Some("%3")), Err("no such job: %3".to_string()) );
+        assert_eq!( jobs.find(None), Err("no such job: %%".to_string()) );
+    }
+
+    #[test]
+    fn signal_number_should_accept_names_and_numbers() {
+        assert_eq!( signal_number("9"), Ok(9) );
+        assert_eq!( signal_number("term"), Ok(SIGTERM) );
+        assert_eq!( signal_number("SIGINT"), Ok(SIGINT) );
+        assert!( signal_number("BLAH").is_err() );
+    }
+}
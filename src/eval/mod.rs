@@ -0,0 +1,1426 @@
+
+mod builtins;
+mod command;
+mod dirs;
+mod env;
+mod error;
+mod glob;
+mod jobs;
+mod prompt;
+mod stack;
+mod value;
+
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::diagnostic;
+use crate::parsing::ast::{self, Ast, Argument, Pattern, Segment, Span, Word, WordPart};
+use crate::parsing::grammar::parse;
+use command::{Capture, Captured, Redirect, Run, Settings, Stage};
+use dirs::Dirs;
+use jobs::Jobs;
+
+pub use builtins::BUILTINS;
+pub use env::Env;
+pub use error::RuntimeError;
+pub use value::{Closure, Context, Value};
+#[cfg(test)]
+pub use dirs::working_dir_turn;
+
+// The evaluator recurses on the native stack, so programs are run on a thread
+// with this much of it. How deep each ash call goes in Rust depends on the
+// build and on how its body nests, so rather than counting calls, recursion is
+// stopped by how close it has come to the end of the thread's stack
+pub const STACK_SIZE : usize = 256 * 1024 * 1024;
+
+// Where the stack's end can't be found, calls are counted instead, few enough
+// for the main thread of a debug build
+const MAX_CALL_DEPTH : usize = 1_000;
+
+pub struct Evaluator {
+    globals : Env,
+    depth : usize,
+    options : Options,
+    // what commands are run with, starting from the shell's own environment
+    environment : HashMap<String, String>,
+    // for each with_env block being run, innermost last, what the variables it
+    // has set or exported were before, for putting back when it ends
+    shadowed : Vec<HashMap<String, Option<String>>>,
+    jobs : Jobs,
+    dirs : Dirs,
+    // the status of the last command run, for last_status()
+    last_status : i32,
+    // how many conditions are being evaluated, where failing commands don't trip errexit
+    testing : usize,
+}
+
+#[derive(Debug)]
+struct Options {
+    // a pipeline fails if any of its stages do, rather than only the last
+    pipefail : bool,
+    // a glob that matches nothing is an error, rather than being passed on as it is
+    failglob : bool,
+    // a command that fails outside of a condition is an error
+    errexit : bool,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options { pipefail: false, failglob: true, errexit: false }
+    }
+}
+
+impl Evaluator {
+    pub fn new() -> Evaluator {
+        let globals = Env::new();
+
+        for builtin in BUILTINS {
+            globals.define(builtin.name, Value::Builtin(*builtin));
+        }
+
+        let environment : HashMap<String, String> = std::env::vars_os()
+            .filter_map(|(name, value)| Some((name.into_string().ok()?, value.into_string().ok()?)))
+            .collect();
+
+        Evaluator { globals, depth: 0, options: Options::default(), environment, shadowed: vec![], jobs: Jobs::new(), dirs: Dirs::new(), last_status: 0, testing: 0 }
+    }
+
+    pub fn globals(&self) -> &Env {
+        &self.globals
+    }
+
+    // For an interactive shell, which hands the terminal to each foreground job
+    pub fn enable_job_control(&mut self) {
+        self.jobs.enable_control();
+    }
+
+    // What last_status() gives, which is the status of the last command run
+    pub fn last_status(&self) -> i32 {
+        self.last_status
+    }
+
+    pub fn set_last_status(&mut self, status : i32) {
+        self.last_status = status;
+    }
+
+    // Messages for background jobs that have finished since this was last called
+    pub fn reap_jobs(&mut self) -> Vec<String> {
+        self.jobs.reap()
+    }
+
+    // Top level statements are evaluated directly in the globals so that
+    // successive calls can see each other's bindings
+    pub fn eval(&mut self, program : &[Ast]) -> Result<Value, RuntimeError> {
+        // a ctrl-c from before this program started isn't meant for it
+        jobs::interrupted();
+        let env = self.globals.clone();
+        let result = self.statements(program, &env);
+
+        // to a prompt, a program that stopped with an error failed like a command would
+        if result.is_err() {
+            self.last_status = 1;
+        }
+        result
+    }
+
+    fn statements(&mut self, statements : &[Ast], env : &Env) -> Result<Value, RuntimeError> {
+        let mut last = Value::unit();
+
+        for statement in statements {
+            last = self.expr(statement, env)?;
+        }
+
+        Ok(last)
+    }
+
+    // Each let in a block binds in a frame of its own, after its value is worked
+    // out, so closures made before it keep the binding they saw, as on the vm.
+    // Top level lets redefine in place, so that later entries can replace them
+    fn block(&mut self, statements : &[Ast], env : &Env) -> Result<Value, RuntimeError> {
+        let mut env = env.child();
+        let mut last = Value::unit();
+
+        for statement in statements {
+            last = match statement {
+                Ast::Let { name, value, .. } => {
+                    let value = self.expr(value, &env)?;
+                    env = env.child();
+                    env.define(name, value);
+                    Value::unit()
+                },
+                other => self.expr(other, &env)?,
+            };
+        }
+
+        Ok(last)
+    }
+
+    fn expr(&mut self, ast : &Ast, env : &Env) -> Result<Value, RuntimeError> {
+        match ast {
+            Ast::Integer(i) => Ok(Value::Integer(*i)),
+            Ast::Bool(b) => Ok(Value::Bool(*b)),
+            Ast::String(s) => Ok(Value::String(s.clone())),
+            Ast::Char(c) => Ok(Value::Char(*c)),
+            Ast::Interpolated(segments) => {
+                let mut s = String::new();
+                for segment in segments {
+                    match segment {
+                        Segment::Literal(text) => s.push_str(text),
+                        Segment::Expr(e) => s.push_str(&self.expr(e, env)?.to_string()),
+                    }
+                }
+                Ok(Value::String(s))
+            },
+            Ast::Variable(name, span) =>
+                env.get(name).ok_or_else(|| RuntimeError::UnboundVariable { name: name.clone(), span: *span }),
+            Ast::Let { name, value, .. } => {
+                let value = self.expr(value, env)?;
+                env.define(name, value);
+                Ok(Value::unit())
+            },
+            Ast::Block(statements) => self.block(statements, env),
+            Ast::If { condition, then, otherwise, span } => {
+                let condition = self.test(condition, env)?;
+                if truthy(&condition, *span)? {
+                    self.expr(then, env)
+                }
+                else {
+                    match otherwise {
+                        Some(otherwise) => self.expr(otherwise, env),
+                        None => Ok(Value::unit()),
+                    }
+                }
+            },
+            Ast::Match { subject, arms, span } => {
+                let subject = self.expr(subject, env)?;
+
+                for arm in arms {
+                    let arm_env = env.child();
+
+                    if !pattern_match(&arm.pattern, &subject, &arm_env) {
+                        continue;
+                    }
+
+                    let guarded = match &arm.guard {
+                        None => true,
+                        Some(guard) => truthy(&self.test(guard, &arm_env)?, *span)?,
+                    };
+
+                    if guarded {
+                        return self.expr(&arm.body, &arm_env);
+                    }
+                }
+
+                Err(RuntimeError::NoMatchingArm { span: *span })
+            },
+            Ast::Function { name, params, body, .. } => {
+                let closure = Closure { name: Some(name.clone())
+                                      , params: params.clone()
+                                      , body: (**body).clone()
+                                      , env: env.clone()
+                                      };
+                env.define(name, Value::Closure(Rc::new(closure)));
+                Ok(Value::unit())
+            },
+            Ast::Lambda { params, body } => {
+                let closure = Closure { name: None
+                                      , params: params.clone()
+                                      , body: (**body).clone()
+                                      , env: env.clone()
+                                      };
+                Ok(Value::Closure(Rc::new(closure)))
+            },
+            Ast::Call { function, args, span } => {
+                let function = self.expr(function, env)?;
+
+                let mut values = vec![];
+                for arg in args {
+                    match arg {
+                        Argument::Positional(e) => values.push((None, self.expr(e, env)?)),
+                        Argument::Named(name, e) => values.push((Some(name.clone()), self.expr(e, env)?)),
+                    }
+                }
+
+                self.call(function, values, *span)
+            },
+            Ast::List(items) => Ok(Value::List(self.items(items, env)?)),
+            Ast::Tuple(items) => Ok(Value::Tuple(self.items(items, env)?)),
+            Ast::Record(fields) => {
+                let mut values = vec![];
+                for (name, e) in fields {
+                    values.push((name.clone(), self.expr(e, env)?));
+                }
+                Ok(Value::Record(values))
+            },
+            Ast::Index { target, index, span } => {
+                let target = self.expr(target, env)?;
+                let index = self.expr(index, env)?;
+                index_value(target, index, *span)
+            },
+            Ast::Field { target, name, span } => {
+                match self.expr(target, env)? {
+                    Value::Record(fields) => field_value(fields, name, *span),
+                    other => Err(RuntimeError::TypeMismatch { expected: "record", found: other.type_name(), span: *span }),
+                }
+            },
+            Ast::Command { stages, background, span } => {
+                let stages = self.expand_stages(stages, env)?;
+
+                let status = match &stages[..] {
+                    [Stage { run: Run::Program(words), redirects, .. }] if !*background && redirects.is_empty() && is_shell_builtin(&words[0]) => {
+                        match self.shell_builtin(words, env, *span)? {
+                            Value::Status(status) => {
+                                self.last_status = status;
+                                status
+                            },
+                            // what a sourced file ends with
+                            other => return Ok(other),
+                        }
+                    },
+                    _ => {
+                        for stage in &stages {
+                            if let Run::Program(words) = &stage.run {
+                                if is_shell_builtin(&words[0]) {
+                                    return Err(RuntimeError::ShellBuiltinStage { name: words[0].clone(), span: stage.span });
+                                }
+                            }
+                        }
+                        self.pipeline(stages, Capture::Nothing, *background)?.status
+                    },
+                };
+
+                if status != 0 && self.options.errexit && self.testing == 0 {
+                    return Err(RuntimeError::CommandStatus { status, span: *span });
+                }
+                Ok(Value::Status(status))
+            },
+            Ast::And { left, right, span } => {
+                let left = self.test(left, env)?;
+                if truthy(&left, *span)? { self.expr(right, env) } else { Ok(left) }
+            },
+            Ast::Or { left, right, span } => {
+                let left = self.test(left, env)?;
+                if truthy(&left, *span)? { Ok(left) } else { self.expr(right, env) }
+            },
+            Ast::Try { body, name, handler } => match self.expr(body, env) {
+                // these stop everything, so aren't for a script to handle
+                Err(error @ RuntimeError::Exit { .. }) | Err(error @ RuntimeError::Interrupted { .. }) => Err(error),
+                Err(error) => {
+                    let handler_env = env.child();
+                    handler_env.define(name, error_value(&error));
+                    self.expr(handler, &handler_env)
+                },
+                ok => ok,
+            },
+            Ast::EnvVar(name, span) => match self.environment.get(name) {
+                Some(value) => Ok(Value::String(value.clone())),
+                None => Err(RuntimeError::UnsetEnvVar { name: name.clone(), span: *span }),
+            },
+            Ast::Export { name, value } => {
+                let value = self.expr(value, env)?;
+                self.export(name, value.to_string());
+                Ok(Value::unit())
+            },
+            Ast::WithEnv { vars, body } => {
+                self.shadowed.push(HashMap::new());
+
+                let result = self.with_env(vars, body, env);
+
+                // anything else changed inside, like $PWD after a cd, stays changed
+                for (name, before) in self.shadowed.pop().unwrap_or_default() {
+                    self.set_variable(&name, before);
+                }
+                result
+            },
+            Ast::Substitution { stages, structured, .. } => {
+                let stages = self.expand_stages(stages, env)?;
+
+                if *structured {
+                    let captured = self.pipeline(stages, Capture::All, false)?;
+                    Ok(Value::Record(vec![ ("stdout".to_string(), Value::String(trim_newlines(captured.stdout)))
+                                         , ("stderr".to_string(), Value::String(trim_newlines(captured.stderr)))
+                                         , ("status".to_string(), Value::Status(captured.status))
+                                         ]))
+                }
+                else {
+                    let captured = self.pipeline(stages, Capture::Stdout, false)?;
+                    Ok(Value::String(trim_newlines(captured.stdout)))
+                }
+            },
+        }
+    }
+
+    // Evaluates a condition, which failing commands don't count as errors in
+    fn test(&mut self, condition : &Ast, env : &Env) -> Result<Value, RuntimeError> {
+        self.testing += 1;
+        let result = self.expr(condition, env);
+        self.testing -= 1;
+        result
+    }
+
+    fn with_env(&mut self, vars : &[(String, Ast)], body : &Ast, env : &Env) -> Result<Value, RuntimeError> {
+        for (name, value) in vars {
+            let value = self.expr(value, env)?;
+            self.export(name, value.to_string());
+        }
+
+        self.expr(body, env)
+    }
+
+    fn export(&mut self, name : &str, value : String) {
+        if let Some(shadowed) = self.shadowed.last_mut() {
+            if !shadowed.contains_key(name) {
+                shadowed.insert(name.to_string(), self.environment.get(name).cloned());
+            }
+        }
+        self.set_variable(name, Some(value));
+    }
+
+    fn set_variable(&mut self, name : &str, value : Option<String>) {
+        match value {
+            Some(value) => self.environment.insert(name.to_string(), value),
+            None => self.environment.remove(name),
+        };
+    }
+
+    fn expand_stages(&mut self, stages : &[ast::Stage], env : &Env) -> Result<Vec<Stage>, RuntimeError> {
+        let mut expanded = vec![];
+
+        for stage in stages {
+            let mut words = vec![];
+            for word in &stage.words {
+                self.expand_word(word, env, stage.span, &mut words)?;
+            }
+
+            let mut redirects = vec![];
+            for redirect in &stage.redirects {
+                redirects.push(self.expand_redirect(redirect, env, stage.span)?);
+            }
+
+            if words.is_empty() {
+                return Err(RuntimeError::EmptyCommand { span: stage.span });
+            }
+
+            // a stage named after an ash function calls it rather than a program
+            let run = match env.get(&words[0]) {
+                Some(function @ Value::Closure(_)) => Run::Function(function, words.split_off(1)),
+                _ => Run::Program(words),
+            };
+
+            expanded.push(Stage { run, redirects, span: stage.span });
+        }
+
+        Ok(expanded)
+    }
+
+    // A background pipeline becomes a job and gives a status of 0 straight away.
+    // Function stages run on the shell itself, so they can't be left running
+    fn pipeline(&mut self, stages : Vec<Stage>, capture : Capture, background : bool) -> Result<Captured, RuntimeError> {
+        if background {
+            if let Some(stage) = stages.iter().find(|stage| matches!(stage.run, Run::Function(..))) {
+                return Err(RuntimeError::BackgroundFunction { span: stage.span });
+            }
+        }
+
+        let text = stages.iter().map(|stage| stage_text(&stage.run)).collect::<Vec<_>>().join(" | ");
+        let environment = self.environment.clone();
+        let settings = Settings { pipefail: self.options.pipefail
+                                , capture
+                                , group: self.jobs.group(background)
+                                , environment: &environment
+                                };
+
+        let (running, started) = command::start(stages, settings, |function, args, span|
+            self.call(function, args.into_iter().map(|arg| (None, arg)).collect(), span));
+
+        if background && started.is_ok() {
+            self.jobs.background(running, text);
+            return Ok(Captured::default());
+        }
+
+        let captured = self.jobs.foreground(running, text);
+        started?;
+        self.last_status = captured.status;
+        Ok(captured)
+    }
+
+    // A word that is only an unquoted list expression becomes one argument per
+    // item, anything else is a single argument unless its bare text makes it a
+    // glob. Text from quotes and expressions is never taken as a glob
+    fn expand_word(&mut self, word : &Word, env : &Env, span : Span, args : &mut Vec<String>) -> Result<(), RuntimeError> {
+        if let [WordPart::Expr(e)] = &word[..] {
+            match self.expr(e, env)? {
+                Value::List(items) => args.extend(items.iter().map(|item| item.to_string())),
+                other => args.push(other.to_string()),
+            }
+            return Ok(());
+        }
+
+        let mut arg = String::new();
+        let mut pattern = String::new();
+        let mut magic = false;
+
+        for (i, part) in word.iter().enumerate() {
+            let (text, bare) = match part {
+                WordPart::Bare(text) if i == 0 && text.starts_with('~') => {
+                    let (home, rest) = self.expand_tilde(text);
+                    arg.push_str(&home);
+                    pattern.push_str(&glob::escape(&home));
+                    (rest.to_string(), true)
+                },
+                WordPart::Bare(text) => (text.clone(), true),
+                WordPart::Quoted(text) => (text.clone(), false),
+                WordPart::Expr(e) | WordPart::QuotedExpr(e) => (self.expr(e, env)?.to_string(), false),
+            };
+
+            arg.push_str(&text);
+            if bare {
+                magic |= glob::has_magic(&text);
+                pattern.push_str(&text);
+            }
+            else {
+                pattern.push_str(&glob::escape(&text));
+            }
+        }
+
+        if !magic {
+            args.push(arg);
+            return Ok(());
+        }
+
+        let paths = glob::expand(&pattern);
+        if !paths.is_empty() {
+            args.extend(paths);
+        }
+        else if self.options.failglob {
+            return Err(RuntimeError::NoGlobMatch { pattern: arg, span });
+        }
+        else {
+            args.push(arg);
+        }
+
+        Ok(())
+    }
+
+    // '~' and '~/...' start from $HOME. Other users' homes aren't looked up
+    fn expand_tilde<'a>(&self, text : &'a str) -> (String, &'a str) {
+        let rest = &text[1..];
+        match self.environment.get("HOME") {
+            Some(home) if rest.is_empty() || rest.starts_with('/') => (home.clone(), rest),
+            _ => (String::new(), text),
+        }
+    }
+
+    fn expand_redirect(&mut self, redirect : &ast::Redirect, env : &Env, span : Span) -> Result<Redirect, RuntimeError> {
+        let mut target = |word : &Word| -> Result<String, RuntimeError> {
+            let mut paths = vec![];
+            self.expand_word(word, env, span, &mut paths)?;
+            if paths.len() == 1 {
+                Ok(paths.remove(0))
+            }
+            else {
+                Err(RuntimeError::AmbiguousRedirect { found: paths.len(), span })
+            }
+        };
+
+        Ok(match redirect {
+            ast::Redirect::Input(word) => Redirect::Input(target(word)?),
+            ast::Redirect::Output(word) => Redirect::Output(target(word)?),
+            ast::Redirect::Append(word) => Redirect::Append(target(word)?),
+            ast::Redirect::Error(word) => Redirect::Error(target(word)?),
+            ast::Redirect::ErrorToOutput => Redirect::ErrorToOutput,
+            ast::Redirect::Both(word) => Redirect::Both(target(word)?),
+        })
+    }
+
+    // Commands that change the shell itself, so can't be programs. Like programs,
+    // they report failing to stderr and with their status
+    fn shell_builtin(&mut self, words : &[String], env : &Env, span : Span) -> Result<Value, RuntimeError> {
+        let args = &words[1..];
+
+        let result = match words[0].as_str() {
+            "set" => return self.set_options(args, span),
+            "source" => return self.source(args, env, span),
+            "exit" => return Err(exit_code(args).map_or_else(|message| RuntimeError::Builtin { message, span }, |code| RuntimeError::Exit { code, span })),
+            "cd" => self.dirs.cd(args, &mut self.environment),
+            "pwd" => self.dirs.pwd(args),
+            "pushd" => self.dirs.pushd(args, &mut self.environment),
+            "popd" => self.dirs.popd(args, &mut self.environment),
+            "jobs" => {
+                for line in self.jobs.list() {
+                    println!("{}", line);
+                }
+                Ok(0)
+            },
+            "fg" => self.jobs.fg(args.first().map(|arg| arg.as_str())),
+            "bg" => self.jobs.bg(args.first().map(|arg| arg.as_str())).map(|_| 0),
+            "wait" => self.jobs.wait(args),
+            "kill" => self.jobs.kill(args).map(|_| 0),
+            other => unreachable!("{} isn't a shell builtin", other),
+        };
+
+        Ok(Value::Status(result.unwrap_or_else(builtin_failed)))
+    }
+
+    // Runs a file's statements in the current scope, so that its bindings are
+    // kept. Errors inside it are placed in the file rather than at 'source',
+    // while a file that can't be read or parsed is a failing status
+    fn source(&mut self, args : &[String], env : &Env, span : Span) -> Result<Value, RuntimeError> {
+        let path = match args {
+            [path] => path,
+            _ => return Ok(Value::Status(builtin_failed("source takes one file".to_string()))),
+        };
+        let failed = |message : String| RuntimeError::SourceFailed { path: path.clone(), message, span };
+
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) => return Ok(Value::Status(builtin_failed(format!("source: {}: {}", path, e)))),
+        };
+        let program = match parse(&text) {
+            Ok(program) => program,
+            Err(error) => return Ok(Value::Status(builtin_failed(format!("source: {}:{}", path, located(&text, error.index(), &error.message()))))),
+        };
+
+        self.enter(span)?;
+        let result = self.statements(&program, env);
+        self.depth -= 1;
+
+        match result {
+            Err(error @ RuntimeError::Exit { .. })
+            | Err(error @ RuntimeError::Interrupted { .. })
+            | Err(error @ RuntimeError::StackOverflow { .. }) => Err(error),
+            Err(error) => Err(failed(located(&text, error.span().start, &error.to_string()))),
+            ok => ok,
+        }
+    }
+
+    // 'set name' turns a shell option on and 'set +name' turns it off
+    fn set_options(&mut self, names : &[String], span : Span) -> Result<Value, RuntimeError> {
+        for name in names {
+            let (option, on) = match name.strip_prefix('+') {
+                Some(option) => (option, false),
+                None => (name.as_str(), true),
+            };
+
+            match option {
+                "pipefail" => self.options.pipefail = on,
+                "failglob" => self.options.failglob = on,
+                "errexit" => self.options.errexit = on,
+                _ => return Err(RuntimeError::UnknownOption { name: option.to_string(), span }),
+            }
+        }
+
+        Ok(Value::Status(0))
+    }
+
+    fn items(&mut self, items : &[Ast], env : &Env) -> Result<Vec<Value>, RuntimeError> {
+        items.iter().map(|item| self.expr(item, env)).collect()
+    }
+
+    pub fn call(&mut self, function : Value, args : Vec<(Option<String>, Value)>, span : Span) -> Result<Value, RuntimeError> {
+        // ctrl-c stops a running ash loop the next time it calls anything
+        if jobs::interrupted() {
+            return Err(RuntimeError::Interrupted { span });
+        }
+
+        match function {
+            Value::Builtin(builtin) => {
+                let mut values = vec![];
+                for (name, value) in args {
+                    if let Some(name) = name {
+                        return Err(RuntimeError::UnknownArgument { name, span });
+                    }
+                    values.push(value);
+                }
+                let context = Context { last_status: self.last_status, home: self.environment.get("HOME").map(|home| home.as_str()) };
+                (builtin.f)(&values, &context, span)
+            },
+            Value::Closure(closure) => {
+                let call_env = closure.env.child();
+
+                for (param, value) in bind_arguments(&closure.params, args, span)? {
+                    call_env.define(&param, value);
+                }
+
+                self.enter(span)?;
+                let result = self.expr(&closure.body, &call_env);
+                self.depth -= 1;
+
+                result
+            },
+            other => Err(RuntimeError::NotCallable { found: other.type_name(), span }),
+        }
+    }
+
+    // Calls and sourced files are how a program recurses, so each checks how
+    // much stack is left before going deeper
+    fn enter(&mut self, span : Span) -> Result<(), RuntimeError> {
+        let overflowed = match stack::limit() {
+            Some(limit) => stack::address() < limit,
+            None => self.depth >= MAX_CALL_DEPTH,
+        };
+
+        if overflowed {
+            return Err(RuntimeError::StackOverflow { span });
+        }
+
+        self.depth += 1;
+        Ok(())
+    }
+}
+
+impl Default for Evaluator {
+    fn default() -> Evaluator {
+        Evaluator::new()
+    }
+}
+
+pub fn bind_arguments(params : &[String], args : Vec<(Option<String>, Value)>, span : Span) -> Result<Vec<(String, Value)>, RuntimeError> {
+    let mut slots : Vec<Option<Value>> = vec![None; params.len()];
+    let found = args.len();
+
+    for (position, (name, value)) in args.into_iter().enumerate() {
+        let slot = match name {
+            None if position < params.len() => position,
+            None => return Err(RuntimeError::ArityMismatch { expected: params.len(), found, span }),
+            Some(name) => match params.iter().position(|param| *param == name) {
+                Some(slot) if slots[slot].is_some() => return Err(RuntimeError::DuplicateArgument { name, span }),
+                Some(slot) => slot,
+                None => return Err(RuntimeError::UnknownArgument { name, span }),
+            },
+        };
+
+        slots[slot] = Some(value);
+    }
+
+    params.iter().zip(slots).map(|(param, slot)| match slot {
+        Some(value) => Ok((param.clone(), value)),
+        None => Err(RuntimeError::MissingArgument { name: param.clone(), span }),
+    }).collect()
+}
+
+pub fn index_value(target : Value, index : Value, span : Span) -> Result<Value, RuntimeError> {
+    fn item<T : Clone>(items : &[T], index : i64, span : Span) -> Result<T, RuntimeError> {
+        if index < 0 || index as usize >= items.len() {
+            Err(RuntimeError::IndexOutOfBounds { index, length: items.len(), span })
+        }
+        else {
+            Ok(items[index as usize].clone())
+        }
+    }
+
+    match (target, index) {
+        (Value::List(items), Value::Integer(i)) => item(&items, i, span),
+        (Value::Tuple(items), Value::Integer(i)) => item(&items, i, span),
+        (Value::String(s), Value::Integer(i)) => item(&s.chars().collect::<Vec<_>>(), i, span).map(Value::Char),
+        (Value::Record(fields), Value::String(name)) => field_value(fields, &name, span),
+        (Value::Record(_), other) => Err(RuntimeError::TypeMismatch { expected: "string", found: other.type_name(), span }),
+        (Value::List(_), other) | (Value::Tuple(_), other) | (Value::String(_), other) =>
+            Err(RuntimeError::TypeMismatch { expected: "integer", found: other.type_name(), span }),
+        (other, _) => Err(RuntimeError::TypeMismatch { expected: "list, tuple, string or record", found: other.type_name(), span }),
+    }
+}
+
+// Commands succeed with a status of 0, so that's what's true for them
+fn truthy(value : &Value, span : Span) -> Result<bool, RuntimeError> {
+    match value {
+        Value::Bool(b) => Ok(*b),
+        Value::Status(status) => Ok(*status == 0),
+        other => Err(RuntimeError::TypeMismatch { expected: "bool or status", found: other.type_name(), span }),
+    }
+}
+
+// What a catch binds. Errors other than failed commands have a status of 1
+fn error_value(error : &RuntimeError) -> Value {
+    let status = match error {
+        RuntimeError::CommandStatus { status, .. } => *status,
+        _ => 1,
+    };
+
+    Value::Record(vec![ ("message".to_string(), Value::String(error.to_string()))
+                      , ("status".to_string(), Value::Status(status))
+                      ])
+}
+
+const SHELL_BUILTINS : [&str; 12] = ["set", "source", "exit", "cd", "pwd", "pushd", "popd", "jobs", "fg", "bg", "wait", "kill"];
+
+fn is_shell_builtin(name : &str) -> bool {
+    SHELL_BUILTINS.contains(&name)
+}
+
+fn builtin_failed(message : String) -> i32 {
+    eprintln!("ash: {}", message);
+    1
+}
+
+// 'exit' alone exits successfully
+fn exit_code(args : &[String]) -> Result<i32, String> {
+    match args {
+        [] => Ok(0),
+        [code] => code.parse().map_err(|_| format!("exit needs a number but found '{}'", code)),
+        _ => Err("exit takes at most one status".to_string()),
+    }
+}
+
+fn located(source : &str, index : usize, message : &str) -> String {
+    let location = diagnostic::locate(source, index);
+    format!("{}:{}: {}", location.line, location.column, message)
+}
+
+// How a job's pipeline is shown by 'jobs' and friends
+fn stage_text(run : &Run) -> String {
+    match run {
+        Run::Program(words) => words.join(" "),
+        Run::Function(Value::Closure(closure), args) => {
+            let mut words = vec![closure.name.clone().unwrap_or_else(|| "fn".to_string())];
+            words.extend(args.iter().cloned());
+            words.join(" ")
+        },
+        Run::Function(_, args) => args.join(" "),
+    }
+}
+
+// Like other shells, substitution drops the newlines a command ends its output with
+fn trim_newlines(mut s : String) -> String {
+    let length = s.trim_end_matches('\n').len();
+    s.truncate(length);
+    s
+}
+
+pub fn field_value(fields : Vec<(String, Value)>, name : &str, span : Span) -> Result<Value, RuntimeError> {
+    fields.into_iter()
+          .find(|(field, _)| field == name)
+          .map(|(_, value)| value)
+          .ok_or_else(|| RuntimeError::MissingField { name: name.to_string(), span })
+}
+
+// Binds into env as it goes, so callers should give each attempt its own env
+fn pattern_match(pattern : &Pattern, value : &Value, env : &Env) -> bool {
+    match (pattern, value) {
+        (Pattern::Literal(ast), value) => Value::from_literal(ast).as_ref() == Some(value),
+        (Pattern::Wildcard, _) => true,
+        (Pattern::Bind(name), value) => {
+            env.define(name, value.clone());
+            true
+        },
+        (Pattern::List { items, rest: None }, Value::List(values)) =>
+            items.len() == values.len()
+            && items.iter().zip(values.iter()).all(|(p, v)| pattern_match(p, v, env)),
+        (Pattern::List { items, rest: Some(rest) }, Value::List(values)) =>
+            items.len() <= values.len()
+            && items.iter().zip(values.iter()).all(|(p, v)| pattern_match(p, v, env))
+            && pattern_match(rest, &Value::List(values[items.len()..].to_vec()), env),
+        (Pattern::Tuple(items), Value::Tuple(values)) =>
+            items.len() == values.len()
+            && items.iter().zip(values.iter()).all(|(p, v)| pattern_match(p, v, env)),
+        (Pattern::Record(fields), Value::Record(values)) =>
+            fields.iter().all(|(name, p)|
+                values.iter().find(|(field, _)| field == name).is_some_and(|(_, v)| pattern_match(p, v, env))),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parsing::grammar::parse;
+
+    fn run(s : &str) -> Result<Value, RuntimeError> {
+        let program = parse(s).expect("test program should parse");
+        Evaluator::new().eval(&program)
+    }
+
+    #[test]
+    fn should_evaluate_literals() {
+        assert_eq!( run("5"), Ok(Value::Integer(5)) );
+        assert_eq!( run("true"), Ok(Value::Bool(true)) );
+        assert_eq!( run("'a'"), Ok(Value::Char('a')) );
+        assert_eq!( run(r#""a""#), Ok(Value::String("a".to_string())) );
+        assert_eq!( run("[1, (2,), { x: 3 }]").map(|v| v.to_string()), Ok("[1, (2,), { x: 3 }]".to_string()) );
+    }
+
+    #[test]
+    fn should_evaluate_let_and_variables() {
+        let v = run("let x = 5; let y = x; y");
+
+        assert_eq!( v, Ok(Value::Integer(5)) );
+    }
+
+    #[test]
+    fn block_bindings_should_not_escape() {
+        let v = run("let x = 1; { let x = 2; x }; x");
+
+        assert_eq!( v, Ok(Value::Integer(1)) );
+    }
+
+    #[test]
+    fn should_report_unbound_variable_with_span() {
+        let v = run("let x = 1;\n  y");
+
+        assert_eq!( v, Err(RuntimeError::UnboundVariable { name: "y".to_string(), span: Span { start: 13, end: 14 } }) );
+    }
+
+    #[test]
+    fn should_evaluate_if() {
+        assert_eq!( run("if false { 1 } else if true { 2 } else { 3 }"), Ok(Value::Integer(2)) );
+        assert_eq!( run("if false { 1 }"), Ok(Value::unit()) );
+    }
+
+    #[test]
+    fn if_should_require_bool_condition() {
+        let v = run("if 1 { 2 }");
+
+        assert!(matches!( v, Err(RuntimeError::TypeMismatch { expected: "bool or status", found: "integer", .. }) ));
+    }
+
+    #[test]
+    fn should_evaluate_recursive_function() {
+        let v = run("
+            fn fact(n) {
+                if eq(n, 0) { 1 } else { mul(n, fact(sub(n, 1))) }
+            }
+            fact(10)");
+
+        assert_eq!( v, Ok(Value::Integer(3628800)) );
+    }
+
+    #[test]
+    fn closures_should_capture_environment() {
+        let v = run("
+            fn adder(x) { fn(y) { add(x, y) } }
+            let add5 = adder(5);
+            let x = 100;
+            add5(1)");
+
+        assert_eq!( v, Ok(Value::Integer(6)) );
+    }
+
+    #[test]
+    fn call_should_bind_named_arguments() {
+        let v = run("fn f(a, b) { sub(a, b) } f(b = 1, a = 10)");
+
+        assert_eq!( v, Ok(Value::Integer(9)) );
+    }
+
+    #[test]
+    fn call_should_report_missing_argument() {
+        let v = run("fn f(a, b) { a } f(1)");
+
+        assert!(matches!( v, Err(RuntimeError::MissingArgument { name, .. }) if name == "b" ));
+    }
+
+    #[test]
+    fn call_should_report_not_callable() {
+        let v = run("let x = 1; x()");
+
+        assert!(matches!( v, Err(RuntimeError::NotCallable { found: "integer", .. }) ));
+    }
+
+    #[test]
+    fn should_evaluate_match_with_patterns_and_guards() {
+        let v = run(r#"
+            fn describe(v) {
+                match v {
+                    [] => "empty",
+                    [x, ..rest] if eq(len(rest), 0) => "one ${x}",
+                    [_, ..] => "many",
+                    { name, age: 5 } => "five year old ${name}",
+                    (a, 'b') => "pair",
+                    _ => "other",
+                }
+            }
+            [describe([]), describe([1]), describe([1, 2]), describe({ age: 5, name: "al" }), describe((1, 'b')), describe(7)]
+        "#);
+
+        assert_eq!( v.map(|v| v.to_string()), Ok(r#"["empty", "one 1", "many", "five year old al", "pair", "other"]"#.to_string()) );
+    }
+
+    #[test]
+    fn match_should_report_no_matching_arm() {
+        let v = run("match 1 { 2 => 3 }");
+
+        assert!(matches!( v, Err(RuntimeError::NoMatchingArm { .. }) ));
+    }
+
+    #[test]
+    fn should_evaluate_index_and_field_access() {
+        let v = run(r#"let r = { items: [(1, "a")] }; r.items[0].1"#);
+
+        assert_eq!( v, Ok(Value::String("a".to_string())) );
+    }
+
+    #[test]
+    fn index_should_report_out_of_bounds() {
+        let v = run("[1, 2][2]");
+
+        assert!(matches!( v, Err(RuntimeError::IndexOutOfBounds { index: 2, length: 2, .. }) ));
+    }
+
+    #[test]
+    fn should_evaluate_interpolation() {
+        let v = run(r#"let name = "world"; "hello ${name} ${[1, "2"]}""#);
+
+        assert_eq!( v, Ok(Value::String(r#"hello world [1, "2"]"#.to_string())) );
+    }
+
+    #[test]
+    fn should_run_command_and_return_status() {
+        assert_eq!( run("$ sh -c 'exit 4'"), Ok(Value::Status(4)) );
+        assert_eq!( run("$ true\n$ false"), Ok(Value::Status(1)) );
+    }
+
+    #[test]
+    fn command_should_expand_expressions_in_words() {
+        let v = run(r#"
+            let xs = ["a b", "c"];
+            let n = 2;
+            $ sh -c 'test "$1" = "a b" && test "$2" = c && test "$3" = x2.txt && test $# = 3' sh ${xs} x${n}.txt
+            "#);
+
+        assert_eq!( v, Ok(Value::Status(0)) );
+    }
+
+    #[test]
+    fn command_should_pass_quoted_list_as_one_argument() {
+        let v = run(r#"
+            let xs = ["a", "b", "c"];
+            [$(printf '%s\n' "${xs}" | wc -l), $(printf '%s\n' ${xs} | wc -l)]
+            "#);
+
+        assert_eq!( v, Ok(Value::List(vec![Value::String("1".to_string()), Value::String("3".to_string())])) );
+    }
+
+    #[test]
+    fn pipeline_should_call_functions_as_stages() {
+        let v = run(r#"
+            fn tag(line, suffix) { "${line}${suffix}" }
+            $ printf 'a\nb\n' | tag x | sh -c 'test "$(cat)" = "$(printf "ax\nbx")"'
+            "#);
+
+        assert_eq!( v, Ok(Value::Status(0)) );
+    }
+
+    #[test]
+    fn pipeline_should_use_function_status() {
+        assert_eq!( run("fn fail(line) { $ false }\n$ echo a | fail"), Ok(Value::Status(1)) );
+    }
+
+    #[test]
+    fn command_should_redirect_to_expanded_path() {
+        let dir = std::env::temp_dir().to_string_lossy().into_owned();
+        let name = format!("ash_eval_redirect_{}", std::process::id());
+
+        let v = run(&format!(r#"
+            let dir = "{}";
+            $ echo hello > ${{dir}}/{}
+            $ grep -q hello < ${{dir}}/{}
+            "#, dir, name, name));
+
+        assert_eq!( v, Ok(Value::Status(0)) );
+        let _ = std::fs::remove_file(std::env::temp_dir().join(name));
+    }
+
+    #[test]
+    fn command_should_reject_redirect_to_several_paths() {
+        let v = run("$ echo > ${[\"a\", \"b\"]}");
+
+        assert!(matches!( v, Err(RuntimeError::AmbiguousRedirect { found: 2, .. }) ));
+    }
+
+    #[test]
+    fn substitution_should_give_trimmed_stdout() {
+        assert_eq!( run("$(printf 'a\\nb\\n\\n')"), Ok(Value::String("a\nb".to_string())) );
+        assert_eq!( run("let n = len($(echo hello)); n"), Ok(Value::Integer(5)) );
+    }
+
+    #[test]
+    fn substitution_should_give_record_when_structured() {
+        let v = run("$?(sh -c 'echo out; echo err >&2; exit 3')");
+
+        assert_eq!( v, Ok(Value::Record(vec![ ("stdout".to_string(), Value::String("out".to_string()))
+                                            , ("stderr".to_string(), Value::String("err".to_string()))
+                                            , ("status".to_string(), Value::Status(3))
+                                            ])) );
+        assert_eq!( run("$?(sh -c 'exit 2').status"), Ok(Value::Status(2)) );
+    }
+
+    #[test]
+    fn substitution_should_capture_pipeline_and_nest_in_words() {
+        let v = run("$(echo $(printf 'a\\nb') | tr a-z A-Z\n)");
+
+        assert_eq!( v, Ok(Value::String("A\nB".to_string())) );
+    }
+
+    #[test]
+    fn substitution_should_capture_function_stage() {
+        let v = run("fn double(line) { [line, line] }\n$(seq 2 | double)");
+
+        assert_eq!( v, Ok(Value::String("1\n1\n2\n2".to_string())) );
+    }
+
+    #[test]
+    fn substitution_should_stop_function_stage_with_head() {
+        let v = run("fn pass(line) { line }\n$(yes | pass | head -1)");
+
+        assert_eq!( v, Ok(Value::String("y".to_string())) );
+    }
+
+    #[test]
+    fn env_var_should_read_shell_environment() {
+        let home = std::env::var("HOME").unwrap_or_default();
+
+        assert_eq!( run("$HOME"), Ok(Value::String(home)) );
+        assert!(matches!( run("$ASH_NO_SUCH_VARIABLE"), Err(RuntimeError::UnsetEnvVar { name, .. }) if name == "ASH_NO_SUCH_VARIABLE" ));
+    }
+
+    #[test]
+    fn export_should_pass_variable_to_commands() {
+        let v = run(r#"
+            export ASH_TEST_X = add(1, 2);
+            $ sh -c 'test "$ASH_TEST_X" = 3 && test "$1" = 3' sh $ASH_TEST_X
+            "#);
+
+        assert_eq!( v, Ok(Value::Status(0)) );
+    }
+
+    #[test]
+    fn with_env_should_undo_its_variables_afterwards() {
+        let v = run(r#"
+            export ASH_TEST_A = "outer";
+            let inside = with_env(ASH_TEST_A = "inner", ASH_TEST_B = 1) {
+                export ASH_TEST_C = 2;
+                $(sh -c 'echo $ASH_TEST_A $ASH_TEST_B $ASH_TEST_C')
+            };
+            [inside, $ASH_TEST_A, $(sh -c 'echo ${ASH_TEST_C:-unset}')]
+            "#);
+
+        assert_eq!( v, Ok(Value::List(vec![ Value::String("inner 1 2".to_string())
+                                          , Value::String("outer".to_string())
+                                          , Value::String("unset".to_string())
+                                          ])) );
+    }
+
+    #[test]
+    fn command_should_expand_globs_in_bare_text_only() {
+        let dir = std::env::temp_dir().join(format!("ash_eval_glob_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        for name in ["b.txt", "a.txt", "*.txt"] {
+            std::fs::write(dir.join(name), "").unwrap();
+        }
+
+        let v = run(&format!(r#"
+            let dir = "{}";
+            let star = "*";
+            $ sh -c 'test "$*" = "$0/*.txt $0/a.txt $0/b.txt"' ${{dir}} ${{dir}}/*.txt
+            $ sh -c 'test $# = 2' sh ${{dir}}/"*.txt" ${{dir}}/${{star}}.txt
+            "#, dir.to_string_lossy()));
+
+        assert_eq!( v, Ok(Value::Status(0)) );
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn command_should_report_or_keep_unmatched_glob() {
+        let v = run("$ echo /ash-no-such-dir/*.rs");
+
+        assert!(matches!( v, Err(RuntimeError::NoGlobMatch { pattern, .. }) if pattern == "/ash-no-such-dir/*.rs" ));
+        assert_eq!( run("$ set +failglob\n$ test /ash-no-such-dir/*.rs = '/ash-no-such-dir/*.rs'"), Ok(Value::Status(0)) );
+    }
+
+    #[test]
+    fn command_should_expand_tilde_at_word_start() {
+        let v = run(r#"
+            export HOME = "/ash/home";
+            $ test ~/src = /ash/home/src -a ~ = /ash/home -a a~ = 'a~' -a '~' = "~" -a ~x = '~x'
+            "#);
+
+        assert_eq!( v, Ok(Value::Status(0)) );
+    }
+
+    #[test]
+    fn set_should_toggle_pipefail() {
+        assert_eq!( run("$ false | true"), Ok(Value::Status(0)) );
+        assert_eq!( run("$ set pipefail\n$ false | true"), Ok(Value::Status(1)) );
+        assert_eq!( run("$ set pipefail\n$ set +pipefail\n$ false | true"), Ok(Value::Status(0)) );
+    }
+
+    #[test]
+    fn set_should_report_unknown_option() {
+        let v = run("$ set blah");
+
+        assert!(matches!( v, Err(RuntimeError::UnknownOption { name, .. }) if name == "blah" ));
+    }
+
+    #[test]
+    fn wait_should_give_status_of_background_job() {
+        assert_eq!( run("$ sh -c 'exit 3' &\n$ wait"), Ok(Value::Status(3)) );
+        assert_eq!( run("$ sh -c 'exit 3' &"), Ok(Value::Status(0)) );
+    }
+
+    #[test]
+    fn kill_should_signal_background_job() {
+        assert_eq!( run("$ sleep 10 &\n$ kill %1\n$ wait %1"), Ok(Value::Status(143)) );
+    }
+
+    #[test]
+    fn fg_should_fail_without_jobs() {
+        assert_eq!( run("$ fg"), Ok(Value::Status(1)) );
+        assert_eq!( run("$ fg || $ true"), Ok(Value::Status(0)) );
+    }
+
+    #[test]
+    fn background_should_refuse_function_stages() {
+        let v = run("fn f(line) { 1 }\n$ echo a | f &");
+
+        assert!(matches!( v, Err(RuntimeError::BackgroundFunction { .. }) ));
+    }
+
+    #[test]
+    fn if_should_take_command_status() {
+        assert_eq!( run("if $ true { 1 } else { 2 }"), Ok(Value::Integer(1)) );
+        assert_eq!( run("if $ sh -c 'exit 3' { 1 } else { 2 }"), Ok(Value::Integer(2)) );
+        assert_eq!( run("not($ false)"), Ok(Value::Bool(true)) );
+    }
+
+    #[test]
+    fn and_or_should_short_circuit() {
+        assert_eq!( run("$ false && $ sh -c 'exit 5'"), Ok(Value::Status(1)) );
+        assert_eq!( run("$ true && sh -c 'exit 5'"), Ok(Value::Status(5)) );
+        assert_eq!( run("$ false || true"), Ok(Value::Status(0)) );
+        assert_eq!( run("true || missing"), Ok(Value::Bool(true)) );
+        assert_eq!( run("false && missing"), Ok(Value::Bool(false)) );
+        assert_eq!( run("false || 3"), Ok(Value::Integer(3)) );
+    }
+
+    #[test]
+    fn and_or_should_require_truthy_operand() {
+        let v = run("1 && true");
+
+        assert!(matches!( v, Err(RuntimeError::TypeMismatch { expected: "bool or status", found: "integer", .. }) ));
+    }
+
+    #[test]
+    fn try_should_catch_errors() {
+        let v = run("try { missing } catch e { e.message }");
+
+        assert_eq!( v, Ok(Value::String("unbound variable 'missing'".to_string())) );
+        assert_eq!( run("try { 1 } catch e { 2 }"), Ok(Value::Integer(1)) );
+        assert!(matches!( run("try { $ exit 2 } catch e { 1 }"), Err(RuntimeError::Exit { code: 2, .. }) ));
+    }
+
+    #[test]
+    fn errexit_should_stop_at_failing_command() {
+        let v = run("$ set errexit\n$ true\n  $ sh -c 'exit 4'\n$ true");
+
+        assert_eq!( v, Err(RuntimeError::CommandStatus { status: 4, span: Span { start: 23, end: 39 } }) );
+        assert_eq!( run("$ sh -c 'exit 4'\n$ true"), Ok(Value::Status(0)) );
+    }
+
+    #[test]
+    fn errexit_should_allow_failures_in_conditions() {
+        let v = run(r#"
+            $ set errexit
+            if $ false { 1 }
+            $ false || true
+            $ false && true
+            "#);
+
+        assert_eq!( v, Ok(Value::Status(1)) );
+        assert!(matches!( run("$ set errexit\n$ true && false"), Err(RuntimeError::CommandStatus { status: 1, .. }) ));
+    }
+
+    #[test]
+    fn try_should_catch_errexit_failure() {
+        let v = run("$ set errexit\ntry { $ sh -c 'exit 6'\n } catch e { e.status }");
+
+        assert_eq!( v, Ok(Value::Status(6)) );
+    }
+
+    // the working directory is put back before checking anything
+    fn in_temp_dirs(name : &str, dirs : &[&str], program : &str) -> (Result<Value, RuntimeError>, std::path::PathBuf, String) {
+        let _turn = dirs::working_dir_turn();
+        let start = std::env::current_dir().unwrap();
+        let root = std::env::temp_dir().join(format!("ash_eval_{}_{}", name, std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        for dir in dirs {
+            std::fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        let root = root.canonicalize().unwrap().to_string_lossy().into_owned();
+
+        let v = run(&program.replace("ROOT", &root));
+
+        let ended = std::env::current_dir().unwrap();
+        std::env::set_current_dir(start).unwrap();
+        let _ = std::fs::remove_dir_all(&root);
+        (v, ended, root)
+    }
+
+    #[test]
+    fn cd_should_change_directory_and_go_back() {
+        let (v, ended, root) = in_temp_dirs("cd", &["sub"], r#"
+            $ cd ROOT
+            $ cd sub
+            $ sh -c 'test "$PWD" = "$0" && test "$OLDPWD" = ROOT' ROOT/sub
+            $ cd -
+            "#);
+
+        assert_eq!( v, Ok(Value::Status(0)) );
+        assert_eq!( ended.to_string_lossy(), root );
+    }
+
+    #[test]
+    fn cd_should_keep_pwd_after_with_env() {
+        let (v, ended, root) = in_temp_dirs("cd_with_env", &["sub"], r#"
+            $ cd ROOT
+            with_env(ASH_TEST_D = 1) { $ cd sub }
+            [$PWD, $OLDPWD, $(sh -c 'echo ${ASH_TEST_D:-unset}')]
+            "#);
+
+        assert_eq!( v, Ok(Value::List(vec![ Value::String(format!("{}/sub", root))
+                                          , Value::String(root.clone())
+                                          , Value::String("unset".to_string())
+                                          ])) );
+        assert_eq!( ended.to_string_lossy(), format!("{}/sub", root) );
+    }
+
+    #[test]
+    fn cwd_should_abbreviate_exported_home() {
+        let (v, _, root) = in_temp_dirs("cwd_home", &["sub"], r#"
+            $ cd ROOT/sub
+            export HOME = "ROOT";
+            let inside = cwd();
+            export HOME = "/";
+            [inside, cwd()]
+            "#);
+
+        assert_eq!( v, Ok(Value::List(vec![ Value::String("~/sub".to_string())
+                                          , Value::String(format!("{}/sub", root))
+                                          ])) );
+    }
+
+    #[test]
+    fn cd_should_search_cdpath() {
+        let (v, ended, root) = in_temp_dirs("cdpath", &["projects/ash"], r#"
+            export CDPATH = ":ROOT/projects";
+            $ cd ash
+            "#);
+
+        assert_eq!( v, Ok(Value::Status(0)) );
+        assert_eq!( ended.to_string_lossy(), format!("{}/projects/ash", root) );
+    }
+
+    #[test]
+    fn cd_should_report_missing_directory() {
+        let (v, _, _) = in_temp_dirs("cd_missing", &[], "$ cd ROOT/missing");
+        let (fallback, _, _) = in_temp_dirs("cd_fallback", &[], "$ cd ROOT/missing || $ sh -c 'exit 2'");
+        let (caught, _, _) = in_temp_dirs("cd_caught", &[], "$ set errexit\ntry { $ cd ROOT/missing\n } catch e { e.status }");
+
+        assert_eq!( v, Ok(Value::Status(1)) );
+        assert_eq!( fallback, Ok(Value::Status(2)) );
+        assert_eq!( caught, Ok(Value::Status(1)) );
+    }
+
+    #[test]
+    fn shell_builtin_should_refuse_pipes_and_redirects() {
+        let path = std::env::temp_dir().join(format!("ash_eval_builtin_redirect_{}", std::process::id()));
+
+        assert!(matches!( run("$ cd / | cat"), Err(RuntimeError::ShellBuiltinStage { name, .. }) if name == "cd" ));
+        assert!(matches!( run(&format!("$ pwd > {}", path.display())), Err(RuntimeError::ShellBuiltinStage { name, .. }) if name == "pwd" ));
+        assert!(matches!( run("$ jobs &"), Err(RuntimeError::ShellBuiltinStage { .. }) ));
+        assert!( !path.exists() );
+    }
+
+    #[test]
+    fn popd_should_return_to_pushed_directories() {
+        let (v, ended, root) = in_temp_dirs("pushd", &["a", "b"], r#"
+            $ pushd ROOT/a
+            $ pushd ROOT/b
+            $ pushd
+            $ sh -c 'test "$PWD" = "$0"' ROOT/a
+            $ popd
+            "#);
+
+        assert_eq!( v, Ok(Value::Status(0)) );
+        assert_eq!( ended.to_string_lossy(), format!("{}/b", root) );
+    }
+
+    #[test]
+    fn popd_should_fail_on_empty_stack() {
+        assert_eq!( run("$ popd"), Ok(Value::Status(1)) );
+    }
+
+    #[test]
+    fn exit_should_unwind_with_code() {
+        assert!(matches!( run("$ exit 3\n1"), Err(RuntimeError::Exit { code: 3, .. }) ));
+        assert!(matches!( run("fn f() { $ exit\n}\nf()"), Err(RuntimeError::Exit { code: 0, .. }) ));
+        assert!(matches!( run("$ exit x"), Err(RuntimeError::Builtin { .. }) ));
+    }
+
+    #[test]
+    fn call_should_stop_runaway_recursion() {
+        let programs = [ "fn f(n) { f(n) } f(1)"
+                       , "fn f(n) { { match n { _ => if true { [{ f(n) }] } } } } f(1)"
+                       , "let f = fn(n) { add(1, f(n)) }; f(1)"
+                       ];
+
+        // the interpreter thread's stack, and ones an embedder might run on
+        for stack_size in [STACK_SIZE, 2 * 1024 * 1024, 512 * 1024] {
+            for program in programs {
+                let overflowed = std::thread::Builder::new()
+                    .stack_size(stack_size)
+                    .spawn(move || matches!( run(program), Err(RuntimeError::StackOverflow { .. }) ))
+                    .unwrap()
+                    .join()
+                    .unwrap();
+
+                assert!( overflowed, "program: {}, stack: {}", program, stack_size );
+            }
+        }
+    }
+
+    #[test]
+    fn source_should_evaluate_file_in_current_scope() {
+        let path = std::env::temp_dir().join(format!("ash_eval_source_{}.ash", std::process::id()));
+        std::fs::write(&path, "let sourced = 2;\nfn twice(x) { [x, x] }\n").unwrap();
+
+        let v = run(&format!("fn f() {{\n$ source {}\ntwice(sourced)\n}}\nf()", path.display()));
+
+        assert_eq!( v.map(|v| v.to_string()), Ok("[2, 2]".to_string()) );
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn source_should_place_errors_in_file() {
+        let path = std::env::temp_dir().join(format!("ash_eval_source_error_{}.ash", std::process::id()));
+        std::fs::write(&path, "let a = 1;\n  b").unwrap();
+
+        let v = run(&format!("$ source {}", path.display()));
+
+        assert!(matches!( v, Err(RuntimeError::SourceFailed { message, .. }) if message == "2:3: unbound variable 'b'" ));
+        assert_eq!( run("$ source /ash-no-such-file.ash"), Ok(Value::Status(1)) );
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn eval_should_keep_bindings_between_calls() {
+        let mut evaluator = Evaluator::new();
+
+        evaluator.eval(&parse("let x = 1;").unwrap()).unwrap();
+        let v = evaluator.eval(&parse("x").unwrap());
+
+        assert_eq!( v, Ok(Value::Integer(1)) );
+    }
+
+    #[test]
+    fn last_status_should_belong_to_each_evaluator() {
+        let mut failed = Evaluator::new();
+        let mut passed = Evaluator::new();
+
+        failed.eval(&parse("$ sh -c 'exit 3'").unwrap()).unwrap();
+        passed.eval(&parse("$ true").unwrap()).unwrap();
+
+        assert_eq!( failed.eval(&parse("last_status()").unwrap()), Ok(Value::Status(3)) );
+        assert_eq!( passed.eval(&parse("last_status()").unwrap()), Ok(Value::Status(0)) );
+    }
+}
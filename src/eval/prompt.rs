@@ -0,0 +1,104 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::parsing::ast::Span;
+use super::dirs::tilde;
+use super::error::RuntimeError;
+use super::value::{Context, Value};
+
+// Builtins for writing a prompt() function, which the REPL calls for each prompt
+
+fn no_arguments(args : &[Value], span : Span) -> Result<(), RuntimeError> {
+    if args.is_empty() {
+        Ok(())
+    }
+    else {
+        Err(RuntimeError::ArityMismatch { expected: 0, found: args.len(), span })
+    }
+}
+
+pub fn last_status(args : &[Value], context : &Context, span : Span) -> Result<Value, RuntimeError> {
+    no_arguments(args, span)?;
+    Ok(Value::Status(context.last_status))
+}
+
+// The working directory, with $HOME shown as '~'
+pub fn cwd(args : &[Value], context : &Context, span : Span) -> Result<Value, RuntimeError> {
+    no_arguments(args, span)?;
+    let dir = std::env::current_dir().map_err(|e| RuntimeError::Builtin { message: format!("cannot find working directory: {}", e), span })?;
+    Ok(Value::String(tilde(&dir, context.home)))
+}
+
+// The branch checked out in the repository around the working directory, the
+// start of the commit when none is, or "" outside of a repository. HEAD is read
+// directly so that a prompt doesn't have to wait on git
+pub fn git_branch(args : &[Value], _context : &Context, span : Span) -> Result<Value, RuntimeError> {
+    no_arguments(args, span)?;
+
+    let branch = std::env::current_dir().ok()
+        .and_then(|dir| git_dir(&dir))
+        .and_then(|git| fs::read_to_string(git.join("HEAD")).ok())
+        .map(|head| branch_name(&head))
+        .unwrap_or_default();
+
+    Ok(Value::String(branch))
+}
+
+// A worktree or submodule has a '.git' file pointing at where its HEAD is
+fn git_dir(start : &Path) -> Option<PathBuf> {
+    for dir in start.ancestors() {
+        let git = dir.join(".git");
+        if git.is_dir() {
+            return Some(git);
+        }
+        if let Ok(link) = fs::read_to_string(&git) {
+            let target = link.trim().strip_prefix("gitdir:")?.trim();
+            return Some(dir.join(target));
+        }
+    }
+    None
+}
+
+fn branch_name(head : &str) -> String {
+    let head = head.trim();
+    match head.strip_prefix("ref:") {
+        Some(reference) => {
+            let reference = reference.trim();
+            reference.strip_prefix("refs/heads/").unwrap_or(reference).to_string()
+        },
+        None => head.chars().take(7).collect(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn branch_name_should_read_ref_or_commit() {
+        assert_eq!( branch_name("ref: refs/heads/feature/x\n"), "feature/x" );
+        assert_eq!( branch_name("3f2a9c01d2e4b5a6c7d8e9f0a1b2c3d4e5f6a7b8\n"), "3f2a9c0" );
+    }
+
+    #[test]
+    fn git_dir_should_search_parents_and_follow_links() {
+        let root = std::env::temp_dir().join(format!("ash_prompt_git_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("repo/.git")).unwrap();
+        fs::create_dir_all(root.join("repo/src/deep")).unwrap();
+        fs::create_dir_all(root.join("worktree")).unwrap();
+        fs::write(root.join("worktree/.git"), "gitdir: ../repo/.git\n").unwrap();
+
+        assert_eq!( git_dir(&root.join("repo/src/deep")), Some(root.join("repo/.git")) );
+        assert_eq!( git_dir(&root.join("worktree")), Some(root.join("worktree/../repo/.git")) );
+        let _ = fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn last_status_should_give_status_from_context() {
+        let context = Context { last_status: 3, home: None };
+
+        assert!(matches!( last_status(&[], &context, Span::default()), Ok(Value::Status(3)) ));
+        assert!(matches!( last_status(&[Value::unit()], &context, Span::default()), Err(RuntimeError::ArityMismatch { .. }) ));
+    }
+}
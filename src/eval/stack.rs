@@ -0,0 +1,84 @@
+use std::cell::Cell;
+#[cfg(target_os = "linux")]
+use std::os::raw::{c_int, c_void};
+
+// How far down the calling thread's native stack the evaluator may recurse.
+// The stack's real size is read for each thread, since an evaluator can be run
+// on any of them, and an eighth of it is kept back for what runs between checks
+
+// pthread_attr_t as opaque storage, larger than glibc and musl make it
+#[cfg(target_os = "linux")]
+#[repr(C)]
+struct Attributes {
+    _opaque : [u64; 16],
+}
+
+#[cfg(target_os = "linux")]
+extern "C" {
+    fn pthread_self() -> usize;
+    fn pthread_getattr_np(thread : usize, attributes : *mut Attributes) -> c_int;
+    fn pthread_attr_getstack(attributes : *const Attributes, address : *mut *mut c_void, size : *mut usize) -> c_int;
+    fn pthread_attr_destroy(attributes : *mut Attributes) -> c_int;
+}
+
+thread_local! {
+    // reading the stack's bounds can mean reading /proc, so it's done once a thread
+    static LIMIT : Cell<Option<Option<usize>>> = const { Cell::new(None) };
+}
+
+// The lowest address recursion should reach, or None where the stack can't be read
+pub fn limit() -> Option<usize> {
+    LIMIT.with(|limit| {
+        if limit.get().is_none() {
+            limit.set(Some(bounds().map(|(lowest, size)| lowest + size / 8)));
+        }
+        limit.get().flatten()
+    })
+}
+
+pub fn address() -> usize {
+    let marker = 0u8;
+    std::hint::black_box(&marker) as *const u8 as usize
+}
+
+// The lowest address of the calling thread's stack, which grows down to it, and its size
+#[cfg(target_os = "linux")]
+fn bounds() -> Option<(usize, usize)> {
+    let mut attributes = Attributes { _opaque: [0; 16] };
+    let mut address = std::ptr::null_mut();
+    let mut size = 0;
+
+    unsafe {
+        if pthread_getattr_np(pthread_self(), &mut attributes) != 0 {
+            return None;
+        }
+        let found = pthread_attr_getstack(&attributes, &mut address, &mut size);
+        pthread_attr_destroy(&mut attributes);
+
+        if found != 0 || address.is_null() || size == 0 {
+            return None;
+        }
+    }
+    Some((address as usize, size))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn bounds() -> Option<(usize, usize)> {
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn limit_should_be_below_the_current_frame() {
+        let here = address();
+        let limit = limit().unwrap();
+
+        assert!( limit < here );
+        // test threads get a few MB of stack at most
+        assert!( here - limit < 64 * 1024 * 1024 );
+    }
+}
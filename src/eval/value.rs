@@ -0,0 +1,200 @@
+
+use std::fmt;
+use std::rc::Rc;
+
+use crate::parsing::ast::{Ast, Span};
+use crate::vm;
+use super::env::Env;
+use super::error::RuntimeError;
+
+#[derive(Debug, Clone)]
+pub enum Value {
+    Integer(i64),
+    Bool(bool),
+    String(String),
+    Char(char),
+    List(Vec<Value>),
+    Tuple(Vec<Value>),
+    Record(Vec<(String, Value)>),
+    // the exit status of a command
+    Status(i32),
+    Closure(Rc<Closure>),
+    Compiled(Rc<vm::Closure>),
+    Builtin(Builtin),
+}
+
+#[derive(Debug)]
+pub struct Closure {
+    pub name : Option<String>,
+    pub params : Vec<String>,
+    pub body : Ast,
+    pub env : Env,
+}
+
+#[derive(Clone, Copy)]
+pub struct Builtin {
+    pub name : &'static str,
+    pub f : fn(&[Value], &Context, Span) -> Result<Value, RuntimeError>,
+}
+
+// What builtins can see of the shell calling them
+#[derive(Default)]
+pub struct Context<'a> {
+    // the status of the last command run
+    pub last_status : i32,
+    // $HOME as the shell's commands see it, which can differ from the process's
+    pub home : Option<&'a str>,
+}
+
+impl fmt::Debug for Builtin {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Builtin({})", self.name)
+    }
+}
+
+impl Value {
+    pub fn unit() -> Value {
+        Value::Tuple(vec![])
+    }
+
+    pub fn from_literal(ast : &Ast) -> Option<Value> {
+        match ast {
+            Ast::Integer(i) => Some(Value::Integer(*i)),
+            Ast::Bool(b) => Some(Value::Bool(*b)),
+            Ast::String(s) => Some(Value::String(s.clone())),
+            Ast::Char(c) => Some(Value::Char(*c)),
+            _ => None,
+        }
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Integer(_) => "integer",
+            Value::Bool(_) => "bool",
+            Value::String(_) => "string",
+            Value::Char(_) => "char",
+            Value::List(_) => "list",
+            Value::Tuple(_) => "tuple",
+            Value::Record(_) => "record",
+            Value::Status(_) => "status",
+            Value::Closure(_) => "function",
+            Value::Compiled(_) => "function",
+            Value::Builtin(_) => "function",
+        }
+    }
+}
+
+impl PartialEq for Value {
+    fn eq(&self, other : &Value) -> bool {
+        match (self, other) {
+            (Value::Integer(a), Value::Integer(b)) => a == b,
+            (Value::Bool(a), Value::Bool(b)) => a == b,
+            (Value::String(a), Value::String(b)) => a == b,
+            (Value::Char(a), Value::Char(b)) => a == b,
+            (Value::List(a), Value::List(b)) => a == b,
+            (Value::Tuple(a), Value::Tuple(b)) => a == b,
+            (Value::Status(a), Value::Status(b)) => a == b,
+            (Value::Record(a), Value::Record(b)) =>
+                a.len() == b.len() && a.iter().all(|(name, v)| b.iter().any(|(n, w)| n == name && v == w)),
+            (Value::Closure(a), Value::Closure(b)) => Rc::ptr_eq(a, b),
+            (Value::Compiled(a), Value::Compiled(b)) => Rc::ptr_eq(a, b),
+            (Value::Builtin(a), Value::Builtin(b)) => a.name == b.name,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    // Strings and chars display as their text, but anywhere inside of a
+    // structure they're quoted so that "1" and 1 can be told apart
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::String(s) => write!(f, "{}", s),
+            Value::Char(c) => write!(f, "{}", c),
+            other => write_repr(other, f),
+        }
+    }
+}
+
+fn write_repr(value : &Value, f : &mut fmt::Formatter) -> fmt::Result {
+    fn write_items(items : &[Value], f : &mut fmt::Formatter) -> fmt::Result {
+        for (i, item) in items.iter().enumerate() {
+            if i != 0 {
+                write!(f, ", ")?;
+            }
+            write_repr(item, f)?;
+        }
+        Ok(())
+    }
+
+    match value {
+        Value::Integer(i) => write!(f, "{}", i),
+        Value::Bool(b) => write!(f, "{}", b),
+        Value::String(s) => write!(f, "{:?}", s),
+        Value::Char(c) => write!(f, "{:?}", c),
+        Value::List(items) => {
+            write!(f, "[")?;
+            write_items(items, f)?;
+            write!(f, "]")
+        },
+        Value::Tuple(items) => {
+            write!(f, "(")?;
+            write_items(items, f)?;
+            if items.len() == 1 {
+                write!(f, ",")?;
+            }
+            write!(f, ")")
+        },
+        Value::Record(fields) => {
+            write!(f, "{{ ")?;
+            for (i, (name, v)) in fields.iter().enumerate() {
+                if i != 0 {
+                    write!(f, ", ")?;
+                }
+                write!(f, "{}: ", name)?;
+                write_repr(v, f)?;
+            }
+            write!(f, " }}")
+        },
+        Value::Status(code) => write!(f, "{}", code),
+        Value::Closure(c) => match &c.name {
+            Some(name) => write!(f, "<fn {}>", name),
+            None => write!(f, "<fn>"),
+        },
+        Value::Compiled(c) => match &c.prototype.name {
+            Some(name) => write!(f, "<fn {}>", name),
+            None => write!(f, "<fn>"),
+        },
+        Value::Builtin(b) => write!(f, "<builtin {}>", b.name),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn display_should_not_quote_top_level_string() {
+        let v = Value::String("blah".to_string());
+
+        assert_eq!( v.to_string(), "blah" );
+    }
+
+    #[test]
+    fn display_should_quote_nested_strings() {
+        let v = Value::List(vec![ Value::String("a".to_string())
+                                , Value::Tuple(vec![Value::Char('b')])
+                                , Value::Record(vec![("x".to_string(), Value::Integer(1))])
+                                ]);
+
+        assert_eq!( v.to_string(), r#"["a", ('b',), { x: 1 }]"# );
+    }
+
+    #[test]
+    fn records_should_be_equal_regardless_of_field_order() {
+        let a = Value::Record(vec![("x".to_string(), Value::Integer(1)), ("y".to_string(), Value::Bool(true))]);
+        let b = Value::Record(vec![("y".to_string(), Value::Bool(true)), ("x".to_string(), Value::Integer(1))]);
+
+        assert_eq!( a, b );
+    }
+}
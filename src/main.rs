@@ -1,5 +1,397 @@
 mod parsing;
+mod eval;
+mod vm;
+mod diagnostic;
+mod editor;
+mod repl;
+
+use std::fs;
+use std::io::{self, IsTerminal, Read};
+use std::process;
+use std::rc::Rc;
+
+use parsing::dump::{self, Format};
+use parsing::grammar::{parse, parse_spanned, ParseError};
+use eval::{Evaluator, RuntimeError, Value};
+
+// A program ending with a command exits with its status, so ash's own failures
+// take sysexits codes that commands rarely use rather than 1 and 2
+const EXIT_SUCCESS : i32 = 0;
+const EXIT_USAGE : i32 = 64;
+const EXIT_PARSE_ERROR : i32 = 65;
+const EXIT_NO_INPUT : i32 = 66;
+const EXIT_RUNTIME_ERROR : i32 = 70;
+
+const USAGE : &str = "\
+usage: ash [repl]
+       ash [--vm] run <file>
+       ash [--vm] -e <expr>
+       ash check [<file> | -e <expr>]
+       ash parse [--dump=ast|sexpr|json|bytecode] [<file> | -e <expr>]
+       ash [--vm] < program.ash
+
+  repl    read and evaluate lines interactively, the default on a terminal
+          after running $ASH_INIT or ~/.config/ash/init.ash
+  run     run a file
+  -e      run an expression and print its value, unless it is unit or the
+          status of a command, which is the exit status instead
+  check   parse without running and report any errors
+  parse   print what the parser makes of a program, with the source positions
+          of each statement and of the variables, calls and commands in it,
+          or with --dump=bytecode what the compiler for --vm makes of it
+  --vm    run on the bytecode machine instead of the evaluator
+
+exit status is that of the last command when a program ends with one, else
+0 on success, 65 for a parse error, 66 for a missing file, 70 for a runtime
+error and 64 for bad arguments";
+
+#[derive(Debug, PartialEq)]
+enum Source {
+    File(String),
+    Expr(String),
+    Stdin,
+}
+
+#[derive(Debug, PartialEq)]
+enum Command {
+    Run(Source),
+    Check(Source),
+    Parse(Source, Format),
+    Disassemble(Source),
+    Repl,
+    Help,
+}
+
+#[derive(Debug, PartialEq)]
+struct Options {
+    command : Command,
+    vm : bool,
+}
 
 fn main() {
-    println!("Hello, world!");
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+
+    let options = match parse_args(&args) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("ash: {}\n{}", message, USAGE);
+            process::exit(EXIT_USAGE);
+        },
+    };
+
+    let code = std::thread::Builder::new()
+        .stack_size(eval::STACK_SIZE)
+        .spawn(move || execute(options))
+        .expect("main fails to spawn interpreter thread")
+        .join()
+        .unwrap_or(EXIT_RUNTIME_ERROR);
+
+    process::exit(code);
+}
+
+fn parse_args(args : &[String]) -> Result<Options, String> {
+    let mut vm = false;
+    let mut rest = args;
+
+    while let Some(arg) = rest.first() {
+        match arg.as_str() {
+            "--vm" => vm = true,
+            "-h" | "--help" => return Ok(Options { command: Command::Help, vm }),
+            _ => break,
+        }
+        rest = &rest[1..];
+    }
+
+    let command = match rest {
+        [] if io::stdin().is_terminal() => Command::Repl,
+        [repl] if repl == "repl" => Command::Repl,
+        [] => Command::Run(Source::Stdin),
+        [e, expr] if e == "-e" => Command::Run(Source::Expr(expr.clone())),
+        [run, file] if run == "run" => Command::Run(Source::File(file.clone())),
+        [check] if check == "check" => Command::Check(Source::Stdin),
+        [check, e, expr] if check == "check" && e == "-e" => Command::Check(Source::Expr(expr.clone())),
+        [check, file] if check == "check" => Command::Check(Source::File(file.clone())),
+        [parse, rest @ ..] if parse == "parse" => {
+            let (format, rest) = match rest.split_first() {
+                Some((dump, rest)) if dump.starts_with("--dump=") => {
+                    let name = &dump["--dump=".len()..];
+                    match Format::from_name(name) {
+                        Some(format) => (Some(format), rest),
+                        // bytecode isn't a view of the tree, it needs the compiler
+                        None if name == "bytecode" => (None, rest),
+                        None => return Err(format!("unknown dump format '{}'", name)),
+                    }
+                },
+                _ => (Some(Format::Ast), rest),
+            };
+            let source = match rest {
+                [] => Source::Stdin,
+                [e, expr] if e == "-e" => Source::Expr(expr.clone()),
+                [file] => Source::File(file.clone()),
+                [_, other, ..] => return Err(format!("unexpected argument '{}'", other)),
+            };
+            match format {
+                Some(format) => Command::Parse(source, format),
+                None => Command::Disassemble(source),
+            }
+        },
+        [e] if e == "-e" => return Err("-e needs an expression".to_string()),
+        [run] if run == "run" => return Err("run needs a file".to_string()),
+        [other, ..] => return Err(format!("unexpected argument '{}'", other)),
+    };
+
+    Ok(Options { command, vm })
+}
+
+fn execute(options : Options) -> i32 {
+    let (source, check) = match options.command {
+        Command::Parse(source, format) => return parse_only(source, format),
+        Command::Disassemble(source) => return disassemble(source),
+        Command::Help => {
+            println!("{}", USAGE);
+            return EXIT_SUCCESS;
+        },
+        Command::Run(source) => (source, false),
+        Command::Check(source) => (source, true),
+        Command::Repl => return repl::run(),
+    };
+
+    let print_result = matches!(source, Source::Expr(_));
+
+    let (name, text) = match read_source(source) {
+        Ok(named) => named,
+        Err(message) => {
+            eprintln!("ash: {}", message);
+            return EXIT_NO_INPUT;
+        },
+    };
+
+    let program = match parse(&text) {
+        Ok(program) => program,
+        Err(error) => {
+            eprint!("{}", parse_diagnostic(&name, &text, &error));
+            return EXIT_PARSE_ERROR;
+        },
+    };
+
+    if check {
+        return EXIT_SUCCESS;
+    }
+
+    let result = if options.vm {
+        match vm::compile(&program) {
+            Ok(script) => vm::Machine::new().run(Rc::new(script)),
+            Err(error) => {
+                eprint!("{}", diagnostic::render(&name, &text, error.span().start, &format!("compile error: {}", error)));
+                return EXIT_RUNTIME_ERROR;
+            },
+        }
+    }
+    else {
+        Evaluator::new().eval(&program)
+    };
+
+    match result {
+        // like other shells, a script that ends with a command exits with its status
+        Ok(Value::Status(code)) => code,
+        Err(RuntimeError::Exit { code, .. }) => code,
+        Ok(value) => {
+            if print_result && value != Value::unit() {
+                println!("{}", value);
+            }
+            EXIT_SUCCESS
+        },
+        Err(error) => {
+            eprint!("{}", runtime_diagnostic(&name, &text, &error));
+            EXIT_RUNTIME_ERROR
+        },
+    }
+}
+
+fn parse_only(source : Source, format : Format) -> i32 {
+    let (name, text) = match read_source(source) {
+        Ok(named) => named,
+        Err(message) => {
+            eprintln!("ash: {}", message);
+            return EXIT_NO_INPUT;
+        },
+    };
+
+    match parse_spanned(&text) {
+        Ok(program) => {
+            print!("{}", dump::dump(&text, &program, format));
+            EXIT_SUCCESS
+        },
+        Err(error) => {
+            eprint!("{}", parse_diagnostic(&name, &text, &error));
+            EXIT_PARSE_ERROR
+        },
+    }
+}
+
+fn disassemble(source : Source) -> i32 {
+    let (name, text) = match read_source(source) {
+        Ok(named) => named,
+        Err(message) => {
+            eprintln!("ash: {}", message);
+            return EXIT_NO_INPUT;
+        },
+    };
+
+    let program = match parse(&text) {
+        Ok(program) => program,
+        Err(error) => {
+            eprint!("{}", parse_diagnostic(&name, &text, &error));
+            return EXIT_PARSE_ERROR;
+        },
+    };
+
+    match vm::compile(&program) {
+        Ok(script) => {
+            print!("{}", vm::disassemble(&script));
+            EXIT_SUCCESS
+        },
+        Err(error) => {
+            eprint!("{}", diagnostic::render(&name, &text, error.span().start, &format!("compile error: {}", error)));
+            EXIT_RUNTIME_ERROR
+        },
+    }
+}
+
+fn read_source(source : Source) -> Result<(String, String), String> {
+    match source {
+        Source::File(path) => match fs::read_to_string(&path) {
+            Ok(text) => Ok((path, text)),
+            Err(e) => Err(format!("cannot read {}: {}", path, e)),
+        },
+        Source::Expr(text) => Ok(("<expr>".to_string(), text)),
+        Source::Stdin => {
+            let mut text = String::new();
+            match io::stdin().read_to_string(&mut text) {
+                Ok(_) => Ok(("<stdin>".to_string(), text)),
+                Err(e) => Err(format!("cannot read stdin: {}", e)),
+            }
+        },
+    }
+}
+
+fn parse_diagnostic(name : &str, source : &str, error : &ParseError) -> String {
+    if error.is_incomplete(source) {
+        // point just past the last thing written rather than at a trailing blank line
+        diagnostic::render(name, source, source.trim_end().len(), "parse error: unexpected end of input")
+    }
+    else {
+        diagnostic::render(name, source, error.index(), &error.message())
+    }
+}
+
+fn runtime_diagnostic(name : &str, source : &str, error : &RuntimeError) -> String {
+    diagnostic::render(name, source, error.span().start, &format!("runtime error: {}", error))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn args(s : &[&str]) -> Vec<String> {
+        s.iter().map(|a| a.to_string()).collect()
+    }
+
+    fn run_expr(s : &str) -> i32 {
+        execute(Options { command: Command::Run(Source::Expr(s.to_string())), vm: false })
+    }
+
+    #[test]
+    fn parse_args_should_parse_run() {
+        let options = parse_args(&args(&["run", "a.ash"]));
+
+        assert_eq!( options, Ok(Options { command: Command::Run(Source::File("a.ash".to_string())), vm: false }) );
+    }
+
+    #[test]
+    fn parse_args_should_parse_expr_with_vm() {
+        let options = parse_args(&args(&["--vm", "-e", "1"]));
+
+        assert_eq!( options, Ok(Options { command: Command::Run(Source::Expr("1".to_string())), vm: true }) );
+    }
+
+    #[test]
+    fn parse_args_should_parse_check() {
+        let file = parse_args(&args(&["check", "a.ash"]));
+        let expr = parse_args(&args(&["check", "-e", "1"]));
+
+        assert_eq!( file.map(|o| o.command), Ok(Command::Check(Source::File("a.ash".to_string()))) );
+        assert_eq!( expr.map(|o| o.command), Ok(Command::Check(Source::Expr("1".to_string()))) );
+    }
+
+    #[test]
+    fn parse_args_should_parse_dump_format() {
+        let default = parse_args(&args(&["parse", "a.ash"]));
+        let json = parse_args(&args(&["parse", "--dump=json", "-e", "1"]));
+        let bytecode = parse_args(&args(&["parse", "--dump=bytecode", "a.ash"]));
+        let unknown = parse_args(&args(&["parse", "--dump=xml", "a.ash"]));
+
+        assert_eq!( default.map(|o| o.command), Ok(Command::Parse(Source::File("a.ash".to_string()), Format::Ast)) );
+        assert_eq!( json.map(|o| o.command), Ok(Command::Parse(Source::Expr("1".to_string()), Format::Json)) );
+        assert_eq!( bytecode.map(|o| o.command), Ok(Command::Disassemble(Source::File("a.ash".to_string()))) );
+        assert!( unknown.is_err() );
+    }
+
+    #[test]
+    fn parse_args_should_parse_repl() {
+        let options = parse_args(&args(&["repl"]));
+
+        assert_eq!( options.map(|o| o.command), Ok(Command::Repl) );
+    }
+
+    #[test]
+    fn parse_args_should_reject_unknown_argument() {
+        let options = parse_args(&args(&["blah"]));
+
+        assert!( options.is_err() );
+    }
+
+    #[test]
+    fn execute_should_distinguish_exit_codes() {
+        assert_eq!( run_expr("add(1, 2)"), EXIT_SUCCESS );
+        assert_eq!( run_expr("add(1, "), EXIT_PARSE_ERROR );
+        assert_eq!( run_expr("add(1, true)"), EXIT_RUNTIME_ERROR );
+    }
+
+    #[test]
+    fn execute_should_exit_with_final_command_status() {
+        assert_eq!( run_expr("$ sh -c 'exit 5'"), 5 );
+    }
+
+    #[test]
+    fn execute_should_reject_commands_on_vm() {
+        let code = execute(Options { command: Command::Run(Source::Expr("$ true".to_string())), vm: true });
+
+        assert_eq!( code, EXIT_RUNTIME_ERROR );
+    }
+
+    #[test]
+    fn check_should_not_run_program() {
+        let code = execute(Options { command: Command::Check(Source::Expr("blah()".to_string())), vm: false });
+
+        assert_eq!( code, EXIT_SUCCESS );
+    }
+
+    #[test]
+    fn execute_should_report_missing_file() {
+        let code = execute(Options { command: Command::Run(Source::File("/does/not/exist.ash".to_string())), vm: false });
+
+        assert_eq!( code, EXIT_NO_INPUT );
+    }
+
+    #[test]
+    fn runtime_diagnostic_should_point_at_span() {
+        let source = "let x = 1;\nblah(x)";
+        let program = parse(source).unwrap();
+        let error = Evaluator::new().eval(&program).unwrap_err();
+
+        let output = runtime_diagnostic("t.ash", source, &error);
+
+        assert!( output.starts_with("t.ash:2:1: runtime error: unbound variable 'blah'\n") );
+    }
 }
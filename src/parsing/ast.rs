@@ -1,7 +1,106 @@
 
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Span {
+    pub start : usize,
+    pub end : usize,
+}
+
 #[derive(Debug, Clone)]
 pub enum Ast {
     Integer(i64),
     Bool(bool),
     String(String),
+    Char(char),
+    Interpolated(Vec<Segment>),
+    Variable(String, Span),
+    Let { name : String, value : Box<Ast>, doc : Option<String> },
+    Block(Vec<Ast>),
+    If { condition : Box<Ast>, then : Box<Ast>, otherwise : Option<Box<Ast>>, span : Span },
+    Match { subject : Box<Ast>, arms : Vec<MatchArm>, span : Span },
+    Function { name : String, params : Vec<String>, body : Box<Ast>, doc : Option<String> },
+    Lambda { params : Vec<String>, body : Box<Ast> },
+    Call { function : Box<Ast>, args : Vec<Argument>, span : Span },
+    List(Vec<Ast>),
+    Tuple(Vec<Ast>),
+    Record(Vec<(String, Ast)>),
+    Index { target : Box<Ast>, index : Box<Ast>, span : Span },
+    Field { target : Box<Ast>, name : String, span : Span },
+    // a background command is started as a job and not waited for
+    Command { stages : Vec<Stage>, background : bool, span : Span },
+    // '&&' and '||' give whichever operand decided them, as in other shells
+    And { left : Box<Ast>, right : Box<Ast>, span : Span },
+    Or { left : Box<Ast>, right : Box<Ast>, span : Span },
+    // the handler runs with name bound to a record describing the error
+    Try { body : Box<Ast>, name : String, handler : Box<Ast> },
+    // a structured substitution gives stdout, stderr and status rather than only stdout
+    Substitution { stages : Vec<Stage>, structured : bool, span : Span },
+    EnvVar(String, Span),
+    Export { name : String, value : Box<Ast> },
+    WithEnv { vars : Vec<(String, Ast)>, body : Box<Ast> },
+}
+
+// One command of a pipeline. A command on its own is a pipeline of one stage
+#[derive(Debug, Clone)]
+pub struct Stage {
+    pub words : Vec<Word>,
+    pub redirects : Vec<Redirect>,
+    pub span : Span,
+}
+
+#[derive(Debug, Clone)]
+pub enum Redirect {
+    // < file
+    Input(Word),
+    // > file
+    Output(Word),
+    // >> file
+    Append(Word),
+    // 2> file
+    Error(Word),
+    // 2>&1
+    ErrorToOutput,
+    // &> file
+    Both(Word),
+}
+
+// A command argument, made of the pieces that were written next to each other
+pub type Word = Vec<WordPart>;
+
+#[derive(Debug, Clone)]
+pub enum WordPart {
+    Bare(String),
+    // kept apart from bare text so that later expansions can leave it alone
+    Quoted(String),
+    Expr(Ast),
+    // a hole inside double quotes, which is one argument whatever its value
+    QuotedExpr(Ast),
+}
+
+#[derive(Debug, Clone)]
+pub enum Segment {
+    Literal(String),
+    Expr(Ast),
+}
+
+#[derive(Debug, Clone)]
+pub enum Argument {
+    Positional(Ast),
+    Named(String, Ast),
+}
+
+#[derive(Debug, Clone)]
+pub struct MatchArm {
+    pub pattern : Pattern,
+    pub guard : Option<Ast>,
+    pub body : Ast,
+}
+
+#[derive(Debug, Clone)]
+pub enum Pattern {
+    Literal(Ast),
+    Wildcard,
+    Bind(String),
+    List { items : Vec<Pattern>, rest : Option<Box<Pattern>> },
+    Tuple(Vec<Pattern>),
+    Record(Vec<(String, Pattern)>),
 }
\ No newline at end of file
@@ -0,0 +1,450 @@
+
+use std::fmt::Write;
+
+use crate::diagnostic::locate;
+use super::ast::{Ast, Argument, Pattern, Redirect, Segment, Span, Stage, Word, WordPart};
+
+// Printable views of a parsed program for finding out what the parser made of
+// a script. Spans are shown wherever the Ast has them, along with the span of
+// each top level statement.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Ast,
+    Sexpr,
+    Json,
+}
+
+impl Format {
+    pub fn from_name(name : &str) -> Option<Format> {
+        match name {
+            "ast" => Some(Format::Ast),
+            "sexpr" => Some(Format::Sexpr),
+            "json" => Some(Format::Json),
+            _ => None,
+        }
+    }
+}
+
+pub fn dump(source : &str, program : &[(Ast, Span)], format : Format) -> String {
+    let nodes = program.iter().map(|(statement, span)| {
+        let mut node = node(statement);
+        node.span = node.span.or(Some(*span));
+        node
+    }).collect::<Vec<_>>();
+
+    let mut out = String::new();
+    match format {
+        Format::Ast => {
+            for n in &nodes {
+                write_tree(source, None, n, 0, &mut out);
+            }
+        },
+        Format::Sexpr => {
+            for n in &nodes {
+                write_sexpr(source, n, &mut out);
+                out.push('\n');
+            }
+        },
+        Format::Json => {
+            out.push('[');
+            for (i, n) in nodes.iter().enumerate() {
+                if i != 0 {
+                    out.push(',');
+                }
+                write_json(source, n, &mut out);
+            }
+            out.push_str("]\n");
+        },
+    }
+    out
+}
+
+enum Attr {
+    // numbers and bools, which are written as they are
+    Raw(String),
+    Text(String),
+    Names(Vec<String>),
+}
+
+enum Child {
+    One(Node),
+    Many(Vec<Node>),
+}
+
+// Each format is written from this rather than from the Ast directly
+struct Node {
+    kind : &'static str,
+    span : Option<Span>,
+    attrs : Vec<(&'static str, Attr)>,
+    children : Vec<(&'static str, Child)>,
+}
+
+impl Node {
+    fn new(kind : &'static str) -> Node {
+        Node { kind, span: None, attrs: vec![], children: vec![] }
+    }
+
+    fn span(mut self, span : Span) -> Node {
+        self.span = Some(span);
+        self
+    }
+
+    fn attr(mut self, name : &'static str, value : Attr) -> Node {
+        self.attrs.push((name, value));
+        self
+    }
+
+    fn one(mut self, name : &'static str, child : Node) -> Node {
+        self.children.push((name, Child::One(child)));
+        self
+    }
+
+    fn many(mut self, name : &'static str, children : Vec<Node>) -> Node {
+        self.children.push((name, Child::Many(children)));
+        self
+    }
+}
+
+fn node(ast : &Ast) -> Node {
+    match ast {
+        Ast::Integer(i) => Node::new("integer").attr("value", Attr::Raw(i.to_string())),
+        Ast::Bool(b) => Node::new("bool").attr("value", Attr::Raw(b.to_string())),
+        Ast::String(s) => Node::new("string").attr("value", Attr::Text(s.clone())),
+        Ast::Char(c) => Node::new("char").attr("value", Attr::Text(c.to_string())),
+        Ast::Interpolated(segments) => Node::new("interpolated").many("segments", segments.iter().map(|segment| match segment {
+            Segment::Literal(text) => Node::new("literal").attr("value", Attr::Text(text.clone())),
+            Segment::Expr(e) => node(e),
+        }).collect()),
+        Ast::Variable(name, span) => Node::new("variable").span(*span).attr("name", Attr::Text(name.clone())),
+        Ast::Let { name, value, doc } => with_doc(Node::new("let").attr("name", Attr::Text(name.clone())), doc).one("value", node(value)),
+        Ast::Block(statements) => Node::new("block").many("statements", statements.iter().map(node).collect()),
+        Ast::If { condition, then, otherwise, span } => {
+            let n = Node::new("if").span(*span).one("condition", node(condition)).one("then", node(then));
+            match otherwise {
+                Some(otherwise) => n.one("otherwise", node(otherwise)),
+                None => n,
+            }
+        },
+        Ast::Match { subject, arms, span } => Node::new("match").span(*span).one("subject", node(subject)).many("arms", arms.iter().map(|arm| {
+            let n = Node::new("arm").one("pattern", pattern(&arm.pattern));
+            let n = match &arm.guard {
+                Some(guard) => n.one("guard", node(guard)),
+                None => n,
+            };
+            n.one("body", node(&arm.body))
+        }).collect()),
+        Ast::Function { name, params, body, doc } =>
+            with_doc(Node::new("fn").attr("name", Attr::Text(name.clone())), doc)
+                .attr("params", Attr::Names(params.clone()))
+                .one("body", node(body)),
+        Ast::Lambda { params, body } => Node::new("lambda").attr("params", Attr::Names(params.clone())).one("body", node(body)),
+        Ast::Call { function, args, span } => Node::new("call").span(*span).one("function", node(function)).many("args", args.iter().map(|arg| match arg {
+            Argument::Positional(e) => node(e),
+            Argument::Named(name, e) => Node::new("named").attr("name", Attr::Text(name.clone())).one("value", node(e)),
+        }).collect()),
+        Ast::List(items) => Node::new("list").many("items", items.iter().map(node).collect()),
+        Ast::Tuple(items) => Node::new("tuple").many("items", items.iter().map(node).collect()),
+        Ast::Record(fields) => Node::new("record").many("fields", fields.iter().map(|(name, value)|
+            Node::new("field").attr("name", Attr::Text(name.clone())).one("value", node(value))).collect()),
+        Ast::Index { target, index, span } => Node::new("index").span(*span).one("target", node(target)).one("index", node(index)),
+        Ast::Field { target, name, span } => Node::new("field").span(*span).attr("name", Attr::Text(name.clone())).one("target", node(target)),
+        Ast::Command { stages, background, span } => {
+            let n = Node::new("command").span(*span);
+            // only marked when set, since most commands are run in the foreground
+            let n = if *background { n.attr("background", Attr::Raw("true".to_string())) } else { n };
+            n.many("stages", stages.iter().map(stage).collect())
+        },
+        Ast::And { left, right, span } => Node::new("and").span(*span).one("left", node(left)).one("right", node(right)),
+        Ast::Or { left, right, span } => Node::new("or").span(*span).one("left", node(left)).one("right", node(right)),
+        Ast::Try { body, name, handler } => Node::new("try").attr("name", Attr::Text(name.clone()))
+            .one("body", node(body))
+            .one("handler", node(handler)),
+        Ast::Substitution { stages, structured, span } => Node::new("substitution").span(*span)
+            .attr("structured", Attr::Raw(structured.to_string()))
+            .many("stages", stages.iter().map(stage).collect()),
+        Ast::EnvVar(name, span) => Node::new("env").span(*span).attr("name", Attr::Text(name.clone())),
+        Ast::Export { name, value } => Node::new("export").attr("name", Attr::Text(name.clone())).one("value", node(value)),
+        Ast::WithEnv { vars, body } => Node::new("with_env").many("vars", vars.iter().map(|(name, value)|
+            Node::new("var").attr("name", Attr::Text(name.clone())).one("value", node(value))).collect())
+            .one("body", node(body)),
+    }
+}
+
+fn stage(s : &Stage) -> Node {
+    let n = Node::new("stage").span(s.span).many("words", s.words.iter().map(word).collect());
+    if s.redirects.is_empty() {
+        n
+    }
+    else {
+        n.many("redirects", s.redirects.iter().map(redirect).collect())
+    }
+}
+
+fn word(w : &Word) -> Node {
+    Node::new("word").many("parts", w.iter().map(|part| match part {
+        WordPart::Bare(text) => Node::new("bare").attr("value", Attr::Text(text.clone())),
+        WordPart::Quoted(text) => Node::new("quoted").attr("value", Attr::Text(text.clone())),
+        WordPart::Expr(e) => node(e),
+        WordPart::QuotedExpr(e) => Node::new("quoted_expr").one("value", node(e)),
+    }).collect())
+}
+
+fn redirect(r : &Redirect) -> Node {
+    let (op, target) = match r {
+        Redirect::Input(w) => ("<", Some(w)),
+        Redirect::Output(w) => (">", Some(w)),
+        Redirect::Append(w) => (">>", Some(w)),
+        Redirect::Error(w) => ("2>", Some(w)),
+        Redirect::ErrorToOutput => ("2>&1", None),
+        Redirect::Both(w) => ("&>", Some(w)),
+    };
+
+    let n = Node::new("redirect").attr("op", Attr::Text(op.to_string()));
+    match target {
+        Some(w) => n.one("target", word(w)),
+        None => n,
+    }
+}
+
+fn with_doc(n : Node, doc : &Option<String>) -> Node {
+    match doc {
+        Some(doc) => n.attr("doc", Attr::Text(doc.clone())),
+        None => n,
+    }
+}
+
+fn pattern(p : &Pattern) -> Node {
+    match p {
+        Pattern::Literal(ast) => node(ast),
+        Pattern::Wildcard => Node::new("wildcard"),
+        Pattern::Bind(name) => Node::new("bind").attr("name", Attr::Text(name.clone())),
+        Pattern::List { items, rest } => {
+            let n = Node::new("list_pattern").many("items", items.iter().map(pattern).collect());
+            match rest {
+                Some(rest) => n.one("rest", pattern(rest)),
+                None => n,
+            }
+        },
+        Pattern::Tuple(items) => Node::new("tuple_pattern").many("items", items.iter().map(pattern).collect()),
+        Pattern::Record(fields) => Node::new("record_pattern").many("fields", fields.iter().map(|(name, p)|
+            Node::new("field").attr("name", Attr::Text(name.clone())).one("pattern", pattern(p))).collect()),
+    }
+}
+
+fn position(source : &str, span : Span) -> String {
+    let start = locate(source, span.start);
+    let end = locate(source, span.end);
+    format!("{}:{}-{}:{}", start.line, start.column, end.line, end.column)
+}
+
+fn attr_text(attr : &Attr) -> String {
+    match attr {
+        Attr::Raw(raw) => raw.clone(),
+        Attr::Text(text) => format!("{:?}", text),
+        Attr::Names(names) => format!("[{}]", names.join(", ")),
+    }
+}
+
+fn write_tree(source : &str, label : Option<&str>, n : &Node, depth : usize, out : &mut String) {
+    out.push_str(&"  ".repeat(depth));
+    if let Some(label) = label {
+        write!(out, "{}: ", label).expect("write to String fails");
+    }
+    out.push_str(n.kind);
+    for (name, attr) in &n.attrs {
+        write!(out, " {}={}", name, attr_text(attr)).expect("write to String fails");
+    }
+    if let Some(span) = n.span {
+        write!(out, " @{}", position(source, span)).expect("write to String fails");
+    }
+    out.push('\n');
+
+    for (name, child) in &n.children {
+        match child {
+            Child::One(c) => write_tree(source, Some(name), c, depth + 1, out),
+            Child::Many(cs) => {
+                out.push_str(&"  ".repeat(depth + 1));
+                writeln!(out, "{}:", name).expect("write to String fails");
+                for c in cs {
+                    write_tree(source, None, c, depth + 2, out);
+                }
+            },
+        }
+    }
+}
+
+fn write_sexpr(source : &str, n : &Node, out : &mut String) {
+    write!(out, "({}", n.kind).expect("write to String fails");
+    for (name, attr) in &n.attrs {
+        write!(out, " :{} {}", name, attr_text(attr)).expect("write to String fails");
+    }
+    if let Some(span) = n.span {
+        write!(out, " @{}", position(source, span)).expect("write to String fails");
+    }
+    for (_, child) in &n.children {
+        match child {
+            Child::One(c) => {
+                out.push(' ');
+                write_sexpr(source, c, out);
+            },
+            Child::Many(cs) => {
+                out.push_str(" (");
+                for (i, c) in cs.iter().enumerate() {
+                    if i != 0 {
+                        out.push(' ');
+                    }
+                    write_sexpr(source, c, out);
+                }
+                out.push(')');
+            },
+        }
+    }
+    out.push(')');
+}
+
+fn json_string(s : &str, out : &mut String) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32).expect("write to String fails"),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+// Spans are given as byte offsets, and also as lines and columns
+fn write_json(source : &str, n : &Node, out : &mut String) {
+    out.push_str("{\"kind\":");
+    json_string(n.kind, out);
+
+    if let Some(span) = n.span {
+        let start = locate(source, span.start);
+        let end = locate(source, span.end);
+        write!( out, ",\"span\":{{\"start\":{},\"end\":{},\"start_line\":{},\"start_column\":{},\"end_line\":{},\"end_column\":{}}}"
+              , span.start, span.end, start.line, start.column, end.line, end.column
+              ).expect("write to String fails");
+    }
+
+    for (name, attr) in &n.attrs {
+        write!(out, ",\"{}\":", name).expect("write to String fails");
+        match attr {
+            Attr::Raw(raw) => out.push_str(raw),
+            Attr::Text(text) => json_string(text, out),
+            Attr::Names(names) => {
+                out.push('[');
+                for (i, name) in names.iter().enumerate() {
+                    if i != 0 {
+                        out.push(',');
+                    }
+                    json_string(name, out);
+                }
+                out.push(']');
+            },
+        }
+    }
+
+    for (name, child) in &n.children {
+        write!(out, ",\"{}\":", name).expect("write to String fails");
+        match child {
+            Child::One(c) => write_json(source, c, out),
+            Child::Many(cs) => {
+                out.push('[');
+                for (i, c) in cs.iter().enumerate() {
+                    if i != 0 {
+                        out.push(',');
+                    }
+                    write_json(source, c, out);
+                }
+                out.push(']');
+            },
+        }
+    }
+
+    out.push('}');
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::super::grammar::parse_spanned;
+
+    fn dump_str(s : &str, format : Format) -> String {
+        dump(s, &parse_spanned(s).expect("test program should parse"), format)
+    }
+
+    #[test]
+    fn ast_should_show_tree_with_positions() {
+        let output = dump_str("let x = 1;\nf(x, y = \"a\")", Format::Ast);
+
+        assert_eq!( output, "\
+let name=\"x\" @1:1-1:10
+  value: integer value=1
+call @2:1-2:14
+  function: variable name=\"f\" @2:1-2:2
+  args:
+    variable name=\"x\" @2:3-2:4
+    named name=\"y\"
+      value: string value=\"a\"
+" );
+    }
+
+    #[test]
+    fn ast_should_fall_back_to_statement_positions() {
+        let output = dump_str("let x = 1;\n{ let y = 2; y }", Format::Ast);
+
+        assert_eq!( output, "\
+let name=\"x\" @1:1-1:10
+  value: integer value=1
+block @2:1-2:17
+  statements:
+    let name=\"y\"
+      value: integer value=2
+    variable name=\"y\" @2:14-2:15
+" );
+    }
+
+    #[test]
+    fn sexpr_should_show_one_line_per_statement() {
+        let output = dump_str("fn f(a) { a[0] }\n[1, 'c']", Format::Sexpr);
+
+        assert_eq!( output, "\
+(fn :name \"f\" :params [a] @1:1-1:17 (block ((index @1:11-1:15 (variable :name \"a\" @1:11-1:12) (integer :value 0)))))
+(list @2:1-2:9 ((integer :value 1) (char :value \"c\")))
+" );
+    }
+
+    #[test]
+    fn sexpr_should_show_command_words() {
+        let output = dump_str("$ ls -l \"a ${x}\"", Format::Sexpr);
+
+        assert_eq!( output, "(command @1:1-1:17 ((stage @1:3-1:17 ((word ((bare :value \"ls\"))) (word ((bare :value \"-l\"))) (word ((quoted :value \"a \") (quoted_expr (variable :name \"x\" @1:14-1:15))))))))\n" );
+    }
+
+    #[test]
+    fn sexpr_should_show_redirects() {
+        let output = dump_str("$ make 2>&1 >log", Format::Sexpr);
+
+        assert_eq!( output, "(command @1:1-1:17 ((stage @1:3-1:17 ((word ((bare :value \"make\")))) ((redirect :op \"2>&1\") (redirect :op \">\" (word ((bare :value \"log\"))))))))\n" );
+    }
+
+    #[test]
+    fn json_should_escape_strings() {
+        let output = dump_str("\"a\\\"b\\n\"", Format::Json);
+
+        assert_eq!( output, "[{\"kind\":\"string\",\"span\":{\"start\":0,\"end\":8,\"start_line\":1,\"start_column\":1,\"end_line\":1,\"end_column\":9},\"value\":\"a\\\"b\\n\"}]\n" );
+    }
+
+    #[test]
+    fn json_should_include_patterns() {
+        let output = dump_str("match x { [a, ..] => a, _ => 0 }", Format::Json);
+
+        assert!( output.contains("{\"kind\":\"list_pattern\",\"items\":[{\"kind\":\"bind\",\"name\":\"a\"}],\"rest\":{\"kind\":\"wildcard\"}}") );
+    }
+}
@@ -1,10 +1,14 @@
 
-use super::parser::{Parser, bind, unit, exact, any, peek, the};
-use super::ast::Ast;
+use super::parser::{Parser, bind, unit, exact, any, peek, the, end, lazy};
+use super::input::Input;
+use super::output::Output;
+use super::ast::{Ast, Argument, MatchArm, Pattern, Redirect, Segment, Span, Stage, Word, WordPart};
     
 use monad::compute;
 
 
+pub const KEYWORDS : &[&str] = &[ "true", "false", "let", "if", "else", "match", "fn", "export", "with_env", "try", "catch" ];
+
 macro_rules! trim { 
     ($p : expr) => {
         compute!{ bind, unit => 
@@ -16,27 +20,565 @@ macro_rules! trim {
     };
 }
 
-pub fn parse() -> Vec<Ast> {
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseError {
+    Failure(usize),
+    Fatal(usize),
+    // something that parses but can't be what it says, like a number too big for an integer
+    Invalid { span : Span, reason : &'static str },
+}
+
+impl ParseError {
+    pub fn index(&self) -> usize {
+        match self {
+            ParseError::Failure(index) => *index,
+            ParseError::Fatal(index) => *index,
+            ParseError::Invalid { span, .. } => span.start,
+        }
+    }
+
+    pub fn message(&self) -> String {
+        match self {
+            ParseError::Invalid { reason, .. } => format!("parse error: {}", reason),
+            _ => "parse error".to_string(),
+        }
+    }
+
+    // Input that's been cut short, by an unclosed bracket or string for 
+    // instance, fails at its very end
+    pub fn is_incomplete(&self, source : &str) -> bool {
+        self.index() >= source.len()
+    }
+}
+
+pub fn parse(s : &str) -> Result<Vec<Ast>, ParseError> {
+    parse_spanned(s).map(|statements| statements.into_iter().map(|(statement, _)| statement).collect())
+}
+
+// Like parse, but also gives where each top level statement is in the source
+pub fn parse_spanned(s : &str) -> Result<Vec<(Ast, Span)>, ParseError> {
+    let mut input = Input::new(s);
+
+    let statements = match statements().parse(&mut input) {
+        Output::Success(ss, _, _) => ss,
+        Output::Failure(index) => return Err(ParseError::Failure(index)),
+        Output::Fatal(index) => return Err(fatal(&input, index)),
+    };
+
+    if let Output::Fatal(index) = junk().zero_or_more().parse(&mut input) {
+        return Err(fatal(&input, index));
+    }
+
+    match end().parse(&mut input) {
+        Output::Success(_, _, _) => Ok(statements),
+        // statements() stops quietly at the first thing it can't parse, so reparse 
+        // that statement to find out where it actually went wrong
+        Output::Failure(index) => match statement().parse(&mut input) {
+            Output::Success(_, _, _) => Err(ParseError::Failure(index)),
+            Output::Failure(index) => Err(ParseError::Failure(index)),
+            Output::Fatal(index) => Err(fatal(&input, index)),
+        },
+        Output::Fatal(index) => Err(fatal(&input, index)),
+    }
+}
+
+fn fatal(input : &Input, index : usize) -> ParseError {
+    match input.reason() {
+        Some((start, end, reason)) => ParseError::Invalid { span: Span { start, end }, reason },
+        None => ParseError::Fatal(index),
+    }
+}
+
+fn statements() -> Parser<Vec<(Ast, Span)>> {
+    // ';' separates statements, but it's optional after anything that ends with a '}'
+    // and after commands, which end with their line
+    Parser::new(|input| {
+        let mut items = vec![];
+
+        loop {
+            let rp = input.restore_point();
+
+            if let Output::Fatal(index) = junk().zero_or_more().parse(input) {
+                return Output::Fatal(index);
+            }
+
+            let start = input.index();
+
+            match statement().parse(input) {
+                Output::Success(s, _, _) => {
+                    let end = input.token_end();
+
+                    let separated = match punct(";").parse(input) {
+                        Output::Success(_, _, _) => true,
+                        Output::Failure(_) => false,
+                        Output::Fatal(index) => return Output::Fatal(index),
+                    };
+                    let block_like = is_block_like(&s);
+
+                    items.push((s, Span { start, end }));
+
+                    if !separated && !block_like {
+                        break;
+                    }
+                },
+                Output::Failure(_) => { input.restore(rp); break },
+                Output::Fatal(index) => return Output::Fatal(index),
+            }
+        }
+
+        // doc comments with no statement after them, before a '}' or the end, are
+        // only comments
+        let token_end = input.token_end();
+        if let Output::Fatal(index) = doc_comments().parse(input) {
+            return Output::Fatal(index);
+        }
+        input.set_token_end(token_end);
+
+        Output::Success(items, 0, 0)
+    })
+}
+
+fn is_block_like(ast : &Ast) -> bool {
+    match ast {
+        Ast::Let { value, .. } | Ast::Export { value, .. } => ends_with_command(value),
+        _ => ends_with_command(ast) 
+          || matches!(ast, Ast::Block(_) 
+                         | Ast::If { .. } 
+                         | Ast::Match { .. } 
+                         | Ast::Function { .. } 
+                         | Ast::Lambda { .. }
+                         | Ast::WithEnv { .. }
+                         | Ast::Try { .. }),
+    }
+}
+
+// a command ends with its line, and so does anything that finishes with one
+fn ends_with_command(ast : &Ast) -> bool {
+    match ast {
+        Ast::Command { .. } => true,
+        Ast::And { right, .. } | Ast::Or { right, .. } => ends_with_command(right),
+        _ => false,
+    }
+}
+
+fn statement() -> Parser<Ast> {
+    compute!{ bind, unit =>
+        doc <- doc_comments();
+        s <- let_statement().or(export_statement()).or(function_definition()).or(expr());
+        unit with_doc(s, doc.clone())
+    }
+}
+
+fn with_doc(ast : Ast, doc : Option<String>) -> Ast {
+    match ast {
+        Ast::Let { name, value, .. } => Ast::Let { name, value, doc },
+        Ast::Function { name, params, body, .. } => Ast::Function { name, params, body, doc },
+        other => other,
+    }
+}
+
+fn let_statement() -> Parser<Ast> {
+    compute!{ bind, unit =>
+        _let <- key("let");
+        name <- trim!( identifier() ).fatal().followed_by(punct("=").fatal());
+        value <- expr().fatal();
+        unit Ast::Let { name: name.clone(), value: Box::new(value), doc: None }
+    }
+}
+
+fn export_statement() -> Parser<Ast> {
+    compute!{ bind, unit =>
+        _export <- key("export");
+        name <- trim!( env_name() ).fatal().followed_by(punct("=").fatal());
+        value <- expr().fatal();
+        unit Ast::Export { name: name.clone(), value: Box::new(value) }
+    }
+}
+
+fn function_definition() -> Parser<Ast> {
+    compute!{ bind, unit =>
+        _fn <- key("fn");
+        name <- trim!( identifier() );
+        f <- function_tail().fatal();
+        unit Ast::Function { name: name.clone(), params: f.0, body: Box::new(f.1), doc: None }
+    }
+}
+
+fn lambda() -> Parser<Ast> {
+    compute!{ bind, unit =>
+        _fn <- key("fn");
+        f <- function_tail().fatal();
+        unit Ast::Lambda { params: f.0, body: Box::new(f.1) }
+    }
+}
+
+fn function_tail() -> Parser<(Vec<String>, Ast)> {
+    compute!{ bind, unit =>
+        _open <- punct("(");
+        params <- trim!( identifier() ).sep_by(punct(",")).followed_by(punct(")").fatal());
+        body <- block().fatal();
+        unit (params.clone(), body)
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Suffix {
+    Call(Vec<Argument>),
+    Index(Ast),
+    Field(String),
+}
+
+// '&&' and '||' share a precedence and group from the left, as in other shells
+fn expr() -> Parser<Ast> {
+    fn operand() -> Parser<Ast> {
+        trim!( compute!{ bind, unit =>
+            target <- spanned(primary());
+            // '{ .. } (x)' is two statements rather than a call
+            suffixes <- if is_block_like(&target.0) { unit(vec![]) } else { spanned(suffix()).zero_or_more() };
+            unit suffixes.into_iter().fold(target.clone(), apply_suffix).0
+        } )
+    }
+
+    fn operation() -> Parser<((bool, Span), Ast)> {
+        compute!{ bind, unit =>
+            and <- spanned(punct("&&").map(|_| true).or(punct("||").map(|_| false)));
+            right <- operand().fatal();
+            unit (and, right)
+        }
+    }
+
+    compute!{ bind, unit =>
+        first <- operand();
+        rest <- operation().zero_or_more();
+        unit rest.into_iter().fold(first.clone(), logical)
+    }
+}
+
+fn logical(left : Ast, ((and, span), right) : ((bool, Span), Ast)) -> Ast {
+    if and {
+        Ast::And { left: Box::new(left), right: Box::new(right), span }
+    }
+    else {
+        Ast::Or { left: Box::new(left), right: Box::new(right), span }
+    }
+}
+
+fn primary() -> Parser<Ast> {
+    number_literal()
+        .or(bool_literal())
+        .or(multi_line_string_literal())
+        .or(string_literal())
+        .or(raw_string_literal())
+        .or(char_literal())
+        .or(substitution())
+        .or(env_var())
+        .or(command())
+        .or(if_expr())
+        .or(with_env())
+        .or(try_expr())
+        .or(match_expr())
+        .or(lambda())
+        .or(variable())
+        .or(list_literal())
+        .or(tuple_literal())
+        .or(record_literal())
+        .or(block())
+}
+
+fn list_literal() -> Parser<Ast> {
+    compute!{ bind, unit =>
+        _open <- punct("[");
+        items <- lazy(expr).sep_by(punct(",")).followed_by(punct("]").fatal());
+        unit Ast::List(items)
+    }
+}
+
+fn tuple_literal() -> Parser<Ast> {
+    // a single expression in parens is only grouping, '(e,)' is the one item tuple.
+    // the first item is parsed once whichever it turns out to be, as parsing it
+    // again for each would double the time taken at every level of nesting
+    fn rest() -> Parser<Vec<Ast>> {
+        compute!{ bind, unit =>
+            _comma <- punct(",");
+            rest <- lazy(expr).sep_by(punct(","));
+            unit rest
+        }
+    }
+
+    let contents = || compute!{ bind, unit =>
+        first <- expr();
+        rest <- rest().maybe();
+        unit match rest {
+            Some(rest) => Ast::Tuple(std::iter::once(first.clone()).chain(rest).collect()),
+            None => first.clone(),
+        }
+    };
+
+    compute!{ bind, unit =>
+        _open <- punct("(");
+        contents <- contents().maybe().followed_by(punct(")").fatal());
+        unit contents.unwrap_or(Ast::Tuple(vec![]))
+    }
+}
+
+fn record_literal() -> Parser<Ast> {
+    // needs at least one 'name:' to tell it apart from a block, so '{}' is an empty block
+    let field = || compute!{ bind, unit =>
+        name <- trim!( identifier() ).followed_by(punct(":"));
+        value <- expr().fatal();
+        unit (name.clone(), value)
+    };
+
+    compute!{ bind, unit =>
+        _open <- punct("{");
+        fields <- field().sep_by(punct(","))
+                         .when(|fields| !fields.is_empty())
+                         .followed_by(punct("}").fatal());
+        unit Ast::Record(fields)
+    }
+}
+
+fn apply_suffix((target, target_span) : (Ast, Span), (suffix, suffix_span) : (Suffix, Span)) -> (Ast, Span) {
+    let span = Span { start: target_span.start, end: suffix_span.end };
+    let target = Box::new(target);
+
+    let ast = match suffix {
+        Suffix::Call(args) => Ast::Call { function: target, args, span },
+        Suffix::Index(index) => Ast::Index { target, index: Box::new(index), span },
+        Suffix::Field(name) => Ast::Field { target, name, span },
+    };
+
+    (ast, span)
+}
+
+fn suffix() -> Parser<Suffix> {
+    call_suffix()
+        .or(index_suffix())
+        .or(field_suffix())
+}
+
+fn index_suffix() -> Parser<Suffix> {
+    compute!{ bind, unit =>
+        _open <- punct("[");
+        index <- expr().fatal().followed_by(punct("]").fatal());
+        unit Suffix::Index(index)
+    }
+}
+
+fn field_suffix() -> Parser<Suffix> {
+    // 't.0' indexes into a tuple
+    let position = || number_literal().map(Suffix::Index);
+    let name = || identifier().map(Suffix::Field);
+
+    compute!{ bind, unit =>
+        _dot <- punct(".");
+        s <- trim!( position().or(name()) ).fatal();
+        unit s
+    }
+}
+
+fn call_suffix() -> Parser<Suffix> {
+    compute!{ bind, unit =>
+        _open <- punct("(");
+        args <- argument().sep_by(punct(",")).followed_by(punct(")").fatal());
+        unit Suffix::Call(args)
+    }
+}
+
+fn argument() -> Parser<Argument> {
+    let named = || compute!{ bind, unit =>
+        name <- trim!( identifier() ).followed_by(punct("="));
+        value <- expr().fatal();
+        unit Argument::Named(name.clone(), value)
+    };
+
+    named().or(lazy(expr).map(Argument::Positional))
+}
+
+fn if_expr() -> Parser<Ast> {
+    let else_branch = || compute!{ bind, unit =>
+        _else <- key("else");
+        otherwise <- block().or(lazy(if_expr)).fatal();
+        unit otherwise
+    };
+
+    let p = compute!{ bind, unit =>
+        _if <- key("if");
+        head <- compute!{ bind, unit => 
+            condition <- expr().fatal();
+            then <- block().fatal();
+            unit (condition.clone(), then)
+        };
+        otherwise <- else_branch().maybe();
+        unit (head.0.clone(), head.1.clone(), otherwise)
+    };
+
+    spanned(p).map(|((condition, then, otherwise), span)| 
+        Ast::If { condition: Box::new(condition)
+                , then: Box::new(then)
+                , otherwise: otherwise.map(Box::new)
+                , span
+                })
+}
+
+fn try_expr() -> Parser<Ast> {
+    let handler = || compute!{ bind, unit =>
+        _catch <- key("catch");
+        name <- trim!( identifier() ).fatal();
+        handler <- block().fatal();
+        unit (name.clone(), handler)
+    };
+
+    compute!{ bind, unit =>
+        _try <- key("try");
+        body <- block().fatal();
+        handler <- handler().fatal();
+        unit Ast::Try { body: Box::new(body.clone()), name: handler.0, handler: Box::new(handler.1) }
+    }
+}
+
+fn match_expr() -> Parser<Ast> {
+    let p = compute!{ bind, unit =>
+        _match <- key("match");
+        subject <- expr().fatal().followed_by(punct("{").fatal());
+        arms <- match_arm().sep_by(punct(",")).followed_by(punct("}").fatal());
+        unit (subject.clone(), arms)
+    };
+
+    spanned(p).map(|((subject, arms), span)| Ast::Match { subject: Box::new(subject), arms, span })
+}
+
+fn match_arm() -> Parser<MatchArm> {
+    let guard = || compute!{ bind, unit =>
+        _if <- key("if");
+        guard <- expr().fatal();
+        unit guard
+    };
+
+    compute!{ bind, unit =>
+        head <- compute!{ bind, unit =>
+            pattern <- pattern();
+            guard <- guard().maybe();
+            unit (pattern.clone(), guard)
+        }.followed_by(punct("=>").fatal());
+        body <- expr().fatal();
+        unit MatchArm { pattern: head.0.clone(), guard: head.1.clone(), body }
+    }
+}
+
+fn pattern() -> Parser<Pattern> {
+    trim!( number_literal().map(Pattern::Literal)
+        .or(bool_literal().map(Pattern::Literal))
+        .or(string_literal().when(|s| matches!(s, Ast::String(_))).map(Pattern::Literal))
+        .or(char_literal().map(Pattern::Literal))
+        .or(wildcard_pattern())
+        .or(identifier().map(Pattern::Bind))
+        .or(list_pattern())
+        .or(tuple_pattern())
+        .or(record_pattern()) )
+}
+
+fn wildcard_pattern() -> Parser<Pattern> {
+    compute!{ bind, unit =>
+        _underscore <- the('_');
+        _i <- not_sym_char();
+        unit Pattern::Wildcard
+    }
+}
+
+fn list_pattern() -> Parser<Pattern> {
+    let rest = || compute!{ bind, unit =>
+        _dots <- punct("..");
+        rest <- wildcard_pattern().or(identifier().map(Pattern::Bind)).maybe();
+        unit rest.unwrap_or(Pattern::Wildcard)
+    };
+
+    compute!{ bind, unit =>
+        _open <- punct("[");
+        items <- pattern().sep_by(punct(","));
+        rest <- rest().maybe().followed_by(punct("]").fatal());
+        unit Pattern::List { items: items.clone(), rest: rest.map(Box::new) }
+    }
+}
+
+fn tuple_pattern() -> Parser<Pattern> {
+    // a single pattern in parens is only grouping, '(p,)' is the one item tuple,
+    // and like tuple_literal the first pattern is only parsed once
+    fn rest() -> Parser<Vec<Pattern>> {
+        compute!{ bind, unit =>
+            _comma <- punct(",");
+            rest <- pattern().sep_by(punct(","));
+            unit rest
+        }
+    }
+
+    let contents = || compute!{ bind, unit =>
+        first <- pattern();
+        rest <- rest().maybe();
+        unit match rest {
+            Some(rest) => Pattern::Tuple(std::iter::once(first.clone()).chain(rest).collect()),
+            None => first.clone(),
+        }
+    };
+
+    compute!{ bind, unit =>
+        _open <- punct("(");
+        contents <- contents().maybe().followed_by(punct(")").fatal());
+        unit contents.unwrap_or(Pattern::Tuple(vec![]))
+    }
+}
+
+fn record_pattern() -> Parser<Pattern> {
+    // '{ name }' is shorthand for '{ name: name }'
+    let field = || compute!{ bind, unit =>
+        name <- trim!( identifier() );
+        p <- compute!{ bind, unit =>
+            _colon <- punct(":");
+            p <- pattern().fatal();
+            unit p
+        }.maybe();
+        unit (name.clone(), p.unwrap_or_else(|| Pattern::Bind(name.clone())))
+    };
+
+    compute!{ bind, unit =>
+        _open <- punct("{");
+        fields <- field().sep_by(punct(",")).followed_by(punct("}").fatal());
+        unit Pattern::Record(fields)
+    }
+}
 
-    vec![]
+fn block() -> Parser<Ast> {
+    compute!{ bind, unit =>
+        _open <- punct("{");
+        body <- statements().followed_by(punct("}").fatal());
+        unit Ast::Block(body.iter().map(|(statement, _)| statement.clone()).collect())
+    }
 }
 
 fn number_literal() -> Parser<Ast> {
     // TODO also need to handle floats
     // TODO also need to handle negative
     // TODO also need to handle sci notation
-    let p = any().when(|d| d.is_digit(10) ).one_or_more();
-    
-    p.map(|ds| Ast::Integer(
-        ds.into_iter()
-            .collect::<String>()
-            .parse::<i64>()
-            .expect("Parsed integer fails parse::<i64>()")))
-} 
+    let p = any().when(|d| d.is_ascii_digit() ).one_or_more();
+
+    // digits are only ever a number, so too many for an i64 is an error
+    Parser::new(move |input| {
+        let start = input.index();
+        match p.parse(input) {
+            Output::Success(ds, from, to) => match ds.into_iter().collect::<String>().parse::<i64>() {
+                Ok(value) => Output::Success(Ast::Integer(value), from, to),
+                Err(_) => {
+                    input.give_reason(start, input.index(), "integer literal out of range");
+                    Output::Fatal(start)
+                },
+            },
+            Output::Failure(index) => Output::Failure(index),
+            Output::Fatal(index) => Output::Fatal(index),
+        }
+    })
+}
 
 fn bool_literal() -> Parser<Ast> {
-    let not_sym_char = || peek().when(|c| !c.is_digit(10) && *c != '_' && !c.is_alphabetic());
-       
     let p = compute!{ bind, unit => 
         v <- exact("true").or(exact("false"));
         _i <- not_sym_char();
@@ -46,140 +588,1656 @@ fn bool_literal() -> Parser<Ast> {
     p.map(|b| Ast::Bool(b.parse::<bool>().expect("Parsed bool fails parse::<bool>()")))
 }
 
-/*
-  private Parser<Expr> StrParser() {
-            static Parser<char> EscapeParser() 
-                => (from slash in Expect("\\")
-                   from other in Expect("t")
-                                 .Or(Expect("n"))
-                                 .Or(Expect("r"))
-                                 .Or(Expect("\\"))
-                                 .Or(Expect("\""))
-                   select other).Select( c => c switch {
-                       "t" => '\t',
-                       "n" => '\n',
-                       "r" => '\r',
-                       "\\" => '\\',
-                       "\"" => '"',
-                       _ => throw new Exception("Impossible escape character encountered"),
-                   });
-
-            static Parser<char> NotQuote() 
-                => from c in Any()
-                   where c != '"'
-                   select c;
-
-            return (from q1 in DoubleQuote() 
-                   from cs in EscapeParser().Or(NotQuote()).ZeroOrMore()
-                   from q2 in DoubleQuote() 
-                   select new Str(new string(cs.ToArray())) as Expr).Trim();
-        }
-*/
+#[derive(Debug, Clone)]
+enum StringPart {
+    Char(char),
+    Hole(Ast),
+}
 
-fn string_literal() -> Parser<Ast> {
+fn escape() -> Parser<char> {
+    // shared by string and char literals
+    let simple = || the('n').or(the('r'))
+                            .or(the('t'))
+                            .or(the('0'))
+                            .or(the('\\'))
+                            .or(the('"'))
+                            .or(the('\''))
+                            .or(the('$'))
+                            .map(|c| match c {
+                                'n' => '\n',
+                                'r' => '\r',
+                                't' => '\t',
+                                '0' => '\0',
+                                other => other,
+                            });
 
-    fn escape_parser() -> Parser<char> {
-        compute!{bind, unit => 
-            _slash <- the('\\');
-            other <- the('n').or(the('r'))
-                             .or(the('t'))
-                             .or(the('\\'))
-                             .or(the('"'));
-            unit other
-        }
+    compute!{bind, unit => 
+        _slash <- the('\\');
+        other <- unicode_escape().or(simple()).fatal();
+        unit other
     }
-
-    unit(Ast::String("blarg".to_string()))
 }
 
-fn key(s : &'static str) -> Parser<&'static str> {
-    let not_sym_char = || peek().when(|c| !c.is_digit(10) && *c != '_' && !c.is_alphabetic());
+fn unicode_escape() -> Parser<char> {
+    let p = compute!{bind, unit =>
+        _u <- exact("u{");
+        digits <- any().when(|c| c.is_ascii_hexdigit()).one_or_more().followed_by(the('}')).fatal();
+        unit digits.into_iter().collect::<String>()
+    };
 
-    trim!( compute!{bind, unit => 
-        _keyword <- exact(s);
-        _i <- not_sym_char();
-        unit s
-    } )
-}
+    fn to_char(digits : &str) -> Option<char> {
+        u32::from_str_radix(digits, 16).ok().and_then(std::char::from_u32)
+    }
 
-fn punct(s : &'static str) -> Parser<&'static str> {
-    trim!( exact(s) )
+    p.when(|digits| to_char(digits).is_some())
+     .map(|digits| to_char(&digits).expect("Parsed unicode escape fails char::from_u32()"))
 }
 
-fn junk() -> Parser<()> {
-    any().when(|c| c.is_whitespace()).map(|_x| ())
-}
+fn char_literal() -> Parser<Ast> {
+    let not_quote = || any().when(|c| *c != '\'' && *c != '\\' && *c != '\n');
 
-#[cfg(test)]
-mod test {
-    use super::*;
-    use super::super::output::Output;
-    use super::super::input::Input;
-    use monad::compute;
+    let p = compute!{bind, unit =>
+        _q1 <- the('\'');
+        c <- escape().or(not_quote()).fatal().followed_by(the('\'').fatal());
+        unit c
+    };
 
-    #[test]
-    fn key_parser_should_fail_illegitimate_target() {
-        let p = key("blah");
-        let mut input = Input::new("  blahx  ");
+    p.map(Ast::Char)
+}
 
-        let v = p.parse(&mut input);
+fn string_literal() -> Parser<Ast> {
 
-        assert!(matches!( v, Output::Failure(_)));
+    fn hole() -> Parser<StringPart> {
+        compute!{bind, unit =>
+            _open <- exact("${");
+            e <- expr().fatal().followed_by(exact("}").fatal());
+            unit StringPart::Hole(e)
+        }
     }
 
-    #[test]
-    fn key_parser_should_parse_legit_target() {
-        let p = key("blah");
-        let mut input = Input::new("  blah  ");
+    let not_quote = || any().when(|c| *c != '"' && *c != '\\');
 
-        let v = p.parse(&mut input);
+    let p = compute!{bind, unit =>
+        _q1 <- the('"');
+        parts <- hole()
+                    .or(escape().map(StringPart::Char))
+                    .or(not_quote().map(StringPart::Char))
+                    .zero_or_more()
+                    .followed_by(the('"').fatal());
+        unit parts
+    };
 
-        assert!(matches!( v, Output::Success(_, _, _)));
-    }
+    p.map(string_from_parts)
+}
 
-    #[test]
-    fn number_literal_should_parse_positive_value() {
-        let p = number_literal();
-        let mut input = Input::new("1234");
+fn string_from_parts(parts : Vec<StringPart>) -> Ast {
+    let mut segments = vec![];
+    let mut literal = String::new();
 
-        let v = p.parse(&mut input);
+    for part in parts {
+        match part {
+            StringPart::Char(c) => literal.push(c),
+            StringPart::Hole(e) => {
+                if !literal.is_empty() {
+                    segments.push(Segment::Literal(std::mem::take(&mut literal)));
+                }
+                segments.push(Segment::Expr(e));
+            },
+        }
+    }
 
-        assert!(matches!( v, Output::Success(Ast::Integer(1234), _, _)));
+    if segments.is_empty() {
+        return Ast::String(literal);
     }
 
-    #[test]
-    fn bool_literal_should_parse_true() {
-        let p = bool_literal();
-        let mut input = Input::new("true ");
+    if !literal.is_empty() {
+        segments.push(Segment::Literal(literal));
+    }
 
-        let v = p.parse(&mut input);
+    Ast::Interpolated(segments)
+}
 
-        match v {
-            Output::Success(Ast::Bool(b), _, _) => assert_eq!( b, true ),
-            it @ _ => panic!( "unexpected output: {:?}", it ),
+fn raw_string_literal() -> Parser<Ast> {
+    // r"..." or r#"..."#, where the number of '#' can grow until the closing 
+    // sequence doesn't appear in the text
+    Parser::new(|input| {
+        let rp = input.restore_point();
+
+        let start = match input.exact("r") {
+            Ok((start, _, _)) => start,
+            Err(index) => return Output::Failure(index),
+        };
+
+        let mut closing = String::from("\"");
+        while input.exact("#").is_ok() {
+            closing.push('#');
         }
-    }
 
-    #[test]
-    fn bool_literal_should_parse_false() {
-        let p = bool_literal();
-        let mut input = Input::new("false ");
+        if let Err(index) = input.exact("\"") {
+            input.restore(rp);
+            return Output::Failure(index);
+        }
 
-        let v = p.parse(&mut input);
+        let mut text = String::new();
+        loop {
+            if let Ok((_, end, _)) = input.exact(&closing) {
+                return Output::Success(Ast::String(text), start, end);
+            }
 
-        match v {
-            Output::Success(Ast::Bool(b), _, _) => assert_eq!( b, false ),
-            it @ _ => panic!( "unexpected output: {:?}", it ),
+            match input.get_char() {
+                Ok((_, c)) => text.push(c),
+                Err(index) => return Output::Fatal(index),
+            }
         }
-    }
+    })
+}
 
-    #[test]
-    fn bool_literal_should_not_parse_symbol_starting_with_bool() {
-        let p = bool_literal();
-        let mut input = Input::new("falsey");
+fn multi_line_string_literal() -> Parser<Ast> {
+    // """ starts a heredoc style string on the next line, and the text is taken 
+    // as is apart from removing the indentation common to all of its lines
+    Parser::new(|input| {
+        let rp = input.restore_point();
 
-        let v = p.parse(&mut input);
+        let start = match input.exact("\"\"\"") {
+            Ok((start, _, _)) => start,
+            Err(index) => return Output::Failure(index),
+        };
 
-        assert!(matches!(v, Output::Failure(_)));
+        let _ = input.take_while(|c| c == ' ' || c == '\t' || c == '\r');
+
+        if let Err(index) = input.exact("\n") {
+            input.restore(rp);
+            return Output::Failure(index);
+        }
+
+        let mut text = String::new();
+        loop {
+            if let Ok((_, end, _)) = input.exact("\"\"\"") {
+                return Output::Success(Ast::String(strip_indentation(&text)), start, end);
+            }
+
+            match input.get_char() {
+                Ok((_, c)) => text.push(c),
+                Err(index) => return Output::Fatal(index),
+            }
+        }
+    })
+}
+
+fn strip_indentation(text : &str) -> String {
+    let mut lines = text.lines().map(|line| line.trim_end_matches('\r')).collect::<Vec<_>>();
+
+    // the closing """ is usually on its own line
+    if text.ends_with('\n') {
+        lines.push("");
+    }
+    if lines.last().is_some_and(|line| line.trim().is_empty()) {
+        lines.pop();
+    }
+
+    let indent = lines.iter()
+                      .filter(|line| !line.trim().is_empty())
+                      .map(|line| line.len() - line.trim_start().len())
+                      .min()
+                      .unwrap_or(0);
+
+    lines.into_iter()
+         .map(|line| line.get(indent..).unwrap_or_else(|| line.trim_start()))
+         .collect::<Vec<_>>()
+         .join("\n")
+}
+
+fn variable() -> Parser<Ast> {
+    spanned(identifier()).map(|(name, span)| Ast::Variable(name, span))
+}
+
+fn identifier() -> Parser<String> {
+    let p = compute!{ bind, unit =>
+        first <- any().when(|c| *c == '_' || c.is_alphabetic());
+        rest <- any().when(|c| is_sym_char(*c)).zero_or_more();
+        unit std::iter::once(first).chain(rest.into_iter()).collect::<String>()
+    };
+
+    p.when(|name| !KEYWORDS.contains(&name.as_str()))
+}
+
+// Environment variable names are kept to what other shells accept
+fn env_name() -> Parser<String> {
+    compute!{ bind, unit =>
+        first <- any().when(|c| *c == '_' || c.is_ascii_alphabetic());
+        rest <- any().when(|c| *c == '_' || c.is_ascii_alphanumeric()).zero_or_more();
+        unit std::iter::once(first).chain(rest.clone()).collect::<String>()
+    }
+}
+
+fn env_var() -> Parser<Ast> {
+    let p = compute!{ bind, unit =>
+        _dollar <- the('$');
+        name <- env_name();
+        unit name
+    };
+
+    spanned(p).map(|(name, span)| Ast::EnvVar(name, span))
+}
+
+// 'with_env(NAME = value, ...) { }' sets variables for the block, and any exports 
+// made inside of it are undone when it ends
+fn with_env() -> Parser<Ast> {
+    fn binding() -> Parser<(String, Ast)> {
+        compute!{ bind, unit =>
+            name <- trim!( env_name() ).followed_by(punct("=").fatal());
+            value <- expr().fatal();
+            unit (name.clone(), value)
+        }
+    }
+
+    fn bindings() -> Parser<Vec<(String, Ast)>> {
+        compute!{ bind, unit =>
+            _open <- punct("(");
+            vars <- binding().sep_by(punct(",")).followed_by(punct(")").fatal());
+            unit vars
+        }
+    }
+
+    compute!{ bind, unit =>
+        _with <- key("with_env");
+        vars <- bindings().maybe();
+        body <- block().fatal();
+        unit Ast::WithEnv { vars: vars.clone().unwrap_or_default(), body: Box::new(body) }
+    }
+}
+
+// a trailing '&' runs the command in the background, but '&&' is left alone
+fn command() -> Parser<Ast> {
+    fn background() -> Parser<bool> {
+        compute!{ bind, unit =>
+            _amp <- the('&');
+            _not_and <- peek().when(|c| *c != '&').map(|_| ()).or(end());
+            _blanks <- blanks().maybe();
+            unit true
+        }
+    }
+
+    // 'a && b' carries on the command line, where b can leave out its '$'
+    fn chained() -> Parser<((bool, Span), Ast)> {
+        compute!{ bind, unit =>
+            and <- spanned(exact("&&").map(|_| true).or(exact("||").map(|_| false)));
+            _space <- any().when(|c| c.is_whitespace()).zero_or_more();
+            _dollar <- the('$').followed_by(blanks()).maybe();
+            pipeline <- spanned(pipeline()).fatal();
+            unit (and, Ast::Command { stages: pipeline.0, background: false, span: pipeline.1 })
+        }
+    }
+
+    // only a lone pipeline can be put in the background
+    let p = compute!{ bind, unit =>
+        _dollar <- the('$').followed_by(blanks());
+        head <- compute!{ bind, unit =>
+            stages <- pipeline().fatal();
+            rest <- chained().zero_or_more();
+            unit (stages.clone(), rest)
+        };
+        background <- if head.1.is_empty() { background().maybe() } else { unit(None) };
+        unit (head.0.clone(), head.1.clone(), background.unwrap_or(false))
+    };
+
+    spanned(p).map(|((stages, rest, background), span)| 
+        rest.into_iter().fold(Ast::Command { stages, background, span }, logical))
+}
+
+// '$(cmd)' gives the command's output and '$?(cmd)' a record of its output, 
+// errors and status. Unlike a '$' command these can be broken across lines
+fn substitution() -> Parser<Ast> {
+    let whitespace = || any().when(|c| c.is_whitespace()).zero_or_more();
+
+    let p = compute!{ bind, unit =>
+        structured <- exact("$(").map(|_| false).or(exact("$?(").map(|_| true));
+        _ws <- whitespace();
+        stages <- pipeline().fatal().followed_by(whitespace()).followed_by(the(')').fatal());
+        unit (stages.clone(), structured)
+    };
+
+    spanned(p).map(|((stages, structured), span)| Ast::Substitution { stages, structured, span })
+}
+
+// a command runs to the end of its line, so only spaces and tabs separate 
+// its words. A '\' at the end of a line continues the command onto the next
+fn blanks() -> Parser<Vec<()>> {
+    any().when(|c| *c == ' ' || *c == '\t').map(|_| ())
+        .or(exact("\\\n").map(|_| ()))
+        .one_or_more()
+}
+
+// a pipeline can be broken across lines after a '|'
+fn pipeline() -> Parser<Vec<Stage>> {
+    fn pipe() -> Parser<Stage> {
+        compute!{ bind, unit =>
+            _bar <- the('|');
+            _not_or <- peek().when(|c| *c != '|').map(|_| ()).or(end());
+            _space <- any().when(|c| c.is_whitespace()).zero_or_more();
+            s <- stage().fatal();
+            unit s
+        }
+    }
+
+    compute!{ bind, unit =>
+        first <- stage();
+        rest <- pipe().zero_or_more();
+        unit std::iter::once(first.clone()).chain(rest).collect::<Vec<_>>()
+    }
+}
+
+// redirections can go anywhere amongst the words, but at least one word is needed
+fn stage() -> Parser<Stage> {
+    let item = redirect().map(Err::<Word, Redirect>).or(word().map(Ok));
+
+    let p = item.followed_by(blanks().maybe()).one_or_more()
+                .when(|items| items.iter().any(|item| item.is_ok()));
+
+    spanned(p).map(|(items, span)| {
+        let (words, redirects) : (Vec<_>, Vec<_>) = items.into_iter().partition(|item| item.is_ok());
+        Stage { words: words.into_iter().filter_map(Result::ok).collect()
+              , redirects: redirects.into_iter().filter_map(Result::err).collect()
+              , span
+              }
+    })
+}
+
+fn redirect() -> Parser<Redirect> {
+    fn target(op : &'static str, f : fn(Word) -> Redirect) -> Parser<Redirect> {
+        compute!{ bind, unit =>
+            _op <- exact(op);
+            _blanks <- any().when(|c| *c == ' ' || *c == '\t').zero_or_more();
+            w <- word().fatal();
+            unit f(w.clone())
+        }
+    }
+
+    // longer operators are tried first so that '2>&1' isn't taken as '2>' to a file named '&1'
+    exact("2>&1").map(|_| Redirect::ErrorToOutput)
+        .or(target("2>", Redirect::Error))
+        .or(target("&>", Redirect::Both))
+        .or(target(">>", Redirect::Append))
+        .or(target(">", Redirect::Output))
+        .or(target("<", Redirect::Input))
+}
+
+// Characters that can't be written unquoted in a command word. Most of them
+// are kept back for pipes, redirections and substitutions
+fn is_word_char(c : char) -> bool {
+    !c.is_whitespace() && !"|&;<>()\"'\\$".contains(c)
+}
+
+fn word() -> Parser<Word> {
+
+    fn bare() -> Parser<Vec<WordPart>> {
+        any().when(|c| is_word_char(*c)).one_or_more()
+            .map(|cs| vec![WordPart::Bare(cs.into_iter().collect())])
+    }
+
+    fn escaped() -> Parser<Vec<WordPart>> {
+        compute!{ bind, unit =>
+            _slash <- the('\\');
+            c <- any().fatal();
+            unit vec![WordPart::Quoted(c.to_string())]
+        }
+    }
+
+    fn single_quoted() -> Parser<Vec<WordPart>> {
+        compute!{ bind, unit =>
+            _q1 <- the('\'');
+            cs <- any().when(|c| *c != '\'').zero_or_more().followed_by(the('\'').fatal());
+            unit vec![WordPart::Quoted(cs.into_iter().collect())]
+        }
+    }
+
+    fn double_quoted() -> Parser<Vec<WordPart>> {
+        fn parts(ast : Ast) -> Option<Vec<WordPart>> {
+            match ast {
+                Ast::String(s) => Some(vec![WordPart::Quoted(s)]),
+                Ast::Interpolated(segments) => Some(segments.into_iter().map(|segment| match segment {
+                    Segment::Literal(s) => WordPart::Quoted(s),
+                    Segment::Expr(e) => WordPart::QuotedExpr(e),
+                }).collect()),
+                _ => None,
+            }
+        }
+
+        string_literal().map(parts)
+                        .when(Option::is_some)
+                        .map(Option::unwrap_or_default)
+    }
+
+    fn hole() -> Parser<Vec<WordPart>> {
+        compute!{ bind, unit =>
+            _open <- exact("${");
+            e <- expr().fatal().followed_by(exact("}").fatal());
+            unit vec![WordPart::Expr(e)]
+        }
+    }
+
+    fn substituted() -> Parser<Vec<WordPart>> {
+        lazy(substitution).map(|e| vec![WordPart::Expr(e)])
+    }
+
+    fn variable() -> Parser<Vec<WordPart>> {
+        env_var().map(|e| vec![WordPart::Expr(e)])
+    }
+
+    // a '$' that isn't starting a hole is just a '$'
+    fn dollar() -> Parser<Vec<WordPart>> {
+        compute!{ bind, unit =>
+            _dollar <- the('$');
+            _not_hole <- peek().when(|c| *c != '{').map(|_| ()).or(end());
+            unit vec![WordPart::Bare("$".to_string())]
+        }
+    }
+
+    // '#' and '}' only end a command at the start of a word, so that arguments like 
+    // 'a#b' and '{}' can be written as they would be in other shells. A '{' on its
+    // own ends one too, for the block after 'if $ test -f x'
+    let p = compute!{ bind, unit =>
+        _start <- peek().when(|c| *c != '#' && *c != '}');
+        _not_block <- not_lone_brace();
+        parts <- bare().or(escaped()).or(single_quoted()).or(double_quoted()).or(hole()).or(substituted()).or(variable()).or(dollar()).one_or_more();
+        unit parts
+    };
+
+    p.map(merge_word_parts)
+}
+
+// takes nothing, and fails if what's next is a '{' followed by whitespace
+fn not_lone_brace() -> Parser<()> {
+    Parser::new(|input| {
+        let rp = input.restore_point();
+        let index = input.index();
+
+        let lone = matches!(input.get_char(), Ok((_, '{')))
+                && input.peek().map_or(true, |(_, c)| c.is_whitespace());
+
+        input.restore(rp);
+        if lone { Output::Failure(index) } else { Output::Success((), index, index) }
+    })
+}
+
+fn merge_word_parts(parts : Vec<Vec<WordPart>>) -> Word {
+    let mut word : Word = vec![];
+
+    for part in parts.into_iter().flatten() {
+        match (word.last_mut(), part) {
+            (Some(WordPart::Bare(last)), WordPart::Bare(s)) => last.push_str(&s),
+            (Some(WordPart::Quoted(last)), WordPart::Quoted(s)) => last.push_str(&s),
+            (_, part) => word.push(part),
+        }
+    }
+
+    word
+}
+
+fn spanned<T : 'static + Clone>(p : Parser<T>) -> Parser<(T, Span)> {
+    Parser::new(move |input| {
+        let start = input.index();
+        match p.parse(input) {
+            // spans stop at the end of the last token rather than after any junk that follows it
+            Output::Success(v, s, e) => Output::Success((v, Span { start, end: input.token_end().max(start) }), s, e),
+            Output::Failure(index) => Output::Failure(index),
+            Output::Fatal(index) => Output::Fatal(index),
+        }
+    })
+}
+
+fn is_sym_char(c : char) -> bool {
+    c.is_ascii_digit() || c == '_' || c.is_alphabetic()
+}
+
+fn not_sym_char() -> Parser<()> {
+    peek().when(|c| !is_sym_char(*c)).map(|_| ()).or(end())
+}
+
+fn key(s : &'static str) -> Parser<&'static str> {
+    trim!( compute!{bind, unit => 
+        _keyword <- exact(s);
+        _i <- not_sym_char();
+        unit s
+    } )
+}
+
+fn punct(s : &'static str) -> Parser<&'static str> {
+    trim!( exact(s) )
+}
+
+fn junk() -> Parser<()> {
+    let p = any().when(|c| c.is_whitespace()).map(|_x| ())
+        .or(line_comment())
+        .or(block_comment());
+
+    // skipping junk leaves the input's token end where it was, so that spans don't cover it
+    Parser::new(move |input| {
+        let token_end = input.token_end();
+        let output = p.parse(input);
+        input.set_token_end(token_end);
+        output
+    })
+}
+
+fn line_comment() -> Parser<()> {
+    // '##' starting a line is a doc comment and '#[' opens a block comment,
+    // neither of which are junk. A '##' after code only comments on that code
+    Parser::new(|input| {
+        let rp = input.restore_point();
+        let line_start = input.at_line_start();
+
+        let start = match input.exact("#") {
+            Ok((start, _, _)) => start,
+            Err(index) => return Output::Failure(index),
+        };
+
+        match input.peek() {
+            Ok((index, '#')) if line_start => { input.restore(rp); Output::Failure(index) },
+            Ok((index, '[')) => { input.restore(rp); Output::Failure(index) },
+            Ok(_) => match input.take_while(|c| c != '\n') {
+                Ok((_, end, _)) => Output::Success((), start, end),
+                Err(index) => Output::Success((), start, index),
+            },
+            Err(index) => Output::Success((), start, index),
+        }
+    })
+}
+
+fn block_comment() -> Parser<()> {
+    Parser::new(|input| {
+        let start = match input.exact("#[") {
+            Ok((start, _, _)) => start,
+            Err(index) => return Output::Failure(index),
+        };
+
+        let mut depth = 1;
+        loop {
+            if let Ok((_, end, _)) = input.exact("]#") {
+                depth -= 1;
+                if depth == 0 {
+                    return Output::Success((), start, end);
+                }
+            }
+            else if input.exact("#[").is_ok() {
+                depth += 1;
+            }
+            else if let Err(end) = input.get_char() {
+                // An unterminated block comment would otherwise silently swallow the rest of the file.
+                // It fails at the end of the input, like an unterminated string, so that the repl
+                // knows to ask for more
+                return Output::Fatal(end);
+            }
+        }
+    })
+}
+
+fn doc_comment() -> Parser<String> {
+    let p = compute!{ bind, unit =>
+        _hashes <- exact("##");
+        _space <- the(' ').maybe();
+        text <- any().when(|c| *c != '\n').zero_or_more();
+        unit text
+    };
+
+    p.map(|cs| cs.into_iter().collect::<String>().trim_end().to_string())
+}
+
+fn doc_comments() -> Parser<Option<String>> {
+    trim!( doc_comment() ).zero_or_more()
+        .map(|lines| if lines.is_empty() { None } else { Some(lines.join("\n")) })
+}
+
+#[cfg(test)]
+// the tests keep their original assertion style
+#[allow(clippy::redundant_pattern, clippy::bool_assert_comparison)]
+mod test {
+    use super::*;
+    use super::super::output::Output;
+    use super::super::input::Input;
+    use monad::compute;
+
+    #[test]
+    fn key_parser_should_fail_illegitimate_target() {
+        let p = key("blah");
+        let mut input = Input::new("  blahx  ");
+
+        let v = p.parse(&mut input);
+
+        assert!(matches!( v, Output::Failure(_)));
+    }
+
+    #[test]
+    fn key_parser_should_parse_legit_target() {
+        let p = key("blah");
+        let mut input = Input::new("  blah  ");
+
+        let v = p.parse(&mut input);
+
+        assert!(matches!( v, Output::Success(_, _, _)));
+    }
+
+    #[test]
+    fn number_literal_should_parse_positive_value() {
+        let p = number_literal();
+        let mut input = Input::new("1234");
+
+        let v = p.parse(&mut input);
+
+        assert!(matches!( v, Output::Success(Ast::Integer(1234), _, _)));
+    }
+
+    #[test]
+    fn number_literal_should_fail_on_overflow() {
+        let p = number_literal();
+        let mut input = Input::new("99999999999999999999");
+
+        let v = p.parse(&mut input);
+
+        assert!(matches!( v, Output::Fatal(0) ));
+        assert_eq!( parse("let x = 99999999999999999999;").err(), Some(ParseError::Invalid { span: Span { start: 8, end: 28 }, reason: "integer literal out of range" }) );
+        assert!(matches!( parse("9223372036854775807").as_deref(), Ok([Ast::Integer(i64::MAX)]) ));
+    }
+
+    #[test]
+    fn bool_literal_should_parse_true() {
+        let p = bool_literal();
+        let mut input = Input::new("true ");
+
+        let v = p.parse(&mut input);
+
+        match v {
+            Output::Success(Ast::Bool(b), _, _) => assert_eq!( b, true ),
+            it @ _ => panic!( "unexpected output: {:?}", it ),
+        }
+    }
+
+    #[test]
+    fn bool_literal_should_parse_false() {
+        let p = bool_literal();
+        let mut input = Input::new("false ");
+
+        let v = p.parse(&mut input);
+
+        match v {
+            Output::Success(Ast::Bool(b), _, _) => assert_eq!( b, false ),
+            it @ _ => panic!( "unexpected output: {:?}", it ),
+        }
+    }
+
+    #[test]
+    fn bool_literal_should_not_parse_symbol_starting_with_bool() {
+        let p = bool_literal();
+        let mut input = Input::new("falsey");
+
+        let v = p.parse(&mut input);
+
+        assert!(matches!(v, Output::Failure(_)));
+    }
+
+    #[test]
+    fn key_parser_should_parse_target_at_end_of_input() {
+        let p = key("blah");
+        let mut input = Input::new("  blah");
+
+        let v = p.parse(&mut input);
+
+        assert!(matches!( v, Output::Success(_, _, _)));
+    }
+
+    #[test]
+    fn identifier_should_parse_symbol_chars() {
+        let p = identifier();
+        let mut input = Input::new("_blah_1 ");
+
+        let v = p.parse(&mut input);
+
+        match v {
+            Output::Success(name, _, _) => assert_eq!( name, "_blah_1" ),
+            it @ _ => panic!( "unexpected output: {:?}", it ),
+        }
+    }
+
+    #[test]
+    fn identifier_should_not_parse_leading_digit() {
+        let p = identifier();
+        let mut input = Input::new("1blah");
+
+        let v = p.parse(&mut input);
+
+        assert!(matches!( v, Output::Failure(_)));
+    }
+
+    #[test]
+    fn identifier_should_not_parse_keyword() {
+        let p = identifier();
+        let mut input = Input::new("true");
+
+        let v = p.parse(&mut input);
+
+        assert!(matches!( v, Output::Failure(_)));
+    }
+
+    #[test]
+    fn identifier_should_parse_symbol_starting_with_keyword() {
+        let p = identifier();
+        let mut input = Input::new("truey");
+
+        let v = p.parse(&mut input);
+
+        match v {
+            Output::Success(name, _, _) => assert_eq!( name, "truey" ),
+            it @ _ => panic!( "unexpected output: {:?}", it ),
+        }
+    }
+
+    #[test]
+    fn variable_should_parse_identifier() {
+        let p = variable();
+        let mut input = Input::new("blah");
+
+        let v = p.parse(&mut input);
+
+        match v {
+            Output::Success(Ast::Variable(name, span), _, _) => {
+                assert_eq!( name, "blah" );
+                assert_eq!( span, Span { start: 0, end: 4 } );
+            },
+            it @ _ => panic!( "unexpected output: {:?}", it ),
+        }
+    }
+
+    #[test]
+    fn let_statement_should_parse() {
+        let p = let_statement();
+        let mut input = Input::new("let x = 5");
+
+        let v = p.parse(&mut input);
+
+        match v {
+            Output::Success(Ast::Let { name, value, doc: None }, _, _) => {
+                assert_eq!( name, "x" );
+                assert!(matches!( *value, Ast::Integer(5) ));
+            },
+            it @ _ => panic!( "unexpected output: {:?}", it ),
+        }
+    }
+
+    #[test]
+    fn let_statement_should_fatal_on_missing_equals() {
+        let p = let_statement();
+        let mut input = Input::new("let x 5");
+
+        let v = p.parse(&mut input);
+
+        assert!(matches!( v, Output::Fatal(6)));
+    }
+
+    #[test]
+    fn statement_should_attach_doc_comment_to_let() {
+        let p = statement();
+        let mut input = Input::new("## the answer\nlet x = 42");
+
+        let v = p.parse(&mut input);
+
+        match v {
+            Output::Success(Ast::Let { doc: Some(doc), .. }, _, _) => assert_eq!( doc, "the answer" ),
+            it @ _ => panic!( "unexpected output: {:?}", it ),
+        }
+    }
+
+    #[test]
+    fn block_should_parse_statements_and_final_expression() {
+        let p = block();
+        let mut input = Input::new("{ let x = 1; let y = x; y }");
+
+        let v = p.parse(&mut input);
+
+        match v {
+            Output::Success(Ast::Block(ss), _, _) => {
+                assert_eq!( ss.len(), 3 );
+                assert!(matches!( &ss[0], Ast::Let { .. } ));
+                assert!(matches!( &ss[1], Ast::Let { .. } ));
+                assert!(matches!( &ss[2], Ast::Variable(name, _) if name == "y" ));
+            },
+            it @ _ => panic!( "unexpected output: {:?}", it ),
+        }
+    }
+
+    #[test]
+    fn block_should_not_need_separator_after_nested_block() {
+        let p = block();
+        let mut input = Input::new("{ { 1 } 2 }");
+
+        let v = p.parse(&mut input);
+
+        assert!(matches!( v, Output::Success(Ast::Block(ss), _, _) if ss.len() == 2 ));
+    }
+
+    #[test]
+    fn block_should_fatal_on_missing_separator() {
+        let p = block();
+        let mut input = Input::new("{ 1 2 }");
+
+        let v = p.parse(&mut input);
+
+        assert!(matches!( v, Output::Fatal(4)));
+    }
+
+    #[test]
+    fn if_expr_should_parse_else_if_chain() {
+        let p = if_expr();
+        let mut input = Input::new("if a { 1 } else if b { 2 } else { 3 }");
+
+        let v = p.parse(&mut input);
+
+        match v {
+            Output::Success(Ast::If { condition, otherwise: Some(otherwise), .. }, _, _) => {
+                assert!(matches!( *condition, Ast::Variable(name, _) if name == "a" ));
+                assert!(matches!( *otherwise, Ast::If { otherwise: Some(_), .. } ));
+            },
+            it @ _ => panic!( "unexpected output: {:?}", it ),
+        }
+    }
+
+    #[test]
+    fn if_expr_should_parse_without_else() {
+        let p = if_expr();
+        let mut input = Input::new("if true { 1 }");
+
+        let v = p.parse(&mut input);
+
+        assert!(matches!( v, Output::Success(Ast::If { otherwise: None, .. }, _, _) ));
+    }
+
+    #[test]
+    fn if_expr_should_fatal_on_missing_block() {
+        let p = if_expr();
+        let mut input = Input::new("if true 1");
+
+        let v = p.parse(&mut input);
+
+        assert!(matches!( v, Output::Fatal(8) ));
+    }
+
+    #[test]
+    fn match_expr_should_parse_arms() {
+        let p = match_expr();
+        let mut input = Input::new("match x { 1 => a, y if y => b, _ => { c }, }");
+
+        let v = p.parse(&mut input);
+
+        match v {
+            Output::Success(Ast::Match { arms, .. }, _, _) => {
+                assert_eq!( arms.len(), 3 );
+                assert!(matches!( arms[0].pattern, Pattern::Literal(Ast::Integer(1)) ));
+                assert!(matches!( &arms[1].pattern, Pattern::Bind(name) if name == "y" ));
+                assert!(matches!( arms[1].guard, Some(Ast::Variable(_, _)) ));
+                assert!(matches!( arms[2].pattern, Pattern::Wildcard ));
+                assert!(matches!( arms[2].body, Ast::Block(_) ));
+            },
+            it @ _ => panic!( "unexpected output: {:?}", it ),
+        }
+    }
+
+    #[test]
+    fn pattern_should_parse_nested_structures() {
+        let p = pattern();
+        let mut input = Input::new("{ name, value: ([a, _, ..rest], (b,), (c)) }");
+
+        let v = p.parse(&mut input);
+
+        match v {
+            Output::Success(Pattern::Record(fields), _, _) => {
+                assert!(matches!( &fields[0], (n, Pattern::Bind(b)) if n == "name" && b == "name" ));
+                match &fields[1] {
+                    (_, Pattern::Tuple(items)) => {
+                        assert!(matches!( &items[0], Pattern::List { items, rest: Some(_) } if items.len() == 2 ));
+                        assert!(matches!( &items[1], Pattern::Tuple(t) if t.len() == 1 ));
+                        assert!(matches!( &items[2], Pattern::Bind(_) ));
+                    },
+                    it @ _ => panic!( "unexpected field: {:?}", it ),
+                }
+            },
+            it @ _ => panic!( "unexpected output: {:?}", it ),
+        }
+    }
+
+    #[test]
+    fn statement_should_parse_function_definition_with_doc() {
+        let p = statement();
+        let mut input = Input::new("## adds things\nfn add(a, b,) { a }");
+
+        let v = p.parse(&mut input);
+
+        match v {
+            Output::Success(Ast::Function { name, params, doc: Some(doc), .. }, _, _) => {
+                assert_eq!( name, "add" );
+                assert_eq!( params, vec!["a", "b"] );
+                assert_eq!( doc, "adds things" );
+            },
+            it @ _ => panic!( "unexpected output: {:?}", it ),
+        }
+    }
+
+    #[test]
+    fn expr_should_parse_lambda() {
+        let p = expr();
+        let mut input = Input::new("fn(x) { x }");
+
+        let v = p.parse(&mut input);
+
+        assert!(matches!( v, Output::Success(Ast::Lambda { params, .. }, _, _) if params == vec!["x"] ));
+    }
+
+    #[test]
+    fn expr_should_parse_call_with_named_arguments() {
+        let p = expr();
+        let mut input = Input::new("f(1, flag = true)");
+
+        let v = p.parse(&mut input);
+
+        match v {
+            Output::Success(Ast::Call { function, args, .. }, _, _) => {
+                assert!(matches!( *function, Ast::Variable(name, _) if name == "f" ));
+                assert!(matches!( args[0], Argument::Positional(Ast::Integer(1)) ));
+                assert!(matches!( &args[1], Argument::Named(name, Ast::Bool(true)) if name == "flag" ));
+            },
+            it @ _ => panic!( "unexpected output: {:?}", it ),
+        }
+    }
+
+    #[test]
+    fn expr_should_parse_chained_calls() {
+        let p = expr();
+        let mut input = Input::new("f(1)()");
+
+        let v = p.parse(&mut input);
+
+        match v {
+            Output::Success(Ast::Call { function, args, .. }, _, _) => {
+                assert!( args.is_empty() );
+                assert!(matches!( *function, Ast::Call { .. } ));
+            },
+            it @ _ => panic!( "unexpected output: {:?}", it ),
+        }
+    }
+
+    #[test]
+    fn expr_should_fatal_on_unclosed_call() {
+        let p = expr();
+        let mut input = Input::new("f(1, 2");
+
+        let v = p.parse(&mut input);
+
+        assert!(matches!( v, Output::Fatal(6) ));
+    }
+
+    #[test]
+    fn expr_should_span_suffixes_from_start_of_target() {
+        let p = expr();
+        let mut input = Input::new("  f(1).x");
+
+        let v = p.parse(&mut input);
+
+        match v {
+            Output::Success(Ast::Field { target, span, .. }, _, _) => {
+                assert_eq!( span, Span { start: 2, end: 8 } );
+                assert!(matches!( *target, Ast::Call { span: Span { start: 2, end: 6 }, .. } ));
+            },
+            it @ _ => panic!( "unexpected output: {:?}", it ),
+        }
+    }
+
+    #[test]
+    fn expr_should_parse_list_with_trailing_comma() {
+        let p = expr();
+        let mut input = Input::new("[1, [2], 3,]");
+
+        let v = p.parse(&mut input);
+
+        match v {
+            Output::Success(Ast::List(items), _, _) => {
+                assert_eq!( items.len(), 3 );
+                assert!(matches!( &items[1], Ast::List(inner) if inner.len() == 1 ));
+            },
+            it @ _ => panic!( "unexpected output: {:?}", it ),
+        }
+    }
+
+    #[test]
+    fn expr_should_parse_tuples_and_grouping() {
+        let p = expr();
+
+        let mut input = Input::new("(1, 2)");
+        assert!(matches!( p.parse(&mut input), Output::Success(Ast::Tuple(items), _, _) if items.len() == 2 ));
+
+        let mut input = Input::new("(1,)");
+        assert!(matches!( p.parse(&mut input), Output::Success(Ast::Tuple(items), _, _) if items.len() == 1 ));
+
+        let mut input = Input::new("()");
+        assert!(matches!( p.parse(&mut input), Output::Success(Ast::Tuple(items), _, _) if items.is_empty() ));
+
+        let mut input = Input::new("(1)");
+        assert!(matches!( p.parse(&mut input), Output::Success(Ast::Integer(1), _, _) ));
+    }
+
+    #[test]
+    fn parse_should_not_backtrack_over_nested_parens() {
+        let depth = 40;
+        let grouped = format!("{}1{}", "(".repeat(depth), ")".repeat(depth));
+        let tuples = format!("{}1{}", "(".repeat(depth), ",)".repeat(depth));
+        let patterns = format!("match x {{ {}a{} => a }}", "(".repeat(depth), ",)".repeat(depth));
+        let start = std::time::Instant::now();
+
+        assert!(matches!( parse(&grouped).as_deref(), Ok([Ast::Integer(1)]) ));
+        assert!(matches!( parse(&tuples).as_deref(), Ok([Ast::Tuple(_)]) ));
+        assert!(matches!( parse(&patterns).as_deref(), Ok([Ast::Match { .. }]) ));
+        assert!( start.elapsed() < std::time::Duration::from_secs(5) );
+    }
+
+    #[test]
+    fn expr_should_parse_record() {
+        let p = expr();
+        let mut input = Input::new("{ name: x, age: 5, }");
+
+        let v = p.parse(&mut input);
+
+        match v {
+            Output::Success(Ast::Record(fields), _, _) => {
+                assert_eq!( fields.len(), 2 );
+                assert_eq!( fields[0].0, "name" );
+                assert!(matches!( fields[1], (_, Ast::Integer(5)) ));
+            },
+            it @ _ => panic!( "unexpected output: {:?}", it ),
+        }
+    }
+
+    #[test]
+    fn expr_should_parse_empty_braces_as_block() {
+        let p = expr();
+        let mut input = Input::new("{}");
+
+        let v = p.parse(&mut input);
+
+        assert!(matches!( v, Output::Success(Ast::Block(ss), _, _) if ss.is_empty() ));
+    }
+
+    #[test]
+    fn expr_should_parse_index_and_field_access() {
+        let p = expr();
+        let mut input = Input::new("config.servers[0].1");
+
+        let v = p.parse(&mut input);
+
+        match v {
+            Output::Success(Ast::Index { target, index, .. }, _, _) => {
+                assert!(matches!( *index, Ast::Integer(1) ));
+                match *target {
+                    Ast::Index { target, .. } => assert!(matches!( *target, Ast::Field { name, .. } if name == "servers" )),
+                    it @ _ => panic!( "unexpected target: {:?}", it ),
+                }
+            },
+            it @ _ => panic!( "unexpected output: {:?}", it ),
+        }
+    }
+
+    #[test]
+    fn string_literal_should_parse_escapes() {
+        let p = string_literal();
+        let mut input = Input::new(r#""a\tb\n\"c\" \$"rest"#);
+
+        let v = p.parse(&mut input);
+
+        match v {
+            Output::Success(Ast::String(s), _, _) => assert_eq!( s, "a\tb\n\"c\" $" ),
+            it @ _ => panic!( "unexpected output: {:?}", it ),
+        }
+    }
+
+    #[test]
+    fn string_literal_should_parse_empty_string() {
+        let p = string_literal();
+        let mut input = Input::new(r#""""#);
+
+        let v = p.parse(&mut input);
+
+        assert!(matches!( v, Output::Success(Ast::String(s), _, _) if s.is_empty() ));
+    }
+
+    #[test]
+    fn string_literal_should_fatal_when_unterminated() {
+        let p = string_literal();
+        let mut input = Input::new(r#""abc"#);
+
+        let v = p.parse(&mut input);
+
+        assert!(matches!( v, Output::Fatal(4) ));
+    }
+
+    #[test]
+    fn string_literal_should_fatal_on_unknown_escape() {
+        let p = string_literal();
+        let mut input = Input::new(r#""a\qb""#);
+
+        let v = p.parse(&mut input);
+
+        assert!(matches!( v, Output::Fatal(3) ));
+    }
+
+    #[test]
+    fn string_literal_should_parse_interpolation() {
+        let p = string_literal();
+        let mut input = Input::new(r#""hello ${ user.name }, you have ${count(xs)}""#);
+
+        let v = p.parse(&mut input);
+
+        match v {
+            Output::Success(Ast::Interpolated(segments), _, _) => {
+                assert_eq!( segments.len(), 4 );
+                assert!(matches!( &segments[0], Segment::Literal(s) if s == "hello " ));
+                assert!(matches!( &segments[1], Segment::Expr(Ast::Field { .. }) ));
+                assert!(matches!( &segments[2], Segment::Literal(s) if s == ", you have " ));
+                assert!(matches!( &segments[3], Segment::Expr(Ast::Call { .. }) ));
+            },
+            it @ _ => panic!( "unexpected output: {:?}", it ),
+        }
+    }
+
+    #[test]
+    fn string_literal_should_allow_strings_inside_holes() {
+        let p = string_literal();
+        let mut input = Input::new(r#""a ${ f("}") } b""#);
+
+        let v = p.parse(&mut input);
+
+        assert!(matches!( v, Output::Success(Ast::Interpolated(segments), _, _) if segments.len() == 3 ));
+    }
+
+    #[test]
+    fn raw_string_literal_should_not_process_escapes() {
+        let p = raw_string_literal();
+        let mut input = Input::new(r#"r"C:\temp\${x}""#);
+
+        let v = p.parse(&mut input);
+
+        match v {
+            Output::Success(Ast::String(s), _, _) => assert_eq!( s, r"C:\temp\${x}" ),
+            it @ _ => panic!( "unexpected output: {:?}", it ),
+        }
+    }
+
+    #[test]
+    fn raw_string_literal_should_allow_quotes_with_hashes() {
+        let p = raw_string_literal();
+        let mut input = Input::new(r###"r##"{ "a": "b"# }"##"###);
+
+        let v = p.parse(&mut input);
+
+        match v {
+            Output::Success(Ast::String(s), _, _) => assert_eq!( s, r##"{ "a": "b"# }"## ),
+            it @ _ => panic!( "unexpected output: {:?}", it ),
+        }
+    }
+
+    #[test]
+    fn raw_string_literal_should_fatal_when_unterminated() {
+        let p = raw_string_literal();
+        let mut input = Input::new(r###"r#"abc"###);
+
+        let v = p.parse(&mut input);
+
+        assert!(matches!( v, Output::Fatal(6) ));
+    }
+
+    #[test]
+    fn expr_should_parse_variable_starting_with_r() {
+        let p = expr();
+        let mut input = Input::new("rows");
+
+        let v = p.parse(&mut input);
+
+        assert!(matches!( v, Output::Success(Ast::Variable(name, _), _, _) if name == "rows" ));
+    }
+
+    #[test]
+    fn multi_line_string_literal_should_strip_common_indentation() {
+        let p = multi_line_string_literal();
+        let mut input = Input::new("\"\"\"\n        SELECT *\n          FROM \"t\"\n\n        WHERE x\n        \"\"\"");
+
+        let v = p.parse(&mut input);
+
+        match v {
+            Output::Success(Ast::String(s), _, _) => assert_eq!( s, "SELECT *\n  FROM \"t\"\n\nWHERE x" ),
+            it @ _ => panic!( "unexpected output: {:?}", it ),
+        }
+    }
+
+    #[test]
+    fn multi_line_string_literal_should_fatal_when_unterminated() {
+        let p = multi_line_string_literal();
+        let mut input = Input::new("\"\"\"\n  abc\n");
+
+        let v = p.parse(&mut input);
+
+        assert!(matches!( v, Output::Fatal(10) ));
+    }
+
+    #[test]
+    fn expr_should_parse_empty_string_before_multi_line_string() {
+        let p = expr();
+        let mut input = Input::new(r#""""#);
+
+        let v = p.parse(&mut input);
+
+        assert!(matches!( v, Output::Success(Ast::String(s), _, _) if s.is_empty() ));
+    }
+
+    #[test]
+    fn char_literal_should_parse_plain_and_escaped_chars() {
+        let p = char_literal();
+
+        let mut input = Input::new("'a'");
+        assert!(matches!( p.parse(&mut input), Output::Success(Ast::Char('a'), _, _) ));
+
+        let mut input = Input::new(r"'\n'");
+        assert!(matches!( p.parse(&mut input), Output::Success(Ast::Char('\n'), _, _) ));
+
+        let mut input = Input::new(r"'\''");
+        assert!(matches!( p.parse(&mut input), Output::Success(Ast::Char('\''), _, _) ));
+
+        let mut input = Input::new(r"'\u{1F600}'");
+        assert!(matches!( p.parse(&mut input), Output::Success(Ast::Char('\u{1F600}'), _, _) ));
+    }
+
+    #[test]
+    fn char_literal_should_fatal_on_invalid_unicode_escape() {
+        let p = char_literal();
+        let mut input = Input::new(r"'\u{D800}'");
+
+        let v = p.parse(&mut input);
+
+        assert!(matches!( v, Output::Fatal(_) ));
+    }
+
+    #[test]
+    fn char_literal_should_fatal_on_more_than_one_char() {
+        let p = char_literal();
+        let mut input = Input::new("'ab'");
+
+        let v = p.parse(&mut input);
+
+        assert!(matches!( v, Output::Fatal(2) ));
+    }
+
+    #[test]
+    fn string_literal_should_parse_unicode_escape() {
+        let p = string_literal();
+        let mut input = Input::new(r#""smile \u{1F600}""#);
+
+        let v = p.parse(&mut input);
+
+        assert!(matches!( v, Output::Success(Ast::String(s), _, _) if s == "smile \u{1F600}" ));
+    }
+
+    fn stages(ast : &Ast) -> Vec<Vec<Vec<String>>> {
+        match ast {
+            Ast::Command { stages, .. } => stages.iter().map(|stage| stage.words.iter().map(|word| word.iter().map(|part| match part {
+                WordPart::Bare(s) => format!("bare {}", s),
+                WordPart::Quoted(s) => format!("quoted {}", s),
+                WordPart::Expr(_) => "expr".to_string(),
+                WordPart::QuotedExpr(_) => "quoted expr".to_string(),
+            }).collect()).collect()).collect(),
+            other => panic!( "expected command but found {:?}", other ),
+        }
+    }
+
+    fn words(ast : &Ast) -> Vec<Vec<String>> {
+        stages(ast).remove(0)
+    }
+
+    #[test]
+    fn command_should_parse_bare_words() {
+        let v = parse("$ git status --short").unwrap();
+
+        assert_eq!( words(&v[0]), vec![vec!["bare git"], vec!["bare status"], vec!["bare --short"]] );
+    }
+
+    #[test]
+    fn command_should_parse_quotes_escapes_and_holes() {
+        let v = parse(r#"$ echo "a ${x}"'b c'd\ e ${y} $"#).unwrap();
+
+        assert_eq!( words(&v[0]), vec![ vec!["bare echo"]
+                                      , vec!["quoted a ", "quoted expr", "quoted b c", "bare d", "quoted  ", "bare e"]
+                                      , vec!["expr"]
+                                      , vec!["bare $"]
+                                      ] );
+    }
+
+    #[test]
+    fn command_should_end_at_line_end_without_separator() {
+        let v = parse("$ ls -l\n$ echo a \\\n  b; let x = 1").unwrap();
+
+        assert_eq!( v.len(), 3 );
+        assert_eq!( words(&v[1]), vec![vec!["bare echo"], vec!["bare a"], vec!["bare b"]] );
+    }
+
+    #[test]
+    fn command_should_end_at_comment_or_brace_only_at_word_start() {
+        let v = parse("{ $ find . -exec echo {} a#b # comment\n}").unwrap();
+
+        match &v[0] {
+            Ast::Block(statements) => assert_eq!( words(&statements[0]).len(), 6 ),
+            other => panic!( "expected block but found {:?}", other ),
+        }
+
+        let v = parse("{ $ ls }").unwrap();
+
+        assert!(matches!( &v[0], Ast::Block(statements) if statements.len() == 1 ));
+    }
+
+    #[test]
+    fn command_should_fatal_without_words() {
+        let v = parse("$ ");
+
+        assert!(matches!( v, Err(ParseError::Fatal(_)) ));
+    }
+
+    #[test]
+    fn command_should_parse_pipeline_stages() {
+        let v = parse("$ ls -l | grep ${x} |\n  wc -l\n1").unwrap();
+
+        assert_eq!( v.len(), 2 );
+        assert_eq!( stages(&v[0]), vec![ vec![vec!["bare ls"], vec!["bare -l"]]
+                                       , vec![vec!["bare grep"], vec!["expr"]]
+                                       , vec![vec!["bare wc"], vec!["bare -l"]]
+                                       ] );
+    }
+
+    #[test]
+    fn command_should_fatal_on_empty_pipeline_stage() {
+        let v = parse("$ ls | ");
+
+        assert!(matches!( v, Err(ParseError::Fatal(_)) ));
+    }
+
+    #[test]
+    fn command_should_parse_trailing_ampersand_as_background() {
+        let v = parse("$ sleep 10 | cat &\n$ wait\n$ a &> log\n").unwrap();
+
+        assert_eq!( v.len(), 3 );
+        assert!(matches!( &v[0], Ast::Command { stages, background: true, .. } if stages.len() == 2 ));
+        assert!(matches!( &v[1], Ast::Command { background: false, .. } ));
+        assert!(matches!( &v[2], Ast::Command { background: false, .. } ));
+    }
+
+    #[test]
+    fn command_should_parse_redirections() {
+        let v = parse("$ make 2>&1 > build.log <in 2> err &>both >>log\n").unwrap();
+
+        match &v[0] {
+            Ast::Command { stages, .. } => {
+                let redirects = stages[0].redirects.iter().map(|r| match r {
+                    Redirect::Input(w) => format!("< {:?}", w),
+                    Redirect::Output(w) => format!("> {:?}", w),
+                    Redirect::Append(w) => format!(">> {:?}", w),
+                    Redirect::Error(w) => format!("2> {:?}", w),
+                    Redirect::ErrorToOutput => "2>&1".to_string(),
+                    Redirect::Both(w) => format!("&> {:?}", w),
+                }).collect::<Vec<_>>();
+
+                assert_eq!( stages[0].words.len(), 1 );
+                assert_eq!( redirects, vec![ "2>&1"
+                                           , "> [Bare(\"build.log\")]"
+                                           , "< [Bare(\"in\")]"
+                                           , "2> [Bare(\"err\")]"
+                                           , "&> [Bare(\"both\")]"
+                                           , ">> [Bare(\"log\")]"
+                                           ] );
+            },
+            other => panic!( "expected command but found {:?}", other ),
+        }
+    }
+
+    #[test]
+    fn command_should_take_digit_before_redirect_as_word_unless_at_word_start() {
+        let v = parse("$ echo a2> x 2").unwrap();
+
+        assert_eq!( words(&v[0]), vec![vec!["bare echo"], vec!["bare a2"], vec!["bare 2"]] );
+    }
+
+    #[test]
+    fn command_should_fatal_on_redirect_without_target_or_words() {
+        assert!(matches!( parse("$ echo >"), Err(ParseError::Fatal(_)) ));
+        assert!(matches!( parse("$ > out"), Err(ParseError::Fatal(_)) ));
+    }
+
+    #[test]
+    fn substitution_should_parse_as_expression() {
+        let v = parse("let r = $?(\n  git status | wc -l\n).status;\nlen($(ls))").unwrap();
+
+        assert_eq!( v.len(), 2 );
+        match &v[0] {
+            Ast::Let { value, .. } => assert!(matches!( &**value, Ast::Field { target, .. }
+                if matches!( &**target, Ast::Substitution { stages, structured: true, .. } if stages.len() == 2 ) )),
+            other => panic!( "expected let but found {:?}", other ),
+        }
+    }
+
+    #[test]
+    fn substitution_should_parse_inside_command_words() {
+        let v = parse("$ echo $(pwd)/x ${y}").unwrap();
+
+        assert_eq!( words(&v[0]), vec![vec!["bare echo"], vec!["expr", "bare /x"], vec!["expr"]] );
+    }
+
+    #[test]
+    fn substitution_should_fatal_when_unclosed() {
+        assert!(matches!( parse("$(ls"), Err(ParseError::Fatal(_)) ));
+    }
+
+    #[test]
+    fn env_var_should_parse_in_expressions_and_words() {
+        let v = parse("$HOME;\n$ ls $HOME/src a$B_2 $ $1").unwrap();
+
+        assert!(matches!( &v[0], Ast::EnvVar(name, _) if name == "HOME" ));
+        assert_eq!( words(&v[1]), vec![ vec!["bare ls"]
+                                      , vec!["expr", "bare /src"]
+                                      , vec!["bare a", "expr"]
+                                      , vec!["bare $"]
+                                      , vec!["bare $1"]
+                                      ] );
+    }
+
+    #[test]
+    fn export_should_parse_like_let() {
+        let v = parse("export PATH = \"/bin\"; export X = $ true\nX").unwrap();
+
+        assert_eq!( v.len(), 3 );
+        assert!(matches!( &v[0], Ast::Export { name, .. } if name == "PATH" ));
+    }
+
+    #[test]
+    fn with_env_should_parse_optional_bindings() {
+        let v = parse("with_env(A = 1, B = \"x\") { $ env }\nwith_env { export C = 2 }").unwrap();
+
+        assert_eq!( v.len(), 2 );
+        assert!(matches!( &v[0], Ast::WithEnv { vars, .. } if vars.len() == 2 ));
+        assert!(matches!( &v[1], Ast::WithEnv { vars, .. } if vars.is_empty() ));
+    }
+
+    #[test]
+    fn and_or_should_group_from_the_left() {
+        let v = parse("a && b || c").unwrap();
+
+        match &v[0] {
+            Ast::Or { left, right, .. } => {
+                assert!(matches!( &**left, Ast::And { .. } ));
+                assert!(matches!( &**right, Ast::Variable(name, _) if name == "c" ));
+            },
+            other => panic!( "expected or but found {:?}", other ),
+        }
+    }
+
+    #[test]
+    fn command_should_chain_pipelines_on_its_line() {
+        let v = parse("$ make && $ make test || echo failed | wc\nx").unwrap();
+
+        assert_eq!( v.len(), 2 );
+        match &v[0] {
+            Ast::Or { left, right, .. } => {
+                assert!(matches!( &**left, Ast::And { .. } ));
+                assert_eq!( stages(right).len(), 2 );
+            },
+            other => panic!( "expected or but found {:?}", other ),
+        }
+    }
+
+    #[test]
+    fn command_should_end_before_lone_brace() {
+        let v = parse("if $ test -f x { 1 } else { 2 }\n$ find . -exec echo {} ;").unwrap();
+
+        assert_eq!( v.len(), 2 );
+        assert!(matches!( &v[0], Ast::If { condition, .. } if words(condition).len() == 3 ));
+        assert_eq!( words(&v[1]).len(), 5 );
+    }
+
+    #[test]
+    fn try_should_parse_handler() {
+        let v = parse("try { $ false } catch e { e.message }\n1").unwrap();
+
+        assert_eq!( v.len(), 2 );
+        assert!(matches!( &v[0], Ast::Try { name, .. } if name == "e" ));
+        assert!(matches!( parse("try { 1 }"), Err(ParseError::Fatal(_)) ));
+    }
+
+    #[test]
+    fn let_should_take_command_without_separator() {
+        let v = parse("let s = $ true\ns").unwrap();
+
+        assert_eq!( v.len(), 2 );
+    }
+
+    #[test]
+    fn parse_should_parse_program() {
+        let v = parse("let x = 1;\n let y = { x };\n y\n");
+
+        match v {
+            Ok(ss) => assert_eq!( ss.len(), 3 ),
+            Err(e) => panic!( "unexpected error: {:?}", e ),
+        }
+    }
+
+    #[test]
+    fn parse_should_report_failure_index() {
+        let v = parse("let x = 1;\n@");
+
+        assert!(matches!( v, Err(ParseError::Failure(11)) ));
+    }
+
+    #[test]
+    fn parse_spanned_should_give_statement_spans() {
+        let v = parse_spanned("  let x = 1;\n## doc\nfn f() { x } # trailing\n\n#[ comment ]#\nf()  ");
+
+        let spans = v.unwrap().into_iter().map(|(_, span)| span).collect::<Vec<_>>();
+
+        assert_eq!( spans, vec![ Span { start: 2, end: 11 }
+                               , Span { start: 13, end: 32 }
+                               , Span { start: 59, end: 62 }
+                               ] );
+    }
+
+    #[test]
+    fn parse_error_should_be_incomplete_when_input_is_cut_short() {
+        for s in ["fn f() {\n", "[1, 2", "\"abc ${1", "#[ comment", "f(1,"] {
+            let e = parse(s).unwrap_err();
+
+            assert!( e.is_incomplete(s), "input: {:?}", s );
+        }
+    }
+
+    #[test]
+    fn parse_error_should_not_be_incomplete_when_input_is_wrong() {
+        for s in ["[1 2]", "let = 1", "'ab'"] {
+            let e = parse(s).unwrap_err();
+
+            assert!( !e.is_incomplete(s), "input: {:?}", s );
+        }
+    }
+
+    #[test]
+    fn junk_should_skip_line_comment() {
+        let p = trim!( number_literal() );
+        let mut input = Input::new("  # a comment\n  1234 # trailing");
+
+        let v = p.parse(&mut input);
+
+        assert!(matches!( v, Output::Success(Ast::Integer(1234), _, _)));
+        assert!( input.peek().is_err() );
+    }
+
+    #[test]
+    fn junk_should_skip_nested_block_comment() {
+        let p = trim!( number_literal() );
+        let mut input = Input::new("#[ outer #[ inner ]# still outer ]# 1234");
+
+        let v = p.parse(&mut input);
+
+        assert!(matches!( v, Output::Success(Ast::Integer(1234), _, _)));
+    }
+
+    #[test]
+    fn junk_should_fatal_on_unterminated_block_comment() {
+        let p = trim!( number_literal() );
+        let mut input = Input::new("  #[ outer #[ inner ]# 1234");
+
+        let v = p.parse(&mut input);
+
+        assert!(matches!( v, Output::Fatal(27)));
+    }
+
+    #[test]
+    fn junk_should_not_skip_doc_comment() {
+        let p = trim!( number_literal() );
+        let mut input = Input::new("## doc\n1234");
+
+        let v = p.parse(&mut input);
+
+        assert!(matches!( v, Output::Failure(_)));
+    }
+
+    #[test]
+    fn junk_should_skip_doc_comment_after_code() {
+        let p = trim!( number_literal() );
+        let mut input = Input::new("1234 ## about 1234\n");
+
+        let v = p.parse(&mut input);
+
+        assert!(matches!( v, Output::Success(Ast::Integer(1234), _, _)));
+        assert!( input.peek().is_err() );
+    }
+
+    #[test]
+    fn parse_should_skip_unattached_doc_comments() {
+        let before_brace = parse("fn f() {\n    1\n    ## nothing follows\n}").unwrap();
+        let at_end = parse("let x = 1;\n## nothing follows\n").unwrap();
+        let after_code = parse("let x = 1; ## about x\nlet y = 2 ## about y\n").unwrap();
+
+        assert!(matches!( before_brace.as_slice(), [Ast::Function { doc: None, .. }] ));
+        assert!(matches!( at_end.as_slice(), [Ast::Let { doc: None, .. }] ));
+        assert!(matches!( after_code.as_slice(), [Ast::Let { doc: None, .. }, Ast::Let { doc: None, .. }] ));
+    }
+
+    #[test]
+    fn doc_comments_should_join_consecutive_lines() {
+        let p = doc_comments();
+        let mut input = Input::new("## first line\n  ##second line\n1234");
+
+        let v = p.parse(&mut input);
+
+        match v {
+            Output::Success(Some(doc), _, _) => assert_eq!( doc, "first line\nsecond line" ),
+            it @ _ => panic!( "unexpected output: {:?}", it ),
+        }
     }
 }
\ No newline at end of file
@@ -1,34 +1,80 @@
 use std::str::CharIndices;
 
 pub struct Input<'a> {
+    source : &'a str,
     cs : CharIndices<'a>,
     total_length : usize,
+    token_end : usize,
+    // what was wrong, when a parser failing fatally knows more than where
+    reason : Option<(usize, usize, &'static str)>,
 }
 
 pub struct RestorePoint<'a> {
     cs : CharIndices<'a>,
+    token_end : usize,
 }
 
 impl<'a> Input<'a> {
 
     pub fn new(s : &'a str) -> Input<'a> {
-        Input { cs: s.char_indices()
+        Input { source: s
+              , cs: s.char_indices()
               , total_length: s.len()
+              , token_end: 0
+              , reason: None
               }
     }
 
     pub fn restore_point(&self) -> RestorePoint<'a> {
         RestorePoint { cs: self.cs.clone()
+                     , token_end: self.token_end
                      }
     }
 
     pub fn restore(&mut self, rp : RestorePoint<'a>) {
         self.cs = rp.cs;
+        self.token_end = rp.token_end;
+    }
+
+    // The index just past the last char consumed. Unlike index this can be 
+    // set back, so that skipping whitespace and comments doesn't move it
+    pub fn token_end(&self) -> usize {
+        self.token_end
+    }
+
+    pub fn set_token_end(&mut self, index : usize) {
+        self.token_end = index;
+    }
+
+    // Nothing parses after a fatal failure, so the reason given for it stays
+    pub fn give_reason(&mut self, start : usize, end : usize, reason : &'static str) {
+        self.reason = Some((start, end, reason));
+    }
+
+    pub fn reason(&self) -> Option<(usize, usize, &'static str)> {
+        self.reason
+    }
+
+    pub fn index(&self) -> usize {
+        match self.cs.clone().next() {
+            Some((index, _)) => index,
+            None => self.total_length,
+        }
+    }
+
+    // Whether nothing but whitespace comes before the next char on its line
+    pub fn at_line_start(&self) -> bool {
+        let before = &self.source[..self.index()];
+        let line = before.rfind('\n').map_or(0, |newline| newline + 1);
+        before[line..].trim().is_empty()
     }
 
     pub fn get_char(&mut self) -> Result<(usize, char), usize> {
         match self.cs.next() {
-            Some(c) => Ok(c),
+            Some((index, c)) => {
+                self.token_end = index + c.len_utf8();
+                Ok((index, c))
+            },
             None => Err(self.total_length),
         }
     }
@@ -57,6 +103,7 @@ impl<'a> Input<'a> {
         }
 
         self.cs = n;
+        self.token_end = self.index();
         Ok((start, end, s))
     }
 
@@ -78,6 +125,8 @@ impl<'a> Input<'a> {
         Ok((start, end, cs.into_iter().collect()))
     }
 
+    // only the tests use this so far, parsers go through Parser::when
+    #[allow(dead_code)]
     pub fn when(&mut self, f : impl Fn(char) -> bool) -> Result<(usize, char), usize> {
         let (index, c) = self.peek()?;
 
@@ -92,6 +141,8 @@ impl<'a> Input<'a> {
 }
 
 #[cfg(test)]
+// the tests keep their original assertion style
+#[allow(clippy::assertions_on_constants, clippy::redundant_pattern_matching)]
 mod test {
     use super::*;
 
@@ -134,6 +185,51 @@ mod test {
         assert_eq!( Err(6), v );
     }
 
+    #[test]
+    fn index_should_return_next_char_index() {
+        let mut input = Input::new("string");
+
+        assert_eq!( 0, input.index() );
+
+        let _ = input.exact("st");
+
+        assert_eq!( 2, input.index() );
+    }
+
+    #[test]
+    fn index_should_return_total_length_at_end() {
+        let mut input = Input::new("string");
+
+        let _ = input.exact("string");
+
+        assert_eq!( 6, input.index() );
+    }
+
+    #[test]
+    fn token_end_should_follow_consumed_chars() {
+        let mut input = Input::new("é blah");
+
+        let _ = input.get_char();
+        assert_eq!( 2, input.token_end() );
+
+        let _ = input.get_char();
+        input.set_token_end(2);
+        let _ = input.exact("bl");
+
+        assert_eq!( 5, input.token_end() );
+    }
+
+    #[test]
+    fn restore_should_reset_token_end() {
+        let mut input = Input::new("string");
+        let rp = input.restore_point();
+
+        let _ = input.exact("str");
+        input.restore(rp);
+
+        assert_eq!( 0, input.token_end() );
+    }
+
     #[test]
     fn exact_failure_should_not_change_index() {
         let mut input = Input::new("string");
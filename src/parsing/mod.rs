@@ -3,5 +3,6 @@ mod input;
 mod output;
 mod parser;
 
-mod ast;
-pub mod grammar;
\ No newline at end of file
+pub mod ast;
+pub mod grammar;
+pub mod dump;
\ No newline at end of file
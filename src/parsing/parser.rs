@@ -2,8 +2,10 @@
 use super::input::Input;
 use super::output::Output;
 
+type Parse<T> = Box<dyn Fn(&mut Input) -> Output<T>>;
+
 pub enum Parser<T : 'static + Clone> {
-    Parse(Box<dyn Fn(&mut Input) -> Output<T>>),
+    Parse(Parse<T>),
     Unit(T),
 }
 
@@ -11,7 +13,7 @@ pub fn bind<A : 'static + Clone, B : 'static + Clone>( pa : Parser<A>, next : im
     Parser::Parse(Box::new(move |input| {
         let rp = input.restore_point();
         match pa.parse(input) {
-            Output::Success(item, start, end) => next(item).parse(input), // TODO start/end needs to be fed into item ?
+            Output::Success(item, _start, _end) => next(item).parse(input), // TODO start/end needs to be fed into item ?
             Output::Failure(index) => { input.restore(rp); Output::Failure(index) },
             Output::Fatal(index) => Output::Fatal(index),
         }
@@ -22,7 +24,7 @@ pub fn unit<T : Clone>( t : T ) -> Parser<T> {
     Parser::Unit(t)
 }
 
-pub fn exact<'a>(s : &'a str) -> Parser<&'a str> {
+pub fn exact(s : &str) -> Parser<&str> {
     Parser::Parse(Box::new(move |input| {
         match input.exact(s) {
             Ok((start, end, value)) => Output::Success(value, start, end),
@@ -60,6 +62,21 @@ pub fn peek() -> Parser<char> {
     }))
 }
 
+pub fn end() -> Parser<()> {
+    Parser::Parse(Box::new(move |input| {
+        match input.peek() {
+            Ok((index, _)) => Output::Failure(index),
+            Err(index) => Output::Success((), index, index),
+        }
+    }))
+}
+
+pub fn lazy<T : 'static + Clone>(f : fn() -> Parser<T>) -> Parser<T> {
+    // defers building a parser until it runs, so that recursive grammar rules 
+    // don't recurse forever while they're being constructed
+    Parser::Parse(Box::new(move |input| f().parse(input)))
+}
+
 impl<T : 'static + Clone> Parser<T> {
     pub fn new(parser : impl Fn(&mut Input) -> Output<T> + 'static) -> Parser<T> {
         Parser::Parse(Box::new(parser))
@@ -98,7 +115,7 @@ impl<T : 'static + Clone> Parser<T> {
             let rp = input.restore_point();
             match self.parse(input) {
                 Output::Success(v, start, end) if pred(&v) => Output::Success(v, start, end),
-                Output::Success(v, index, _) => { input.restore(rp); Output::Failure(index) },
+                Output::Success(_, index, _) => { input.restore(rp); Output::Failure(index) },
                 Output::Failure(index) => { input.restore(rp); Output::Failure(index) },
                 Output::Fatal(index) => Output::Fatal(index),
             }
@@ -125,7 +142,7 @@ impl<T : 'static + Clone> Parser<T> {
                 let rp = input.restore_point();
 
                 match self.parse(input) {
-                    Output::Success(v, start, end) => items.push(v), // TODO start/end ?
+                    Output::Success(v, _start, _end) => items.push(v), // TODO start/end ?
                     Output::Failure(_) => { input.restore(rp); break },
                     Output::Fatal(index) => return Output::Fatal(index),
                 }
@@ -143,7 +160,7 @@ impl<T : 'static + Clone> Parser<T> {
             let rp = input.restore_point();
 
             match self.parse(input) {
-                Output::Success(v, start, end) => items.push(v), // TODO start/end ?
+                Output::Success(v, _start, _end) => items.push(v), // TODO start/end ?
                 Output::Failure(index) => { input.restore(rp); return Output::Failure(index) },
                 Output::Fatal(index) => return Output::Fatal(index),
             }
@@ -152,7 +169,35 @@ impl<T : 'static + Clone> Parser<T> {
                 let rp = input.restore_point();
 
                 match self.parse(input) {
-                    Output::Success(v, start, end) => items.push(v), // TODO start/end ?
+                    Output::Success(v, _start, _end) => items.push(v), // TODO start/end ?
+                    Output::Failure(_) => { input.restore(rp); break },
+                    Output::Fatal(index) => return Output::Fatal(index),
+                }
+            }
+
+            Output::Success(items, 0, 0) // TODO start/end ?
+        }))
+    }
+
+    pub fn sep_by<S : 'static + Clone>(self, sep : Parser<S>) -> Parser<Vec<T>> {
+        // zero or more items, where a trailing separator is allowed
+        Parser::Parse(Box::new(move |input| {
+
+            let mut items : Vec<T> = vec![];
+
+            loop {
+                let rp = input.restore_point();
+
+                match self.parse(input) {
+                    Output::Success(v, _, _) => items.push(v),
+                    Output::Failure(_) => { input.restore(rp); break },
+                    Output::Fatal(index) => return Output::Fatal(index),
+                }
+
+                let rp = input.restore_point();
+
+                match sep.parse(input) {
+                    Output::Success(_, _, _) => { },
                     Output::Failure(_) => { input.restore(rp); break },
                     Output::Fatal(index) => return Output::Fatal(index),
                 }
@@ -162,6 +207,21 @@ impl<T : 'static + Clone> Parser<T> {
         }))
     }
 
+    pub fn followed_by<B : 'static + Clone>(self, other : Parser<B>) -> Parser<T> {
+        Parser::Parse(Box::new(move |input| {
+            let rp = input.restore_point();
+            match self.parse(input) {
+                Output::Success(v, start, _) => match other.parse(input) {
+                    Output::Success(_, _, end) => Output::Success(v, start, end),
+                    Output::Failure(index) => { input.restore(rp); Output::Failure(index) },
+                    Output::Fatal(index) => Output::Fatal(index),
+                },
+                Output::Failure(index) => { input.restore(rp); Output::Failure(index) },
+                Output::Fatal(index) => Output::Fatal(index),
+            }
+        }))
+    }
+
     pub fn or(self, other : Parser<T>) -> Parser<T> {
 
         Parser::Parse(Box::new(move |input| {
@@ -182,4 +242,97 @@ mod test {
     use super::*;
     use monad::compute;
 
+    fn nested() -> Parser<usize> {
+        compute!{ bind, unit =>
+            _open <- the('(');
+            depth <- lazy(nested).maybe().followed_by(the(')'));
+            unit depth.map_or(1, |d| d + 1)
+        }
+    }
+
+    #[test]
+    fn lazy_should_support_recursive_parser() {
+        let p = lazy(nested);
+        let mut input = Input::new("((()))");
+
+        let v = p.parse(&mut input);
+
+        assert!(matches!( v, Output::Success(3, _, _)));
+    }
+
+    #[test]
+    fn end_should_succeed_at_end_of_input() {
+        let p = compute!{ bind, unit => 
+            _s <- exact("string");
+            _e <- end();
+            unit ()
+        };
+        let mut input = Input::new("string");
+
+        let v = p.parse(&mut input);
+
+        assert!(matches!( v, Output::Success((), _, _)));
+    }
+
+    #[test]
+    fn followed_by_should_return_first_value() {
+        let p = exact("st").followed_by(exact("ring"));
+        let mut input = Input::new("string");
+
+        let v = p.parse(&mut input);
+
+        assert!(matches!( v, Output::Success("st", 0, 5)));
+    }
+
+    #[test]
+    fn followed_by_failure_should_not_change_index() {
+        let p = exact("st").followed_by(exact("rung"));
+        let mut input = Input::new("string");
+
+        let v = p.parse(&mut input);
+
+        assert!(matches!( v, Output::Failure(_)));
+        assert_eq!( input.peek(), Ok((0, 's')) );
+    }
+
+    #[test]
+    fn sep_by_should_parse_separated_items() {
+        let p = any().sep_by(the(','));
+        let mut input = Input::new("a,b,c");
+
+        let v = p.parse(&mut input);
+
+        assert!(matches!( v, Output::Success(cs, _, _) if cs == vec!['a', 'b', 'c'] ));
+    }
+
+    #[test]
+    fn sep_by_should_allow_trailing_separator() {
+        let p = the('a').sep_by(the(','));
+        let mut input = Input::new("a,a,)");
+
+        let v = p.parse(&mut input);
+
+        assert!(matches!( v, Output::Success(cs, _, _) if cs.len() == 2 ));
+        assert_eq!( input.peek(), Ok((4, ')')) );
+    }
+
+    #[test]
+    fn sep_by_should_parse_zero_items() {
+        let p = the('a').sep_by(the(','));
+        let mut input = Input::new(")");
+
+        let v = p.parse(&mut input);
+
+        assert!(matches!( v, Output::Success(cs, _, _) if cs.is_empty() ));
+    }
+
+    #[test]
+    fn end_should_fail_before_end_of_input() {
+        let p = end();
+        let mut input = Input::new("string");
+
+        let v = p.parse(&mut input);
+
+        assert!(matches!( v, Output::Failure(0)));
+    }
 }
\ No newline at end of file
@@ -0,0 +1,375 @@
+
+use std::fs;
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+
+use crate::diagnostic;
+use crate::editor::{self, Editor, History, ReadLine};
+use crate::eval::{Evaluator, RuntimeError, Value};
+use crate::parsing::ast::Span;
+use crate::parsing::grammar::{parse, KEYWORDS};
+
+const PROMPT : &str = "ash> ";
+const CONTINUATION_PROMPT : &str = "...> ";
+
+#[derive(Debug, PartialEq)]
+pub enum Feed {
+    // more lines are needed before the entry can be parsed
+    Incomplete,
+    Done(Option<String>),
+    Error(String),
+    // 'exit' was run with this status
+    Exit(i32),
+}
+
+pub struct Repl {
+    evaluator : Evaluator,
+    buffer : String,
+}
+
+impl Repl {
+    pub fn new() -> Repl {
+        Repl { evaluator: Evaluator::new(), buffer: String::new() }
+    }
+
+    // A prompt() function, from the init file say, renders the prompt when
+    // there is one. Continuation lines always get the plain prompt
+    pub fn prompt(&mut self) -> String {
+        if !self.buffer.is_empty() {
+            return CONTINUATION_PROMPT.to_string();
+        }
+
+        let custom = match self.evaluator.globals().get("prompt") {
+            Some(custom @ Value::Closure(_)) => custom,
+            _ => return PROMPT.to_string(),
+        };
+
+        // the commands prompt() runs mustn't change what last_status() gives afterwards
+        let status = self.evaluator.last_status();
+        let result = self.evaluator.call(custom, vec![], Span::default());
+        self.evaluator.set_last_status(status);
+
+        match result {
+            Ok(Value::String(prompt)) => prompt,
+            Ok(other) => other.to_string(),
+            Err(error) => {
+                eprintln!("ash: prompt() failed: {}", error);
+                PROMPT.to_string()
+            },
+        }
+    }
+
+    // Evaluates a startup file, so that what it defines is there for later entries
+    pub fn load(&mut self, name : &str, source : &str) -> Result<(), String> {
+        let program = parse(source).map_err(|error| diagnostic::render(name, source, error.index(), &error.message()))?;
+
+        match self.evaluator.eval(&program) {
+            Ok(_) => Ok(()),
+            Err(error) => Err(diagnostic::render(name, source, error.span().start, &format!("runtime error: {}", error))),
+        }
+    }
+
+    // Lines are buffered until they parse, or until the parse fails somewhere
+    // before the end of what's been entered
+    pub fn feed(&mut self, line : &str) -> Feed {
+        self.buffer.push_str(line);
+        if !self.buffer.ends_with('\n') {
+            self.buffer.push('\n');
+        }
+
+        let program = match parse(&self.buffer) {
+            Ok(program) => program,
+            Err(error) if error.is_incomplete(&self.buffer) => return Feed::Incomplete,
+            Err(error) => {
+                let source = std::mem::take(&mut self.buffer);
+                return Feed::Error(diagnostic::render("<repl>", &source, error.index(), &error.message()));
+            },
+        };
+
+        let source = std::mem::take(&mut self.buffer);
+
+        match self.evaluator.eval(&program) {
+            Ok(value) if value == Value::unit() || value == Value::Status(0) => Feed::Done(None),
+            Ok(value) => Feed::Done(Some(value.to_string())),
+            Err(RuntimeError::Exit { code, .. }) => Feed::Exit(code),
+            Err(error) => Feed::Error(diagnostic::render("<repl>", &source, error.span().start, &format!("runtime error: {}", error))),
+        }
+    }
+
+    // Everything that tab can complete to
+    pub fn names(&self) -> Vec<String> {
+        let mut names = KEYWORDS.iter().map(|k| k.to_string()).collect::<Vec<_>>();
+        names.extend(self.evaluator.globals().names());
+        names
+    }
+
+    // Takes the terminal so ctrl-c and ctrl-z go to foreground jobs
+    pub fn enable_job_control(&mut self) {
+        self.evaluator.enable_job_control();
+    }
+
+    // Reports background jobs that have finished, as the prompt is shown
+    pub fn report_jobs(&mut self) {
+        for message in self.evaluator.reap_jobs() {
+            eprintln!("{}", message);
+        }
+    }
+
+    // Throws away a partly entered entry
+    pub fn cancel(&mut self) {
+        self.buffer.clear();
+    }
+
+    // Called when input ends part way through an entry
+    pub fn finish(&mut self) -> Option<Feed> {
+        if self.buffer.trim().is_empty() {
+            return None;
+        }
+
+        let source = std::mem::take(&mut self.buffer);
+        Some(Feed::Error(diagnostic::render("<repl>", &source, source.trim_end().len(), "parse error: unexpected end of input")))
+    }
+}
+
+impl Default for Repl {
+    fn default() -> Repl {
+        Repl::new()
+    }
+}
+
+pub fn run() -> i32 {
+    let mut repl = Repl::new();
+    load_init(&mut repl);
+
+    if io::stdin().is_terminal() && io::stdout().is_terminal() {
+        run_interactive(repl)
+    }
+    else {
+        run_lines(repl)
+    }
+}
+
+// $ASH_INIT, or ~/.config/ash/init.ash
+fn init_path() -> Option<PathBuf> {
+    match std::env::var_os("ASH_INIT") {
+        Some(path) => Some(PathBuf::from(path)),
+        None => std::env::var_os("HOME").map(|home| Path::new(&home).join(".config/ash/init.ash")),
+    }
+}
+
+// Having no init file is fine unless $ASH_INIT asked for one
+fn load_init(repl : &mut Repl) {
+    let path = match init_path() {
+        Some(path) => path,
+        None => return,
+    };
+
+    match fs::read_to_string(&path) {
+        Ok(source) => {
+            if let Err(message) = repl.load(&path.to_string_lossy(), &source) {
+                eprint!("{}", message);
+            }
+        },
+        Err(e) if e.kind() == io::ErrorKind::NotFound && std::env::var_os("ASH_INIT").is_none() => { },
+        Err(e) => eprintln!("ash: cannot read {}: {}", path.display(), e),
+    }
+}
+
+fn report(feed : Feed) {
+    match feed {
+        Feed::Incomplete => { },
+        Feed::Done(Some(value)) => println!("{}", value),
+        Feed::Done(None) => { },
+        Feed::Error(message) => eprint!("{}", message),
+        Feed::Exit(_) => { },
+    }
+}
+
+fn run_interactive(mut repl : Repl) -> i32 {
+    let history = editor::default_path().map(History::load).unwrap_or_default();
+    let mut editor = Editor::new(history);
+    repl.enable_job_control();
+
+    loop {
+        repl.report_jobs();
+        let prompt = repl.prompt();
+        match editor.read_line(&prompt, &repl.names()) {
+            Ok(ReadLine::Line(line)) => match repl.feed(&line) {
+                Feed::Exit(code) => return code,
+                feed => report(feed),
+            },
+            Ok(ReadLine::Interrupted) => repl.cancel(),
+            Ok(ReadLine::Eof) => {
+                if let Some(feed) = repl.finish() {
+                    report(feed);
+                }
+                return 0;
+            },
+            Err(e) => {
+                eprintln!("ash: cannot read input: {}", e);
+                return 1;
+            },
+        }
+    }
+}
+
+// Without a terminal there's nothing to edit on, so lines are read as they come
+fn run_lines(mut repl : Repl) -> i32 {
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+
+    loop {
+        repl.report_jobs();
+        print!("{}", repl.prompt());
+        io::stdout().flush().expect("repl fails to flush stdout");
+
+        let line = match lines.next() {
+            Some(Ok(line)) => line,
+            Some(Err(e)) => {
+                eprintln!("ash: cannot read input: {}", e);
+                return 1;
+            },
+            None => {
+                println!();
+                if let Some(feed) = repl.finish() {
+                    report(feed);
+                }
+                return 0;
+            },
+        };
+
+        match repl.feed(&line) {
+            Feed::Exit(code) => return code,
+            feed => report(feed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn feed_should_keep_bindings_between_entries() {
+        let mut repl = Repl::new();
+
+        assert_eq!( repl.feed("let x = 5;"), Feed::Done(None) );
+        assert_eq!( repl.feed("add(x, 1)"), Feed::Done(Some("6".to_string())) );
+    }
+
+    #[test]
+    fn feed_should_continue_unclosed_block() {
+        let mut repl = Repl::new();
+
+        assert_eq!( repl.feed("fn f(x) {"), Feed::Incomplete );
+        assert_eq!( repl.prompt(), CONTINUATION_PROMPT );
+        assert_eq!( repl.feed("  [x, x]"), Feed::Incomplete );
+        assert_eq!( repl.feed("}"), Feed::Done(None) );
+        assert_eq!( repl.prompt(), PROMPT );
+        assert_eq!( repl.feed("f(1)"), Feed::Done(Some("[1, 1]".to_string())) );
+    }
+
+    #[test]
+    fn feed_should_continue_unclosed_string() {
+        let mut repl = Repl::new();
+
+        assert_eq!( repl.feed("\"a"), Feed::Incomplete );
+        assert_eq!( repl.feed("b\""), Feed::Done(Some("a\nb".to_string())) );
+    }
+
+    #[test]
+    fn feed_should_report_error_and_reset() {
+        let mut repl = Repl::new();
+
+        assert!(matches!( repl.feed("[1 2]"), Feed::Error(_) ));
+        assert_eq!( repl.prompt(), PROMPT );
+        assert!(matches!( repl.feed("blah"), Feed::Error(message) if message.contains("unbound variable 'blah'") ));
+        assert_eq!( repl.feed("1"), Feed::Done(Some("1".to_string())) );
+    }
+
+    #[test]
+    fn names_should_include_keywords_builtins_and_bindings() {
+        let mut repl = Repl::new();
+
+        repl.feed("let blah = 1;");
+        let names = repl.names();
+
+        for name in ["match", "print", "blah"] {
+            assert!( names.iter().any(|n| n == name), "name: {}", name );
+        }
+    }
+
+    #[test]
+    fn feed_should_report_exit() {
+        let mut repl = Repl::new();
+
+        assert_eq!( repl.feed("$ exit 3"), Feed::Exit(3) );
+    }
+
+    #[test]
+    fn prompt_should_call_prompt_function() {
+        // cwd() reads the working directory, which other tests move
+        let _turn = crate::eval::working_dir_turn();
+        let mut repl = Repl::new();
+        assert_eq!( repl.prompt(), PROMPT );
+
+        assert_eq!( repl.load("init.ash", "fn prompt() { \"${last_status()} ${cwd()}> \" }"), Ok(()) );
+        assert_eq!( repl.feed("$ sh -c 'exit 2'"), Feed::Done(Some("2".to_string())) );
+
+        assert!( repl.prompt().starts_with("2 ") );
+        assert!( repl.prompt().ends_with("> ") );
+        assert_eq!( repl.feed("fn f(x) {"), Feed::Incomplete );
+        assert_eq!( repl.prompt(), CONTINUATION_PROMPT );
+    }
+
+    #[test]
+    fn prompt_should_keep_last_status() {
+        let mut repl = Repl::new();
+
+        assert_eq!( repl.load("init.ash", "fn prompt() { let s = last_status(); $ true; \"${s}> \" }"), Ok(()) );
+        assert_eq!( repl.feed("$ sh -c 'exit 2'"), Feed::Done(Some("2".to_string())) );
+
+        assert_eq!( repl.prompt(), "2> " );
+        assert_eq!( repl.prompt(), "2> " );
+        assert_eq!( repl.feed("last_status()"), Feed::Done(Some("2".to_string())) );
+    }
+
+    #[test]
+    fn prompt_should_fall_back_when_prompt_function_fails() {
+        let mut repl = Repl::new();
+
+        assert_eq!( repl.load("init.ash", "fn prompt() { missing }"), Ok(()) );
+        assert_eq!( repl.prompt(), PROMPT );
+    }
+
+    #[test]
+    fn load_should_report_errors_in_init_file() {
+        let mut repl = Repl::new();
+
+        let v = repl.load("init.ash", "let a = 1;\nb");
+
+        assert!(matches!( v, Err(message) if message.starts_with("init.ash:2:1: runtime error") ));
+        assert!(matches!( repl.load("init.ash", "let = 1"), Err(message) if message.contains("parse error") ));
+    }
+
+    #[test]
+    fn cancel_should_drop_partial_entry() {
+        let mut repl = Repl::new();
+
+        repl.feed("[1,");
+        repl.cancel();
+
+        assert_eq!( repl.prompt(), PROMPT );
+        assert_eq!( repl.feed("2"), Feed::Done(Some("2".to_string())) );
+    }
+
+    #[test]
+    fn finish_should_report_unfinished_entry() {
+        let mut repl = Repl::new();
+
+        repl.feed("(1,");
+
+        assert!(matches!( repl.finish(), Some(Feed::Error(_)) ));
+        assert_eq!( repl.finish(), None );
+    }
+}
@@ -0,0 +1,75 @@
+
+// Rough comparisons between the tree walking evaluator and the virtual machine.
+// These are ignored by default, run them with:
+//
+//     cargo test --release -- --ignored --nocapture bench
+
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use crate::eval::{self, Evaluator, Value};
+use crate::parsing::grammar::parse;
+use super::{compile, Machine};
+
+fn time<F : FnMut() -> Value>(mut f : F) -> (Value, Duration) {
+    let start = Instant::now();
+    let value = f();
+    (value, start.elapsed())
+}
+
+// The evaluator recurses on the native stack, so it gets the one ash gives it
+// rather than the smaller one the test harness gives out
+fn compare(name : &'static str, source : &'static str) {
+    std::thread::Builder::new()
+        .stack_size(eval::STACK_SIZE)
+        .spawn(move || compare_on_thread(name, source))
+        .expect("bench thread fails to spawn")
+        .join()
+        .expect("bench thread panics");
+}
+
+fn compare_on_thread(name : &str, source : &str) {
+    let program = parse(source).expect("bench program should parse");
+
+    let (expected, tree) = time(|| Evaluator::new().eval(&program).expect("bench program should evaluate"));
+    let (actual, vm) = time(|| Machine::new().run(Rc::new(compile(&program).expect("bench program should compile"))).expect("bench program should run"));
+
+    assert_eq!( actual, expected );
+
+    println!("{}: evaluator {:?}, vm {:?}", name, tree, vm);
+}
+
+#[test]
+#[ignore]
+fn bench_fib() {
+    compare("fib", "
+        fn fib(n) { if lt(n, 2) { n } else { add(fib(sub(n, 1)), fib(sub(n, 2))) } }
+        fib(25)");
+}
+
+#[test]
+#[ignore]
+fn bench_list_building() {
+    compare("list building", "
+        fn build(n, acc) { if eq(n, 0) { acc } else { build(sub(n, 1), push(acc, n)) } }
+        len(build(2000, []))");
+}
+
+#[test]
+#[ignore]
+fn bench_match() {
+    compare("match", "
+        fn classify(v) {
+            match v {
+                (0, _) => 0,
+                (_, [x, ..rest]) if gt(x, 5) => 1,
+                (n, [_, ..]) => 2,
+                _ => 3,
+            }
+        }
+        fn run(n, total) {
+            if eq(n, 0) { total }
+            else { run(sub(n, 1), add(total, classify((rem(n, 4), [rem(n, 10), n])))) }
+        }
+        run(5000, 0)");
+}
@@ -0,0 +1,64 @@
+
+use std::rc::Rc;
+
+use crate::eval::Value;
+use crate::parsing::ast::{Pattern, Span};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    Constant(usize),
+    Unit,
+    Pop,
+    // pops n values, closing any upvalues that point at them
+    PopN(usize),
+    // like PopN, but for the n values underneath the top of the stack
+    EndScope(usize),
+    GetLocal(usize),
+    GetUpvalue(usize),
+    // the operand is the constant holding the global's name
+    GetGlobal(usize),
+    DefineGlobal(usize),
+    Jump(usize),
+    JumpIfFalse(usize),
+    Call(usize),
+    // argument count, index into Chunk::argument_names
+    CallNamed(usize, usize),
+    Closure(usize),
+    MakeList(usize),
+    MakeTuple(usize),
+    // index into Chunk::field_names
+    MakeRecord(usize),
+    Index,
+    Field(usize),
+    Interpolate(usize),
+    // index into Chunk::patterns, local slot of the subject. On a match it pushes
+    // each bound value and then true, otherwise it only pushes false
+    TestPattern(usize, usize),
+    NoMatch,
+    Return,
+}
+
+#[derive(Debug, Default)]
+pub struct Chunk {
+    pub code : Vec<Op>,
+    pub spans : Vec<Span>,
+    pub constants : Vec<Value>,
+    pub prototypes : Vec<Rc<Prototype>>,
+    pub patterns : Vec<Pattern>,
+    pub field_names : Vec<Vec<String>>,
+    pub argument_names : Vec<Vec<Option<String>>>,
+}
+
+#[derive(Debug)]
+pub struct Prototype {
+    pub name : Option<String>,
+    pub params : Vec<String>,
+    pub captures : Vec<Capture>,
+    pub chunk : Chunk,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Capture {
+    Local(usize),
+    Upvalue(usize),
+}
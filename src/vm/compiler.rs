@@ -0,0 +1,505 @@
+
+use std::fmt;
+use std::rc::Rc;
+
+use crate::eval::Value;
+use crate::parsing::ast::{Ast, Argument, Pattern, Segment, Span};
+use super::bytecode::{Capture, Chunk, Op, Prototype};
+
+struct Local {
+    name : String,
+    slot : usize,
+}
+
+// Blocks are expressions, so a local's slot can't be worked out from the number
+// of locals alone. Instead each function tracks how many values will be on its
+// part of the stack at every point in the code.
+struct Function {
+    chunk : Chunk,
+    locals : Vec<Local>,
+    captures : Vec<Capture>,
+    height : usize,
+}
+
+impl Function {
+    fn new(params : &[String]) -> Function {
+        Function { chunk: Chunk::default()
+                 , locals: params.iter().enumerate().map(|(slot, name)| Local { name: name.clone(), slot }).collect()
+                 , captures: vec![]
+                 , height: params.len()
+                 }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CompileError {
+    // the machine only covers the core language, the shell parts need the evaluator
+    Unsupported { construct : &'static str, span : Span },
+}
+
+impl CompileError {
+    pub fn span(&self) -> Span {
+        match self {
+            CompileError::Unsupported { span, .. } => *span,
+        }
+    }
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CompileError::Unsupported { construct, .. } => write!(f, "{} can't be run on the bytecode machine", construct),
+        }
+    }
+}
+
+pub fn compile(program : &[Ast]) -> Result<Prototype, CompileError> {
+    let mut compiler = Compiler { functions: vec![Function::new(&[])], error: None };
+
+    compiler.top_level(program);
+
+    if let Some(error) = compiler.error {
+        return Err(error);
+    }
+
+    let script = compiler.functions.pop().expect("compile fails to find script function");
+
+    Ok(Prototype { name: None, params: vec![], captures: script.captures, chunk: script.chunk })
+}
+
+struct Compiler {
+    functions : Vec<Function>,
+    // the first error found. Compiling carries on past it, but the output is thrown away
+    error : Option<CompileError>,
+}
+
+impl Compiler {
+
+    fn current(&mut self) -> &mut Function {
+        self.functions.last_mut().expect("Compiler has no current function")
+    }
+
+    fn emit(&mut self, op : Op, span : Span) -> usize {
+        let f = self.current();
+
+        f.height = match op {
+            Op::Constant(_) | Op::Unit | Op::GetLocal(_) | Op::GetUpvalue(_)
+                | Op::GetGlobal(_) | Op::Closure(_) => f.height + 1,
+            Op::Pop | Op::DefineGlobal(_) | Op::JumpIfFalse(_) | Op::Index => f.height - 1,
+            Op::PopN(n) | Op::EndScope(n) | Op::Call(n) | Op::CallNamed(n, _) => f.height - n,
+            Op::MakeList(n) | Op::MakeTuple(n) | Op::Interpolate(n) => f.height - n + 1,
+            Op::MakeRecord(names) => f.height - f.chunk.field_names[names].len() + 1,
+            // TestPattern and the control flow ops have their heights set by their callers
+            Op::Field(_) | Op::Jump(_) | Op::TestPattern(_, _) | Op::NoMatch | Op::Return => f.height,
+        };
+
+        f.chunk.code.push(op);
+        f.chunk.spans.push(span);
+        f.chunk.code.len() - 1
+    }
+
+    fn patch(&mut self, at : usize) {
+        let f = self.current();
+        let target = f.chunk.code.len();
+
+        f.chunk.code[at] = match f.chunk.code[at] {
+            Op::Jump(_) => Op::Jump(target),
+            Op::JumpIfFalse(_) => Op::JumpIfFalse(target),
+            op => panic!("Compiler::patch given non jump op {:?}", op),
+        };
+    }
+
+    fn constant(&mut self, value : Value) -> usize {
+        let constants = &mut self.current().chunk.constants;
+
+        match constants.iter().position(|c| *c == value) {
+            Some(index) => index,
+            None => {
+                constants.push(value);
+                constants.len() - 1
+            },
+        }
+    }
+
+    fn unsupported(&mut self, construct : &'static str, span : Span) {
+        if self.error.is_none() {
+            self.error = Some(CompileError::Unsupported { construct, span });
+        }
+        // stands in for the value so that the stack heights stay consistent
+        self.emit(Op::Unit, span);
+    }
+
+    fn declare(&mut self, name : &str, slot : usize) {
+        self.current().locals.push(Local { name: name.to_string(), slot });
+    }
+
+    fn end_scope(&mut self, marker : usize) {
+        let n = self.current().locals.len() - marker;
+        self.current().locals.truncate(marker);
+        if n != 0 {
+            self.emit(Op::EndScope(n), Span::default());
+        }
+    }
+
+    fn resolve_local(&self, level : usize, name : &str) -> Option<usize> {
+        self.functions[level].locals.iter().rev().find(|local| local.name == name).map(|local| local.slot)
+    }
+
+    fn resolve_upvalue(&mut self, level : usize, name : &str) -> Option<usize> {
+        if level == 0 {
+            return None;
+        }
+
+        let capture = match self.resolve_local(level - 1, name) {
+            Some(slot) => Capture::Local(slot),
+            None => Capture::Upvalue(self.resolve_upvalue(level - 1, name)?),
+        };
+
+        let captures = &mut self.functions[level].captures;
+        match captures.iter().position(|c| *c == capture) {
+            Some(index) => Some(index),
+            None => {
+                captures.push(capture);
+                Some(captures.len() - 1)
+            },
+        }
+    }
+
+    fn top_level(&mut self, statements : &[Ast]) {
+        // top level bindings are globals so that functions can refer to
+        // things defined after them
+        for (i, statement) in statements.iter().enumerate() {
+            let last = i == statements.len() - 1;
+
+            match statement {
+                Ast::Let { name, value, .. } => {
+                    self.expr(value);
+                    let name = self.constant(Value::String(name.clone()));
+                    self.emit(Op::DefineGlobal(name), Span::default());
+                    if last {
+                        self.emit(Op::Unit, Span::default());
+                    }
+                },
+                Ast::Function { name, params, body, .. } => {
+                    self.function(Some(name), params, body);
+                    let name = self.constant(Value::String(name.clone()));
+                    self.emit(Op::DefineGlobal(name), Span::default());
+                    if last {
+                        self.emit(Op::Unit, Span::default());
+                    }
+                },
+                other => {
+                    self.expr(other);
+                    if !last {
+                        self.emit(Op::Pop, Span::default());
+                    }
+                },
+            }
+        }
+
+        if statements.is_empty() {
+            self.emit(Op::Unit, Span::default());
+        }
+
+        self.emit(Op::Return, Span::default());
+    }
+
+    fn block(&mut self, statements : &[Ast]) {
+        let marker = self.current().locals.len();
+
+        for (i, statement) in statements.iter().enumerate() {
+            let last = i == statements.len() - 1;
+
+            match statement {
+                Ast::Let { name, value, .. } => {
+                    self.expr(value);
+                    let slot = self.current().height - 1;
+                    self.declare(name, slot);
+                    if last {
+                        self.emit(Op::Unit, Span::default());
+                    }
+                },
+                Ast::Function { name, params, body, .. } => {
+                    // declared first so that the function can capture itself
+                    let slot = self.current().height;
+                    self.declare(name, slot);
+                    self.function(Some(name), params, body);
+                    if last {
+                        self.emit(Op::Unit, Span::default());
+                    }
+                },
+                other => {
+                    self.expr(other);
+                    if !last {
+                        self.emit(Op::Pop, Span::default());
+                    }
+                },
+            }
+        }
+
+        if statements.is_empty() {
+            self.emit(Op::Unit, Span::default());
+        }
+
+        self.end_scope(marker);
+    }
+
+    fn function(&mut self, name : Option<&String>, params : &[String], body : &Ast) {
+        self.functions.push(Function::new(params));
+
+        self.expr(body);
+        self.emit(Op::Return, Span::default());
+
+        let f = self.functions.pop().expect("Compiler::function fails to pop function");
+        let prototype = Prototype { name: name.cloned(), params: params.to_vec(), captures: f.captures, chunk: f.chunk };
+
+        let prototypes = &mut self.current().chunk.prototypes;
+        prototypes.push(Rc::new(prototype));
+        let index = prototypes.len() - 1;
+
+        self.emit(Op::Closure(index), Span::default());
+    }
+
+    fn expr(&mut self, ast : &Ast) {
+        match ast {
+            Ast::Integer(_) | Ast::Bool(_) | Ast::String(_) | Ast::Char(_) => {
+                let value = Value::from_literal(ast).expect("Compiler::expr fails to convert literal");
+                let index = self.constant(value);
+                self.emit(Op::Constant(index), Span::default());
+            },
+            Ast::Interpolated(segments) => {
+                for segment in segments {
+                    match segment {
+                        Segment::Literal(text) => {
+                            let index = self.constant(Value::String(text.clone()));
+                            self.emit(Op::Constant(index), Span::default());
+                        },
+                        Segment::Expr(e) => self.expr(e),
+                    }
+                }
+                self.emit(Op::Interpolate(segments.len()), Span::default());
+            },
+            Ast::Variable(name, span) => {
+                let level = self.functions.len() - 1;
+                if let Some(slot) = self.resolve_local(level, name) {
+                    self.emit(Op::GetLocal(slot), *span);
+                }
+                else if let Some(index) = self.resolve_upvalue(level, name) {
+                    self.emit(Op::GetUpvalue(index), *span);
+                }
+                else {
+                    let index = self.constant(Value::String(name.clone()));
+                    self.emit(Op::GetGlobal(index), *span);
+                }
+            },
+            Ast::Let { .. } | Ast::Function { .. } => self.block(std::slice::from_ref(ast)),
+            Ast::Block(statements) => self.block(statements),
+            Ast::If { condition, then, otherwise, span } => {
+                self.expr(condition);
+                let to_else = self.emit(Op::JumpIfFalse(0), *span);
+
+                self.expr(then);
+                let to_end = self.emit(Op::Jump(0), Span::default());
+
+                self.patch(to_else);
+                self.current().height -= 1;
+
+                match otherwise {
+                    Some(otherwise) => self.expr(otherwise),
+                    None => { self.emit(Op::Unit, Span::default()); },
+                }
+
+                self.patch(to_end);
+            },
+            Ast::Match { subject, arms, span } => {
+                let marker = self.current().locals.len();
+
+                self.expr(subject);
+                let subject_slot = self.current().height - 1;
+                self.declare("", subject_slot);
+
+                let height = self.current().height;
+                let mut to_end = vec![];
+
+                for arm in arms {
+                    let mut binds = vec![];
+                    pattern_binds(&arm.pattern, &mut binds);
+
+                    self.current().chunk.patterns.push(arm.pattern.clone());
+                    let pattern = self.current().chunk.patterns.len() - 1;
+
+                    self.emit(Op::TestPattern(pattern, subject_slot), *span);
+                    self.current().height = height + binds.len() + 1;
+                    let to_next_arm = self.emit(Op::JumpIfFalse(0), *span);
+
+                    let arm_marker = self.current().locals.len();
+                    for (i, name) in binds.iter().enumerate() {
+                        self.declare(name, height + i);
+                    }
+
+                    let to_guard_failed = arm.guard.as_ref().map(|guard| {
+                        self.expr(guard);
+                        self.emit(Op::JumpIfFalse(0), *span)
+                    });
+
+                    self.expr(&arm.body);
+                    self.end_scope(arm_marker);
+                    to_end.push(self.emit(Op::Jump(0), Span::default()));
+
+                    if let Some(to_guard_failed) = to_guard_failed {
+                        self.patch(to_guard_failed);
+                        self.current().height = height + binds.len();
+                        if !binds.is_empty() {
+                            self.emit(Op::PopN(binds.len()), Span::default());
+                        }
+                    }
+
+                    self.patch(to_next_arm);
+                    self.current().height = height;
+                }
+
+                self.emit(Op::NoMatch, *span);
+
+                for jump in to_end {
+                    self.patch(jump);
+                }
+                self.current().height = height + 1;
+
+                self.end_scope(marker);
+            },
+            Ast::Lambda { params, body } => self.function(None, params, body),
+            Ast::Call { function, args, span } => {
+                self.expr(function);
+
+                let mut names = vec![];
+                for arg in args {
+                    match arg {
+                        Argument::Positional(e) => { self.expr(e); names.push(None); },
+                        Argument::Named(name, e) => { self.expr(e); names.push(Some(name.clone())); },
+                    }
+                }
+
+                if names.iter().all(|name| name.is_none()) {
+                    self.emit(Op::Call(args.len()), *span);
+                }
+                else {
+                    self.current().chunk.argument_names.push(names);
+                    let index = self.current().chunk.argument_names.len() - 1;
+                    self.emit(Op::CallNamed(args.len(), index), *span);
+                }
+            },
+            Ast::List(items) => {
+                for item in items {
+                    self.expr(item);
+                }
+                self.emit(Op::MakeList(items.len()), Span::default());
+            },
+            Ast::Tuple(items) => {
+                for item in items {
+                    self.expr(item);
+                }
+                self.emit(Op::MakeTuple(items.len()), Span::default());
+            },
+            Ast::Record(fields) => {
+                for (_, value) in fields {
+                    self.expr(value);
+                }
+                self.current().chunk.field_names.push(fields.iter().map(|(name, _)| name.clone()).collect());
+                let index = self.current().chunk.field_names.len() - 1;
+                self.emit(Op::MakeRecord(index), Span::default());
+            },
+            Ast::Index { target, index, span } => {
+                self.expr(target);
+                self.expr(index);
+                self.emit(Op::Index, *span);
+            },
+            Ast::Field { target, name, span } => {
+                self.expr(target);
+                let index = self.constant(Value::String(name.clone()));
+                self.emit(Op::Field(index), *span);
+            },
+            Ast::Command { span, .. } => self.unsupported("commands", *span),
+            Ast::And { span, .. } | Ast::Or { span, .. } => self.unsupported("'&&' and '||'", *span),
+            Ast::Try { .. } => self.unsupported("try", Span::default()),
+            Ast::Substitution { span, .. } => self.unsupported("command substitution", *span),
+            Ast::EnvVar(_, span) => self.unsupported("environment variables", *span),
+            Ast::Export { .. } | Ast::WithEnv { .. } => self.unsupported("environment variables", Span::default()),
+        }
+    }
+}
+
+// The order here has to agree with the order that the machine pushes bound values
+pub fn pattern_binds(pattern : &Pattern, binds : &mut Vec<String>) {
+    match pattern {
+        Pattern::Literal(_) | Pattern::Wildcard => { },
+        Pattern::Bind(name) => binds.push(name.clone()),
+        Pattern::List { items, rest } => {
+            for item in items {
+                pattern_binds(item, binds);
+            }
+            if let Some(rest) = rest {
+                pattern_binds(rest, binds);
+            }
+        },
+        Pattern::Tuple(items) => {
+            for item in items {
+                pattern_binds(item, binds);
+            }
+        },
+        Pattern::Record(fields) => {
+            for (_, p) in fields {
+                pattern_binds(p, binds);
+            }
+        },
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parsing::grammar::parse;
+
+    fn compile_str(s : &str) -> Prototype {
+        compile(&parse(s).expect("test program should parse")).expect("test program should compile")
+    }
+
+    #[test]
+    fn should_reuse_equal_constants() {
+        let p = compile_str(r#"["a", "a", 1, 1]"#);
+
+        assert_eq!( p.chunk.constants.len(), 2 );
+    }
+
+    #[test]
+    fn should_assign_local_slots_above_temporaries() {
+        let p = compile_str("[1, { let x = 2; x }]");
+
+        assert!( p.chunk.code.contains(&Op::GetLocal(1)) );
+    }
+
+    #[test]
+    fn should_capture_enclosing_locals_as_upvalues() {
+        let p = compile_str("fn f(x) { fn(y) { fn() { x } } }");
+
+        let f = &p.chunk.prototypes[0];
+        let middle = &f.chunk.prototypes[0];
+        let inner = &middle.chunk.prototypes[0];
+
+        assert_eq!( middle.captures, vec![Capture::Local(0)] );
+        assert_eq!( inner.captures, vec![Capture::Upvalue(0)] );
+    }
+
+    #[test]
+    fn should_report_unsupported_commands() {
+        let v = compile(&parse("fn f() { $ ls }").unwrap());
+
+        assert!(matches!( v, Err(CompileError::Unsupported { construct: "commands", .. }) ));
+    }
+
+    #[test]
+    fn should_resolve_unknown_names_as_globals() {
+        let p = compile_str("fn f() { g() }");
+
+        assert!( p.chunk.prototypes[0].chunk.code.contains(&Op::GetGlobal(0)) );
+    }
+}
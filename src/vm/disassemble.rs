@@ -0,0 +1,71 @@
+
+use std::fmt::Write;
+
+use super::bytecode::{Op, Prototype};
+
+pub fn disassemble(prototype : &Prototype) -> String {
+    let mut out = String::new();
+    write_prototype(prototype, &mut out);
+    out
+}
+
+fn write_prototype(prototype : &Prototype, out : &mut String) {
+    let chunk = &prototype.chunk;
+
+    match &prototype.name {
+        Some(name) => writeln!(out, "== fn {}({}) ==", name, prototype.params.join(", ")),
+        None if prototype.params.is_empty() && prototype.captures.is_empty() => writeln!(out, "== script =="),
+        None => writeln!(out, "== fn({}) ==", prototype.params.join(", ")),
+    }.expect("write to String fails");
+
+    for (ip, op) in chunk.code.iter().enumerate() {
+        let comment = match op {
+            Op::Constant(index) | Op::GetGlobal(index) | Op::DefineGlobal(index) | Op::Field(index) =>
+                Some(format!("{:?}", chunk.constants[*index])),
+            Op::CallNamed(_, index) => Some(format!("{:?}", chunk.argument_names[*index])),
+            Op::MakeRecord(index) => Some(format!("{:?}", chunk.field_names[*index])),
+            Op::TestPattern(index, _) => Some(format!("{:?}", chunk.patterns[*index])),
+            Op::Closure(index) => Some(match &chunk.prototypes[*index].name {
+                Some(name) => format!("fn {}", name),
+                None => "fn".to_string(),
+            }),
+            _ => None,
+        };
+
+        match comment {
+            Some(comment) => writeln!(out, "{:04} {:?} ; {}", ip, op, comment),
+            None => writeln!(out, "{:04} {:?}", ip, op),
+        }.expect("write to String fails");
+    }
+
+    for nested in &chunk.prototypes {
+        writeln!(out).expect("write to String fails");
+        write_prototype(nested, out);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parsing::grammar::parse;
+    use super::super::compile;
+
+    #[test]
+    fn should_list_ops_with_constants() {
+        let p = compile(&parse("let x = 5;").unwrap()).unwrap();
+
+        let output = disassemble(&p);
+
+        assert_eq!( output, "== script ==\n0000 Constant(0) ; Integer(5)\n0001 DefineGlobal(1) ; String(\"x\")\n0002 Unit\n0003 Return\n" );
+    }
+
+    #[test]
+    fn should_include_nested_functions() {
+        let p = compile(&parse("fn f(a) { a }").unwrap()).unwrap();
+
+        let output = disassemble(&p);
+
+        assert!( output.contains("0000 Closure(0) ; fn f") );
+        assert!( output.contains("== fn f(a) ==\n0000 GetLocal(0)\n0001 Return\n") );
+    }
+}
@@ -0,0 +1,456 @@
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+use crate::eval::{self, RuntimeError, Value, BUILTINS};
+use crate::parsing::ast::{Pattern, Span};
+use super::bytecode::{Capture, Op, Prototype};
+
+const MAX_FRAMES : usize = 10_000;
+
+pub struct Closure {
+    pub prototype : Rc<Prototype>,
+    pub upvalues : Vec<Rc<RefCell<Upvalue>>>,
+}
+
+impl fmt::Debug for Closure {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        // An upvalue can hold the closure itself
+        write!(f, "Closure({:?})", self.prototype.name)
+    }
+}
+
+// Open upvalues point at a stack slot while the local is still alive, and are
+// closed over a copy of its value when it goes out of scope
+#[derive(Debug)]
+pub enum Upvalue {
+    Open(usize),
+    Closed(Value),
+}
+
+struct Frame {
+    closure : Rc<Closure>,
+    ip : usize,
+    base : usize,
+}
+
+pub struct Machine {
+    globals : HashMap<String, Value>,
+    stack : Vec<Value>,
+    frames : Vec<Frame>,
+    open_upvalues : Vec<Rc<RefCell<Upvalue>>>,
+}
+
+impl Machine {
+    pub fn new() -> Machine {
+        let globals = BUILTINS.iter().map(|b| (b.name.to_string(), Value::Builtin(*b))).collect();
+
+        Machine { globals, stack: vec![], frames: vec![], open_upvalues: vec![] }
+    }
+
+    // Globals are kept between runs
+    pub fn run(&mut self, script : Rc<Prototype>) -> Result<Value, RuntimeError> {
+        let closure = Rc::new(Closure { prototype: script, upvalues: vec![] });
+
+        // slot for the callee, the same as any other call
+        self.stack.push(Value::unit());
+        self.frames.push(Frame { closure, ip: 0, base: 1 });
+
+        let result = self.execute();
+
+        if result.is_err() {
+            self.stack.clear();
+            self.frames.clear();
+            self.open_upvalues.clear();
+        }
+
+        result
+    }
+
+    fn execute(&mut self) -> Result<Value, RuntimeError> {
+        let frame = self.frames.last().expect("Machine::execute has no frame");
+        let mut closure = frame.closure.clone();
+        let mut ip = frame.ip;
+        let mut base = frame.base;
+
+        loop {
+            let chunk = &closure.prototype.chunk;
+            let op = chunk.code[ip];
+            let span = chunk.spans[ip];
+            ip += 1;
+
+            match op {
+                Op::Constant(index) => self.stack.push(chunk.constants[index].clone()),
+                Op::Unit => self.stack.push(Value::unit()),
+                Op::Pop => { self.stack.pop(); },
+                Op::PopN(n) => {
+                    let to = self.stack.len() - n;
+                    self.close_upvalues(to);
+                    self.stack.truncate(to);
+                },
+                Op::EndScope(n) => {
+                    let result = self.pop();
+                    let to = self.stack.len() - n;
+                    self.close_upvalues(to);
+                    self.stack.truncate(to);
+                    self.stack.push(result);
+                },
+                Op::GetLocal(slot) => self.stack.push(self.stack[base + slot].clone()),
+                Op::GetUpvalue(index) => {
+                    let value = match &*closure.upvalues[index].borrow() {
+                        Upvalue::Open(slot) => self.stack[*slot].clone(),
+                        Upvalue::Closed(value) => value.clone(),
+                    };
+                    self.stack.push(value);
+                },
+                Op::GetGlobal(index) => {
+                    let name = constant_name(&chunk.constants[index]);
+                    match self.globals.get(name) {
+                        Some(value) => self.stack.push(value.clone()),
+                        None => return Err(RuntimeError::UnboundVariable { name: name.to_string(), span }),
+                    }
+                },
+                Op::DefineGlobal(index) => {
+                    let name = constant_name(&chunk.constants[index]).to_string();
+                    let value = self.pop();
+                    self.globals.insert(name, value);
+                },
+                Op::Jump(target) => ip = target,
+                Op::JumpIfFalse(target) => {
+                    match self.pop() {
+                        Value::Bool(true) | Value::Status(0) => { },
+                        Value::Bool(false) | Value::Status(_) => ip = target,
+                        other => return Err(RuntimeError::TypeMismatch { expected: "bool or status", found: other.type_name(), span }),
+                    }
+                },
+                Op::Call(argc) | Op::CallNamed(argc, _) => {
+                    let names = match op {
+                        Op::CallNamed(_, index) => Some(chunk.argument_names[index].clone()),
+                        _ => None,
+                    };
+
+                    if let Some(frame) = self.call(argc, names, span)? {
+                        self.frames.last_mut().expect("Machine::execute lost calling frame").ip = ip;
+
+                        closure = frame.closure.clone();
+                        ip = 0;
+                        base = frame.base;
+
+                        self.frames.push(frame);
+                    }
+                },
+                Op::Closure(index) => {
+                    let prototype = chunk.prototypes[index].clone();
+
+                    let upvalues = prototype.captures.iter().map(|capture| match capture {
+                        Capture::Local(slot) => self.capture_upvalue(base + slot),
+                        Capture::Upvalue(index) => closure.upvalues[*index].clone(),
+                    }).collect();
+
+                    self.stack.push(Value::Compiled(Rc::new(Closure { prototype, upvalues })));
+                },
+                Op::MakeList(n) => {
+                    let items = self.stack.split_off(self.stack.len() - n);
+                    self.stack.push(Value::List(items));
+                },
+                Op::MakeTuple(n) => {
+                    let items = self.stack.split_off(self.stack.len() - n);
+                    self.stack.push(Value::Tuple(items));
+                },
+                Op::MakeRecord(index) => {
+                    let names = &chunk.field_names[index];
+                    let values = self.stack.split_off(self.stack.len() - names.len());
+                    self.stack.push(Value::Record(names.iter().cloned().zip(values).collect()));
+                },
+                Op::Index => {
+                    let index = self.pop();
+                    let target = self.pop();
+                    self.stack.push(eval::index_value(target, index, span)?);
+                },
+                Op::Field(index) => {
+                    let name = constant_name(&chunk.constants[index]);
+                    let value = match self.pop() {
+                        Value::Record(fields) => eval::field_value(fields, name, span)?,
+                        other => return Err(RuntimeError::TypeMismatch { expected: "record", found: other.type_name(), span }),
+                    };
+                    self.stack.push(value);
+                },
+                Op::Interpolate(n) => {
+                    let parts = self.stack.split_off(self.stack.len() - n);
+                    self.stack.push(Value::String(parts.iter().map(|part| part.to_string()).collect()));
+                },
+                Op::TestPattern(index, slot) => {
+                    let mut binds = vec![];
+                    if test_pattern(&chunk.patterns[index], &self.stack[base + slot], &mut binds) {
+                        self.stack.extend(binds);
+                        self.stack.push(Value::Bool(true));
+                    }
+                    else {
+                        self.stack.push(Value::Bool(false));
+                    }
+                },
+                Op::NoMatch => return Err(RuntimeError::NoMatchingArm { span }),
+                Op::Return => {
+                    let result = self.pop();
+
+                    self.close_upvalues(base);
+                    self.stack.truncate(base - 1);
+                    self.frames.pop();
+
+                    match self.frames.last() {
+                        Some(frame) => {
+                            closure = frame.closure.clone();
+                            ip = frame.ip;
+                            base = frame.base;
+                            self.stack.push(result);
+                        },
+                        None => return Ok(result),
+                    }
+                },
+            }
+        }
+    }
+
+    fn pop(&mut self) -> Value {
+        self.stack.pop().expect("Machine stack underflow")
+    }
+
+    fn call(&mut self, argc : usize, names : Option<Vec<Option<String>>>, span : Span) -> Result<Option<Frame>, RuntimeError> {
+        let callee = self.stack.len() - argc - 1;
+
+        match self.stack[callee].clone() {
+            Value::Builtin(builtin) => {
+                if let Some(name) = names.into_iter().flatten().flatten().next() {
+                    return Err(RuntimeError::UnknownArgument { name, span });
+                }
+
+                let args = self.stack.split_off(callee + 1);
+                self.stack.pop();
+                // the machine runs no commands, so has no status or $HOME of its own
+                self.stack.push((builtin.f)(&args, &eval::Context::default(), span)?);
+
+                Ok(None)
+            },
+            Value::Compiled(closure) => {
+                let params = &closure.prototype.params;
+
+                // the evaluator's argument binding is only needed when the
+                // arguments aren't already in parameter order
+                if names.is_some() || argc != params.len() {
+                    let args = self.stack.split_off(callee + 1);
+                    let names = names.unwrap_or_else(|| vec![None; argc]);
+                    let bound = eval::bind_arguments(params, names.into_iter().zip(args).collect(), span)?;
+                    self.stack.extend(bound.into_iter().map(|(_, value)| value));
+                }
+
+                if self.frames.len() >= MAX_FRAMES {
+                    return Err(RuntimeError::StackOverflow { span });
+                }
+
+                Ok(Some(Frame { closure, ip: 0, base: callee + 1 }))
+            },
+            other => Err(RuntimeError::NotCallable { found: other.type_name(), span }),
+        }
+    }
+
+    fn capture_upvalue(&mut self, slot : usize) -> Rc<RefCell<Upvalue>> {
+        let existing = self.open_upvalues.iter().find(|upvalue| matches!(*upvalue.borrow(), Upvalue::Open(s) if s == slot));
+
+        match existing {
+            Some(upvalue) => upvalue.clone(),
+            None => {
+                let upvalue = Rc::new(RefCell::new(Upvalue::Open(slot)));
+                self.open_upvalues.push(upvalue.clone());
+                upvalue
+            },
+        }
+    }
+
+    fn close_upvalues(&mut self, from : usize) {
+        let stack = &self.stack;
+
+        self.open_upvalues.retain(|upvalue| {
+            let slot = match *upvalue.borrow() {
+                Upvalue::Open(slot) => slot,
+                Upvalue::Closed(_) => return false,
+            };
+
+            if slot >= from {
+                *upvalue.borrow_mut() = Upvalue::Closed(stack[slot].clone());
+                false
+            }
+            else {
+                true
+            }
+        });
+    }
+}
+
+impl Default for Machine {
+    fn default() -> Machine {
+        Machine::new()
+    }
+}
+
+fn constant_name(constant : &Value) -> &str {
+    match constant {
+        Value::String(name) => name,
+        other => panic!("Machine expected name constant but found {:?}", other),
+    }
+}
+
+// Pushes bound values in the same order that the compiler declared them
+fn test_pattern(pattern : &Pattern, value : &Value, binds : &mut Vec<Value>) -> bool {
+    match (pattern, value) {
+        (Pattern::Literal(ast), value) => Value::from_literal(ast).as_ref() == Some(value),
+        (Pattern::Wildcard, _) => true,
+        (Pattern::Bind(_), value) => {
+            binds.push(value.clone());
+            true
+        },
+        (Pattern::List { items, rest: None }, Value::List(values)) =>
+            items.len() == values.len()
+            && items.iter().zip(values.iter()).all(|(p, v)| test_pattern(p, v, binds)),
+        (Pattern::List { items, rest: Some(rest) }, Value::List(values)) =>
+            items.len() <= values.len()
+            && items.iter().zip(values.iter()).all(|(p, v)| test_pattern(p, v, binds))
+            && test_pattern(rest, &Value::List(values[items.len()..].to_vec()), binds),
+        (Pattern::Tuple(items), Value::Tuple(values)) =>
+            items.len() == values.len()
+            && items.iter().zip(values.iter()).all(|(p, v)| test_pattern(p, v, binds)),
+        (Pattern::Record(fields), Value::Record(values)) =>
+            fields.iter().all(|(name, p)|
+                values.iter().find(|(field, _)| field == name).is_some_and(|(_, v)| test_pattern(p, v, binds))),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::eval::Evaluator;
+    use crate::parsing::grammar::parse;
+    use super::super::compile;
+
+    fn run(s : &str) -> Result<Value, RuntimeError> {
+        let program = parse(s).expect("test program should parse");
+        Machine::new().run(Rc::new(compile(&program).expect("test program should compile")))
+    }
+
+    fn evaluate(s : &str) -> Result<Value, RuntimeError> {
+        let program = parse(s).expect("test program should parse");
+        Evaluator::new().eval(&program)
+    }
+
+    #[test]
+    fn should_agree_with_evaluator() {
+        let programs = [
+            "5",
+            "let x = 1; let y = { let x = 2; add(x, 1) }; [x, y]",
+            "if eq(1, 2) { 'a' } else if true { 'b' } else { 'c' }",
+            "fn fact(n) { if eq(n, 0) { 1 } else { mul(n, fact(sub(n, 1))) } } fact(10)",
+            "fn f(a, b) { sub(a, b) } f(b = 1, a = 10)",
+            r#"let r = { items: [(1, "a")] }; r.items[0].1"#,
+            r#"let name = "world"; "hello ${name} ${[1, "2"]}""#,
+            r#"
+            fn describe(v) {
+                match v {
+                    [] => "empty",
+                    [x, ..rest] if eq(len(rest), 0) => "one ${x}",
+                    [_, ..] => "many",
+                    { name, age: 5 } => "five year old ${name}",
+                    (a, 'b') => "pair ${a}",
+                    _ => "other",
+                }
+            }
+            [describe([]), describe([1]), describe([1, 2]), describe({ age: 5, name: "al" }), describe((1, 'b')), describe(7)]
+            "#,
+            "[1, 2][2]",
+            "let x = 1;\n  y",
+            "if 1 { 2 }",
+            "fn f(a, b) { a } f(1)",
+            "match 1 { 2 => 3 }",
+        ];
+
+        for program in programs.iter() {
+            assert_eq!( run(program), evaluate(program), "program: {}", program );
+        }
+    }
+
+    #[test]
+    fn should_agree_with_evaluator_on_shadowing() {
+        let programs = [
+            "{ let x = 1; let g = fn() { x }; let x = 2; [g(), x] }",
+            "fn f() { let x = 1; let g = fn() { x }; let x = 2; add(g(), x) } f()",
+            "{ let x = 1; let x = add(x, 1); x }",
+            "{ let f = fn(n) { f(n) }; f(1) }",
+            "{ fn f() { y } let y = 1; f() }",
+            "let x = 1; let g = fn() { x }; let x = 2; g()",
+            "fn fact(n) { let m = n; if eq(m, 0) { 1 } else { let m = mul(m, fact(sub(n, 1))); m } } fact(5)",
+        ];
+
+        for program in programs.iter() {
+            assert_eq!( run(program), evaluate(program), "program: {}", program );
+        }
+    }
+
+    #[test]
+    fn closures_should_outlive_captured_locals() {
+        let v = run("
+            fn adder(x) { fn(y) { add(x, y) } }
+            let add5 = adder(5);
+            let add7 = adder(7);
+            [add5(1), add7(1)]");
+
+        assert_eq!( v.map(|v| v.to_string()), Ok("[6, 8]".to_string()) );
+    }
+
+    #[test]
+    fn closures_should_share_captured_local() {
+        let v = run("
+            let fs = {
+                let x = 10;
+                (fn() { x }, fn() { add(x, 1) })
+            };
+            [fs.0(), fs.1()]");
+
+        assert_eq!( v.map(|v| v.to_string()), Ok("[10, 11]".to_string()) );
+    }
+
+    #[test]
+    fn closures_should_capture_through_several_levels() {
+        let v = run("fn f(x) { fn(y) { fn(z) { [x, y, z] } } } f(1)(2)(3)");
+
+        assert_eq!( v.map(|v| v.to_string()), Ok("[1, 2, 3]".to_string()) );
+    }
+
+    #[test]
+    fn local_function_should_call_itself() {
+        let v = run("
+            {
+                fn count(n) { if eq(n, 0) { 0 } else { add(1, count(sub(n, 1))) } }
+                count(50)
+            }");
+
+        assert_eq!( v, Ok(Value::Integer(50)) );
+    }
+
+    #[test]
+    fn should_report_stack_overflow() {
+        let v = run("fn f() { add(1, f()) } f()");
+
+        assert!(matches!( v, Err(RuntimeError::StackOverflow { .. }) ));
+    }
+
+    #[test]
+    fn run_should_keep_globals_between_runs() {
+        let mut machine = Machine::new();
+
+        machine.run(Rc::new(compile(&parse("let x = 1;").unwrap()).unwrap())).unwrap();
+        let v = machine.run(Rc::new(compile(&parse("x").unwrap()).unwrap()));
+
+        assert_eq!( v, Ok(Value::Integer(1)) );
+    }
+}
@@ -0,0 +1,12 @@
+
+mod bytecode;
+mod compiler;
+mod disassemble;
+mod machine;
+
+#[cfg(test)]
+mod bench;
+
+pub use compiler::compile;
+pub use disassemble::disassemble;
+pub use machine::{Closure, Machine};